@@ -0,0 +1,258 @@
+//! Files sent along with a prompt
+//!
+//! `lok ask -f` and a step's `attach` name files or globs (`src/**/*.rs`).
+//! Text files are read as they are; PNG, JPEG, GIF and WebP images are
+//! base64-encoded for multimodal models. Backends send each attachment their
+//! native way where they have one, and otherwise write it into the prompt
+//! between file delimiters (see [`Attachment::inline`]).
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A file attached to a user turn
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Attachment {
+    Text {
+        /// As given, relative to the query's working directory
+        path: String,
+        content: String,
+    },
+    Image {
+        path: String,
+        /// e.g. "image/png"
+        media_type: String,
+        /// Base64 of the file
+        data: String,
+    },
+}
+
+impl Attachment {
+    /// Read `path` (relative to `cwd`), as an image if its extension says so
+    pub fn load(path: &str, cwd: &Path) -> Result<Self> {
+        let bytes = std::fs::read(cwd.join(path))
+            .with_context(|| format!("Failed to read attachment {}", path))?;
+        if let Some(media_type) = image_media_type(path) {
+            return Ok(Attachment::Image {
+                path: path.to_string(),
+                media_type: media_type.to_string(),
+                data: base64::engine::general_purpose::STANDARD.encode(bytes),
+            });
+        }
+        let content = String::from_utf8(bytes).map_err(|_| {
+            anyhow::anyhow!(
+                "Attachment {} is neither text nor a PNG, JPEG, GIF or WebP image",
+                path
+            )
+        })?;
+        Ok(Attachment::Text {
+            path: path.to_string(),
+            content,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Attachment::Text { path, .. } | Attachment::Image { path, .. } => path,
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        matches!(self, Attachment::Image { .. })
+    }
+
+    /// The attachment written into a prompt. Images can't be, so they are
+    /// named by path, which agents working in the same directory can open.
+    pub fn inline(&self) -> String {
+        match self {
+            Attachment::Text { path, content } => format!(
+                "--- BEGIN FILE {} ---\n{}\n--- END FILE {} ---",
+                path,
+                content.trim_end_matches('\n'),
+                path
+            ),
+            Attachment::Image { path, .. } => format!("--- IMAGE {} ---", path),
+        }
+    }
+}
+
+/// `text` followed by `attachments` written out, for backends without a
+/// native way to send them
+pub fn inline(text: &str, attachments: &[&Attachment]) -> String {
+    let mut out = text.to_string();
+    for attachment in attachments {
+        if !out.is_empty() {
+            out.push_str("\n\n");
+        }
+        out.push_str(&attachment.inline());
+    }
+    out
+}
+
+fn image_media_type(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Load every file named by `patterns`, relative to `cwd`. Globs take `*`,
+/// `?` and `**` (any number of directories) and skip hidden files; each
+/// pattern must match something. Files matched twice are attached once.
+pub fn load_all(patterns: &[String], cwd: &Path) -> Result<Vec<Attachment>> {
+    let mut paths: Vec<String> = Vec::new();
+    for pattern in patterns {
+        let matched = if is_glob(pattern) {
+            glob(pattern, cwd)?
+        } else {
+            vec![pattern.clone()]
+        };
+        if matched.is_empty() {
+            anyhow::bail!("Attachment pattern '{}' matched no files", pattern);
+        }
+        for path in matched {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+        .iter()
+        .map(|path| Attachment::load(path, cwd))
+        .collect()
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Files under `cwd` matching `pattern`, as `/`-separated relative paths in
+/// sorted order
+fn glob(pattern: &str, cwd: &Path) -> Result<Vec<String>> {
+    let regex = glob_regex(pattern)?;
+    // Walk from the deepest directory without wildcards
+    let base = pattern
+        .split('/')
+        .take_while(|part| !is_glob(part))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let mut matches = Vec::new();
+    let mut dirs = vec![base];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(cwd.join(&dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if regex.is_match(&path) {
+                matches.push(path);
+            }
+        }
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+fn glob_regex(pattern: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            regex.push_str("(?:[^/]+/)*");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+            continue;
+        }
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    regex.push('$');
+    Regex::new(&regex).with_context(|| format!("Invalid attachment pattern '{}'", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, path: &str, content: &[u8]) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_load_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "src/main.rs", b"fn main() {}\n");
+        write(dir.path(), "src/backend/mod.rs", b"mod a;\n");
+        write(dir.path(), "src/notes.md", b"notes");
+        write(dir.path(), "src/.hidden.rs", b"");
+        write(dir.path(), "README.md", b"# readme");
+
+        let attachments = load_all(
+            &["src/**/*.rs".to_string(), "src/main.rs".to_string()],
+            dir.path(),
+        )
+        .unwrap();
+        let paths: Vec<&str> = attachments.iter().map(|a| a.path()).collect();
+        assert_eq!(paths, ["src/backend/mod.rs", "src/main.rs"]);
+
+        let attachments = load_all(&["*.md".to_string()], dir.path()).unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(
+            attachments[0].inline(),
+            "--- BEGIN FILE README.md ---\n# readme\n--- END FILE README.md ---"
+        );
+
+        assert!(load_all(&["*.toml".to_string()], dir.path()).is_err());
+        assert!(load_all(&["missing.rs".to_string()], dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_images_and_binaries() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "shot.PNG", &[0x89, b'P', b'N', b'G']);
+        write(dir.path(), "blob.bin", &[0xff, 0xfe, 0x00]);
+
+        let image = Attachment::load("shot.PNG", dir.path()).unwrap();
+        assert_eq!(
+            image,
+            Attachment::Image {
+                path: "shot.PNG".to_string(),
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }
+        );
+        assert_eq!(image.inline(), "--- IMAGE shot.PNG ---");
+        assert!(Attachment::load("blob.bin", dir.path()).is_err());
+    }
+}
@@ -1,4 +1,8 @@
+use super::retry::{backoff_hint, HttpError};
+use super::{Completion, QueryRequest, Role, ToolCall};
+use chrono::Utc;
 use crate::config::BackendConfig;
+use crate::usage::Usage;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use aws_sdk_bedrockruntime::primitives::Blob;
@@ -7,6 +11,7 @@ use serde::{Deserialize, Serialize};
 use std::path::Path;
 
 pub struct BedrockBackend {
+    name: String,
     client: Client,
     pub model_id: String,
 }
@@ -20,6 +25,10 @@ struct BedrockRequest {
     messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -51,12 +60,25 @@ pub enum ContentBlock {
         tool_use_id: String,
         content: String,
     },
+    #[serde(rename = "image")]
+    Image { source: ImageSource },
+}
+
+/// A base64-encoded image
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageSource {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub media_type: String,
+    pub data: String,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct BedrockResponse {
     pub content: Vec<ResponseBlock>,
     pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -73,7 +95,7 @@ pub enum ResponseBlock {
 }
 
 impl BedrockBackend {
-    pub async fn new(config: &BackendConfig) -> Result<Self> {
+    pub async fn new(name: &str, config: &BackendConfig) -> Result<Self> {
         let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
         let client = Client::new(&aws_config);
 
@@ -82,21 +104,28 @@ impl BedrockBackend {
             .clone()
             .unwrap_or_else(|| "us.anthropic.claude-sonnet-4-20250514-v1:0".to_string());
 
-        Ok(Self { client, model_id })
+        Ok(Self {
+            name: name.to_string(),
+            client,
+            model_id,
+        })
     }
 
+    /// Send a message list, taking the system prompt and sampling options from `options`
     pub async fn invoke_with_messages(
         &self,
-        system: Option<&str>,
+        options: &QueryRequest,
         messages: Vec<Message>,
         tools: Option<Vec<serde_json::Value>>,
     ) -> Result<BedrockResponse> {
         let request = BedrockRequest {
             anthropic_version: "bedrock-2023-05-31".to_string(),
-            max_tokens: 4096,
-            system: system.map(|s| s.to_string()),
+            max_tokens: options.max_tokens.unwrap_or(4096),
+            system: options.system_with_format_hint(),
             messages,
             tools,
+            temperature: options.temperature,
+            stop_sequences: options.stop.clone(),
         };
 
         let body = serde_json::to_vec(&request)?;
@@ -109,7 +138,7 @@ impl BedrockBackend {
             .body(Blob::new(body))
             .send()
             .await
-            .context("Failed to invoke Bedrock model")?;
+            .map_err(invoke_error)?;
 
         let response_body = response.body.as_ref();
         let response: BedrockResponse =
@@ -119,31 +148,131 @@ impl BedrockBackend {
     }
 }
 
+/// Keep the HTTP status and `Retry-After` of a throttled or failed invocation
+fn invoke_error(
+    error: aws_sdk_bedrockruntime::error::SdkError<
+        aws_sdk_bedrockruntime::operation::invoke_model::InvokeModelError,
+        aws_sdk_bedrockruntime::config::http::HttpResponse,
+    >,
+) -> anyhow::Error {
+    let body = aws_sdk_bedrockruntime::error::DisplayErrorContext(&error).to_string();
+    match error.raw_response() {
+        Some(raw) => {
+            let status = raw.status().as_u16();
+            let retry_after = backoff_hint(
+                status,
+                |name| raw.headers().get(name).map(str::to_string),
+                Utc::now(),
+            );
+            HttpError {
+                provider: "Bedrock".to_string(),
+                status,
+                body,
+                retry_after,
+            }
+            .into()
+        }
+        None => anyhow::Error::new(error).context("Failed to invoke Bedrock model"),
+    }
+}
+
+/// Anthropic messages for Bedrock: tool calls become `tool_use` blocks, and
+/// consecutive tool results are sent as one user turn of `tool_result` blocks
+fn bedrock_messages(request: &QueryRequest) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    for m in request.messages() {
+        match m.role {
+            Role::Tool => {
+                let block = ContentBlock::ToolResult {
+                    tool_use_id: m.tool_call_id.unwrap_or_default(),
+                    content: m.content,
+                };
+                match messages.last_mut() {
+                    Some(Message {
+                        role,
+                        content: MessageContent::Blocks(blocks),
+                    }) if role == "user" => blocks.push(block),
+                    _ => messages.push(Message {
+                        role: "user".to_string(),
+                        content: MessageContent::Blocks(vec![block]),
+                    }),
+                }
+            }
+            Role::Assistant if !m.tool_calls.is_empty() => {
+                let mut blocks = Vec::new();
+                if !m.content.is_empty() {
+                    blocks.push(ContentBlock::Text { text: m.content });
+                }
+                blocks.extend(m.tool_calls.into_iter().map(|call| ContentBlock::ToolUse {
+                    id: call.id,
+                    name: call.name,
+                    input: call.input,
+                }));
+                messages.push(Message {
+                    role: "assistant".to_string(),
+                    content: MessageContent::Blocks(blocks),
+                });
+            }
+            // Text files are written into the turn; images go before it
+            Role::User if m.images().next().is_some() => {
+                let mut blocks: Vec<ContentBlock> = m
+                    .images()
+                    .map(|(media_type, data)| ContentBlock::Image {
+                        source: ImageSource {
+                            kind: "base64".to_string(),
+                            media_type: media_type.to_string(),
+                            data: data.to_string(),
+                        },
+                    })
+                    .collect();
+                blocks.push(ContentBlock::Text {
+                    text: m.content_with_attachments(false),
+                });
+                messages.push(Message {
+                    role: "user".to_string(),
+                    content: MessageContent::Blocks(blocks),
+                });
+            }
+            _ => messages.push(Message {
+                role: m.role.as_str().to_string(),
+                content: MessageContent::Text(m.content_with_attachments(false)),
+            }),
+        }
+    }
+    messages
+}
+
 #[async_trait]
 impl super::Backend for BedrockBackend {
     fn name(&self) -> &str {
-        "bedrock"
+        &self.name
     }
 
-    async fn query(&self, prompt: &str, _cwd: &Path) -> Result<String> {
-        let messages = vec![Message {
-            role: "user".to_string(),
-            content: MessageContent::Text(prompt.to_string()),
-        }];
-
-        let response = self.invoke_with_messages(None, messages, None).await?;
-
-        let text = response
-            .content
-            .into_iter()
-            .filter_map(|block| match block {
-                ResponseBlock::Text { text } => Some(text),
-                _ => None,
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        Ok(text)
+    async fn complete(&self, request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+        let tools = (!request.tools.is_empty())
+            .then(|| request.tools.iter().map(|t| serde_json::json!(t)).collect());
+        let response = self
+            .invoke_with_messages(request, bedrock_messages(request), tools)
+            .await?;
+
+        let usage = response.usage;
+        let mut texts = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in response.content {
+            match block {
+                ResponseBlock::Text { text } => texts.push(text),
+                ResponseBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(ToolCall { id, name, input })
+                }
+            }
+        }
+
+        Ok(Completion {
+            text: texts.join("\n"),
+            usage,
+            tool_calls,
+            ..Default::default()
+        })
     }
 
     fn is_available(&self) -> bool {
@@ -156,4 +285,8 @@ impl super::Backend for BedrockBackend {
             ))
             .exists()
     }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
 }
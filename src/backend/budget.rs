@@ -0,0 +1,218 @@
+//! Context-window budgeting for prompts
+//!
+//! Token counts are estimated (about four characters per token), so budgets
+//! are approximate. A backend's window comes from `context_window` in its
+//! config; Ollama backends without one use `options.num_ctx`, or Ollama's own
+//! default, which it silently truncates prompts to.
+
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+
+/// Context Ollama gives a model when `num_ctx` isn't set
+pub const OLLAMA_DEFAULT_NUM_CTX: u64 = 2048;
+
+/// Tokens kept free for the "part i of n" header of each chunk
+const CHUNK_HEADER_TOKENS: u64 = 32;
+
+/// What to do with a prompt that doesn't fit the backend's context window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextOverflow {
+    /// Fail the step with the estimated size and the budget
+    #[default]
+    Error,
+    /// Cut the middle of the prompt, keeping its start and end
+    Truncate,
+    /// Leave out earlier step outputs, oldest first, until the prompt fits
+    DropOldest,
+    /// Send the prompt in parts that each fit and join the answers
+    Chunk,
+}
+
+/// Tokens available to a prompt on one backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Budget {
+    pub backend: String,
+    pub window: u64,
+    /// Kept free for the answer (the request's `max_tokens`)
+    pub reserved: u64,
+}
+
+impl Budget {
+    /// Budget for `name`, if its context window is known
+    pub fn for_backend(name: &str, config: &Config, reserved: Option<u32>) -> Option<Self> {
+        let backend = config.backends.get(name)?;
+        let window = backend.context_window.or_else(|| {
+            (backend.kind.as_deref().unwrap_or(name) == "ollama").then(|| {
+                backend
+                    .options
+                    .get("num_ctx")
+                    .and_then(serde_json::Value::as_u64)
+                    .unwrap_or(OLLAMA_DEFAULT_NUM_CTX)
+            })
+        })?;
+        Some(Self {
+            backend: name.to_string(),
+            window,
+            reserved: reserved.map_or(0, u64::from),
+        })
+    }
+
+    /// The smallest budget of `names`, so the prompt fits all of them
+    pub fn for_backends(names: &[String], config: &Config, reserved: Option<u32>) -> Option<Self> {
+        names
+            .iter()
+            .filter_map(|name| Self::for_backend(name, config, reserved))
+            .min_by_key(|budget| budget.available())
+    }
+
+    /// Tokens left for the prompt once the answer is reserved
+    pub fn available(&self) -> u64 {
+        self.window.saturating_sub(self.reserved)
+    }
+
+    /// "~N of M tokens (backend)" for verbose output
+    pub fn describe(&self, tokens: u64) -> String {
+        let mut text = format!("~{} of {} tokens", tokens, self.available());
+        if self.reserved > 0 {
+            text.push_str(&format!(
+                " ({} window, {} reserved for output)",
+                self.window, self.reserved
+            ));
+        }
+        text.push_str(&format!(" on {}", self.backend));
+        text
+    }
+}
+
+/// Rough token count: about four characters per token
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Cut the middle out of `text` so it fits `max_tokens`, marking the gap
+pub fn truncate_middle(text: &str, max_tokens: u64) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let marker = format!(
+        "\n\n[... about {} tokens truncated to fit the context window ...]\n\n",
+        estimate_tokens(text).saturating_sub(max_tokens)
+    );
+    let keep = (max_tokens * 4).saturating_sub(marker.chars().count() as u64) as usize;
+    let head = keep.div_ceil(2);
+    let tail = keep - head;
+
+    let mut truncated: String = chars[..head].iter().collect();
+    truncated.push_str(&marker);
+    truncated.extend(&chars[chars.len() - tail..]);
+    truncated
+}
+
+/// Split `text` into parts of at most `max_tokens`, each headed with its
+/// position. Parts break at line ends where possible.
+pub fn split_chunks(text: &str, max_tokens: u64) -> Vec<String> {
+    let max_chars = (max_tokens.saturating_sub(CHUNK_HEADER_TOKENS).max(1) * 4) as usize;
+
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in text.split_inclusive('\n') {
+        let mut line = line;
+        // Lines longer than a whole part are split wherever they reach the limit
+        while current.chars().count() + line.chars().count() > max_chars {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+                continue;
+            }
+            let split = line
+                .char_indices()
+                .nth(max_chars)
+                .map_or(line.len(), |(i, _)| i);
+            parts.push(line[..split].to_string());
+            line = &line[split..];
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    let total = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| {
+            format!(
+                "[Part {} of {} of a prompt too long to send at once. Answer for this part.]\n\n{}",
+                i + 1,
+                total,
+                part
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendConfig;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn test_budget_windows() {
+        let mut config = Config::default();
+        config.backends.insert(
+            "big".to_string(),
+            BackendConfig {
+                kind: Some("openai".to_string()),
+                context_window: Some(128_000),
+                ..Default::default()
+            },
+        );
+
+        // Ollama without num_ctx gets Ollama's default; unknown windows aren't budgeted
+        let ollama = Budget::for_backend("ollama", &config, None).unwrap();
+        assert_eq!(ollama.window, OLLAMA_DEFAULT_NUM_CTX);
+        assert!(Budget::for_backend("codex", &config, None).is_none());
+
+        let big = Budget::for_backend("big", &config, Some(1000)).unwrap();
+        assert_eq!(big.available(), 127_000);
+
+        let names = vec!["big".to_string(), "ollama".to_string(), "codex".to_string()];
+        let smallest = Budget::for_backends(&names, &config, None).unwrap();
+        assert_eq!(smallest.backend, "ollama");
+    }
+
+    #[test]
+    fn test_truncate_middle_keeps_ends() {
+        let text = format!("START{}END", "x".repeat(4000));
+        let truncated = truncate_middle(&text, 100);
+        assert!(estimate_tokens(&truncated) <= 100);
+        assert!(truncated.starts_with("START"));
+        assert!(truncated.ends_with("END"));
+        assert!(truncated.contains("tokens truncated"));
+
+        assert_eq!(truncate_middle("short", 100), "short");
+    }
+
+    #[test]
+    fn test_split_chunks_fit() {
+        let text = "line of text\n".repeat(200);
+        let chunks = split_chunks(&text, 100);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| estimate_tokens(c) <= 100));
+        assert!(chunks[0].starts_with(&format!("[Part 1 of {}", chunks.len())));
+
+        // One very long line is split too
+        let chunks = split_chunks(&"y".repeat(2000), 100);
+        assert!(chunks.iter().all(|c| estimate_tokens(c) <= 100));
+    }
+}
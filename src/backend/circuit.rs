@@ -0,0 +1,371 @@
+//! Per-run circuit breaker for backends that keep failing
+//!
+//! After `threshold` consecutive failures of the same kind (auth errors,
+//! timeouts, rate limits...) a backend's circuit opens and further requests
+//! fail immediately with `CircuitOpen`, which a fallback chain treats like the
+//! original error. Once `cooldown` has passed, one request is let through as a
+//! probe: success closes the circuit, failure keeps it open for another cooldown.
+
+use super::stream::ChunkSender;
+use super::{Backend, Completion, Health, QueryRequest};
+use crate::config::CircuitBreakerConfig;
+use crate::utils::{classify_backend_error, BackendErrorKind};
+use anyhow::Result;
+use async_trait::async_trait;
+use colored::Colorize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Returned instead of querying a backend whose circuit is open
+#[derive(Debug, thiserror::Error)]
+#[error("{backend} skipped: circuit open after {failures} consecutive errors ({}), retrying in {}s", kind.description(), retry_in.as_secs().max(1))]
+pub struct CircuitOpen {
+    pub backend: String,
+    pub kind: BackendErrorKind,
+    pub failures: u32,
+    pub retry_in: Duration,
+}
+
+/// Classify a backend error, seeing through `CircuitOpen`
+pub fn error_kind(error: &anyhow::Error) -> BackendErrorKind {
+    match error.downcast_ref::<CircuitOpen>() {
+        Some(open) => open.kind,
+        None => classify_backend_error(&format!("{:#}", error)),
+    }
+}
+
+#[derive(Default)]
+struct Circuit {
+    kind: Option<BackendErrorKind>,
+    failures: u32,
+    opened_at: Option<Instant>,
+    /// A probe request is in flight after the cooldown
+    probing: bool,
+}
+
+/// Failure counts per backend name. Clones share state, so one breaker can be
+/// handed to every step of a workflow or every round of a debate.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            circuits: Arc::default(),
+            threshold: config.threshold,
+            cooldown: Duration::from_secs(config.cooldown),
+        }
+    }
+
+    /// Wrap `backend` so its requests are counted and short-circuited by this breaker
+    pub fn wrap(&self, backend: Arc<dyn Backend>) -> Arc<dyn Backend> {
+        if self.threshold == 0 {
+            return backend;
+        }
+        Arc::new(BreakerBackend {
+            inner: backend,
+            breaker: self.clone(),
+        })
+    }
+
+    /// Fail fast if `name`'s circuit is open; after the cooldown, admit one probe
+    fn check(&self, name: &str) -> Result<(), CircuitOpen> {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(circuit) = circuits.get_mut(name) else {
+            return Ok(());
+        };
+        let Some(opened_at) = circuit.opened_at else {
+            return Ok(());
+        };
+
+        let elapsed = opened_at.elapsed();
+        if elapsed >= self.cooldown && !circuit.probing {
+            circuit.probing = true;
+            return Ok(());
+        }
+
+        Err(CircuitOpen {
+            backend: name.to_string(),
+            kind: circuit.kind.unwrap_or(BackendErrorKind::Unknown),
+            failures: circuit.failures,
+            retry_in: self.cooldown.saturating_sub(elapsed),
+        })
+    }
+
+    fn record_success(&self, name: &str) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(circuit) = circuits.get_mut(name) {
+            if circuit.opened_at.is_some() {
+                eprintln!("{} {} is answering again", "info:".cyan(), name);
+            }
+            *circuit = Circuit::default();
+        }
+    }
+
+    /// Count a query to `name` cut off by a deadline of the caller's, such as
+    /// a step timeout, which the backend itself only sees as a cancellation
+    pub fn record_timeout(&self, name: &str) {
+        if self.threshold > 0 {
+            self.record_failure(name, BackendErrorKind::Timeout);
+        }
+    }
+
+    /// A cancelled probe says nothing either way; let the next request probe
+    fn record_cancelled(&self, name: &str) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(circuit) = circuits.get_mut(name) {
+            circuit.probing = false;
+        }
+    }
+
+    fn record_failure(&self, name: &str, kind: BackendErrorKind) {
+        // Errors another backend couldn't avoid say nothing about this one's health
+        if !kind.should_fall_back() {
+            self.record_success(name);
+            return;
+        }
+
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let circuit = circuits.entry(name.to_string()).or_default();
+        if circuit.kind == Some(kind) {
+            circuit.failures += 1;
+        } else {
+            circuit.kind = Some(kind);
+            circuit.failures = 1;
+        }
+
+        // A failed probe keeps the circuit open for another cooldown
+        let was_open = circuit.opened_at.is_some();
+        circuit.probing = false;
+        if was_open || circuit.failures >= self.threshold {
+            circuit.opened_at = Some(Instant::now());
+            if !was_open {
+                eprintln!(
+                    "{} {} failed {} times in a row ({}); skipping it for {}s",
+                    "warning:".yellow(),
+                    name,
+                    circuit.failures,
+                    kind.description(),
+                    self.cooldown.as_secs()
+                );
+            }
+        }
+    }
+}
+
+/// A request in flight. One dropped before finishing (cancelled by a step
+/// timeout, a consensus that no longer needs it, Ctrl-C...) counts as neither
+/// success nor failure, but frees the probe slot it may hold.
+struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    name: &'a str,
+    finished: bool,
+}
+
+impl Attempt<'_> {
+    fn finish<T>(mut self, result: Result<T>) -> Result<T> {
+        self.finished = true;
+        match &result {
+            Ok(_) => self.breaker.record_success(self.name),
+            Err(e) => self.breaker.record_failure(self.name, error_kind(e)),
+        }
+        result
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.breaker.record_cancelled(self.name);
+        }
+    }
+}
+
+struct BreakerBackend {
+    inner: Arc<dyn Backend>,
+    breaker: CircuitBreaker,
+}
+
+impl BreakerBackend {
+    fn start(&self) -> Result<Attempt<'_>> {
+        self.breaker.check(self.inner.name())?;
+        Ok(Attempt {
+            breaker: &self.breaker,
+            name: self.inner.name(),
+            finished: false,
+        })
+    }
+}
+
+#[async_trait]
+impl Backend for BreakerBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let attempt = self.start()?;
+        attempt.finish(self.inner.complete(request, cwd).await)
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        let attempt = self.start()?;
+        attempt.finish(self.inner.query_stream(request, cwd, chunks).await)
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    async fn health(&self) -> Health {
+        self.inner.health().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::limit::TimeLimitedBackend;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Backend that fails with `error` until `healthy` is set
+    struct Flaky {
+        error: &'static str,
+        healthy: std::sync::atomic::AtomicBool,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Backend for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn complete(&self, _request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok("ok".to_string().into())
+            } else {
+                anyhow::bail!("{}", self.error)
+            }
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+    }
+
+    fn flaky(error: &'static str) -> Arc<Flaky> {
+        Arc::new(Flaky {
+            error,
+            healthy: Default::default(),
+            calls: AtomicUsize::new(0),
+        })
+    }
+
+    fn breaker(threshold: u32, cooldown: u64) -> CircuitBreaker {
+        CircuitBreaker::new(&CircuitBreakerConfig {
+            threshold,
+            cooldown,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_opens_after_consecutive_failures() {
+        let inner = flaky("HTTP 401 Unauthorized");
+        let backend = breaker(2, 60).wrap(inner.clone());
+
+        for _ in 0..2 {
+            backend.query("hi", Path::new(".")).await.unwrap_err();
+        }
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        let open = err
+            .downcast_ref::<CircuitOpen>()
+            .expect("circuit should be open");
+        assert_eq!(open.kind, BackendErrorKind::AuthError);
+        assert_eq!(error_kind(&err), BackendErrorKind::AuthError);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_errors_do_not_open() {
+        let inner = flaky("prompt is malformed");
+        let backend = breaker(2, 60).wrap(inner.clone());
+
+        for _ in 0..4 {
+            backend.query("hi", Path::new(".")).await.unwrap_err();
+        }
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_probe_after_cooldown_closes_circuit() {
+        let inner = flaky("connection refused");
+        let breaker = breaker(1, 0);
+        let backend = breaker.wrap(inner.clone());
+
+        backend.query("hi", Path::new(".")).await.unwrap_err();
+        assert!(breaker.circuits.lock().unwrap()["flaky"]
+            .opened_at
+            .is_some());
+
+        // Cooldown of 0s: the next request is the probe
+        inner.healthy.store(true, Ordering::SeqCst);
+        assert_eq!(backend.query("hi", Path::new(".")).await.unwrap(), "ok");
+        assert!(breaker.circuits.lock().unwrap()["flaky"]
+            .opened_at
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_is_neutral() {
+        struct Hang;
+
+        #[async_trait]
+        impl Backend for Hang {
+            fn name(&self) -> &str {
+                "hang"
+            }
+
+            async fn complete(&self, _request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+                std::future::pending().await
+            }
+
+            fn is_available(&self) -> bool {
+                true
+            }
+        }
+
+        let breaker = breaker(1, 60);
+        let backend = breaker.wrap(Arc::new(Hang));
+        let query = backend.query("hi", Path::new("."));
+        tokio::time::timeout(Duration::from_millis(10), query)
+            .await
+            .unwrap_err();
+        assert!(breaker.circuits.lock().unwrap().get("hang").is_none());
+
+        // A timeout beneath the breaker is a failure like any other
+        let limited = TimeLimitedBackend::new(Arc::new(Hang), Duration::from_millis(10));
+        let backend = breaker.wrap(Arc::new(limited));
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        assert!(err.downcast_ref::<CircuitOpen>().is_none());
+        assert_eq!(error_kind(&err), BackendErrorKind::Timeout);
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        assert!(err.downcast_ref::<CircuitOpen>().is_some());
+    }
+}
@@ -1,4 +1,11 @@
+use super::health::{self, Health};
+use super::permissions::CliPermissions;
+use super::prompt_via::{PromptInput, PromptVia};
+use super::retry::HttpError;
+use super::stream::{ChunkSender, LineBuffer};
+use super::{Attachment, Completion, Message, QueryRequest, Role, ToolCall};
 use crate::config::BackendConfig;
+use crate::usage::Usage;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use secrecy::{ExposeSecret, SecretString};
@@ -8,6 +15,15 @@ use std::path::Path;
 use std::process::Stdio;
 use tokio::process::Command;
 
+/// System prompt for API requests that don't set one
+const DEFAULT_SYSTEM: &str = "You are a helpful assistant.";
+
+/// The Messages API requires max_tokens
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// First CLI release with `--append-system-prompt`, `--resume` and JSON output
+const MIN_CLI_VERSION: &str = "1.0.0";
+
 /// Claude backend mode - API or CLI
 #[derive(Clone)]
 pub enum ClaudeMode {
@@ -21,33 +37,75 @@ pub enum ClaudeMode {
     Cli {
         command: String,
         model: Option<String>,
+        permissions: CliPermissions,
+        prompt_via: Option<PromptVia>,
     },
 }
 
 pub struct ClaudeBackend {
+    name: String,
     mode: ClaudeMode,
+    min_version: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct ClaudeResponse {
     content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<Usage>,
 }
 
 #[derive(Deserialize)]
 struct ContentBlock {
+    #[serde(rename = "type", default)]
+    kind: String,
     text: Option<String>,
+    /// Set on `tool_use` blocks
+    id: Option<String>,
+    name: Option<String>,
+    input: Option<serde_json::Value>,
+}
+
+impl ClaudeResponse {
+    /// Text blocks joined by newlines, `tool_use` blocks as tool calls
+    fn into_completion(self) -> Completion {
+        let mut texts = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in self.content {
+            match (block.kind.as_str(), block.id, block.name) {
+                ("tool_use", Some(id), Some(name)) => tool_calls.push(ToolCall {
+                    id,
+                    name,
+                    input: block.input.unwrap_or_default(),
+                }),
+                _ => texts.extend(block.text),
+            }
+        }
+
+        Completion {
+            text: texts.join("\n"),
+            usage: self.usage,
+            tool_calls,
+            ..Default::default()
+        }
+    }
 }
 
 impl ClaudeBackend {
-    pub fn new(config: &BackendConfig) -> Result<Self> {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
         // Check if we have a command configured (CLI mode) or API key (API mode)
         if let Some(ref cmd) = config.command {
             // CLI mode - use claude command
             Ok(Self {
+                name: name.to_string(),
                 mode: ClaudeMode::Cli {
                     command: cmd.clone(),
                     model: config.model.clone(),
+                    // The CLI is run with fixed flags, not `args`
+                    permissions: CliPermissions::new("claude", config, &[])?,
+                    prompt_via: config.prompt_via,
                 },
+                min_version: config.min_version.clone(),
             })
         } else {
             // API mode - requires API key
@@ -68,28 +126,18 @@ impl ClaudeBackend {
             let client = reqwest::Client::new();
 
             Ok(Self {
+                name: name.to_string(),
                 mode: ClaudeMode::Api {
                     api_key,
                     model,
                     client,
                 },
+                min_version: None,
             })
         }
     }
 
-    /// Get API mode details (for conductor)
-    pub fn api_details(&self) -> Option<(&SecretString, &str, &reqwest::Client)> {
-        match &self.mode {
-            ClaudeMode::Api {
-                api_key,
-                model,
-                client,
-            } => Some((api_key, model, client)),
-            ClaudeMode::Cli { .. } => None,
-        }
-    }
-
-    async fn query_api(&self, system: &str, prompt: &str) -> Result<String> {
+    fn api_request(&self, request: &QueryRequest, stream: bool) -> Result<reqwest::RequestBuilder> {
         let (api_key, model, client) = match &self.mode {
             ClaudeMode::Api {
                 api_key,
@@ -99,32 +147,25 @@ impl ClaudeBackend {
             ClaudeMode::Cli { .. } => anyhow::bail!("API mode required for this operation"),
         };
 
-        let request = serde_json::json!({
-            "model": model,
-            "max_tokens": 4096,
-            "system": system,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": prompt
-                }
-            ]
-        });
+        let request = api_body(model, request, stream);
 
-        let response = client
+        Ok(client
             .post("https://api.anthropic.com/v1/messages")
             .header("x-api-key", api_key.expose_secret())
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
-            .json(&request)
+            .json(&request))
+    }
+
+    async fn query_api(&self, request: &QueryRequest) -> Result<Completion> {
+        let response = self
+            .api_request(request, false)?
             .send()
             .await
             .context("Failed to send request to Claude API")?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("Claude API error {}: {}", status, body);
+            return Err(HttpError::from_response("Claude API", response).await.into());
         }
 
         let response: ClaudeResponse = response
@@ -132,40 +173,113 @@ impl ClaudeBackend {
             .await
             .context("Failed to parse Claude response")?;
 
-        let text = response
-            .content
-            .into_iter()
-            .filter_map(|block| block.text)
-            .collect::<Vec<_>>()
-            .join("\n");
+        Ok(response.into_completion())
+    }
+
+    /// Same as `query_api`, but reads the server-sent event stream as it arrives
+    async fn query_api_stream(
+        &self,
+        request: &QueryRequest,
+        chunks: &ChunkSender,
+    ) -> Result<Completion> {
+        let mut response = self
+            .api_request(request, true)?
+            .send()
+            .await
+            .context("Failed to send request to Claude API")?;
+
+        if !response.status().is_success() {
+            return Err(HttpError::from_response("Claude API", response).await.into());
+        }
+
+        let mut lines = LineBuffer::default();
+        let mut output = String::new();
+        let mut usage = Usage::default();
+        let mut handle_line = |line: &str| -> Result<()> {
+            // Only `data:` lines carry payloads; `event:` repeats the type
+            let Some(data) = line.strip_prefix("data:") else {
+                return Ok(());
+            };
+            let event: serde_json::Value = serde_json::from_str(data.trim())
+                .with_context(|| format!("Invalid Claude stream event: {}", data))?;
+            if let Some(text) = stream_event_text(&event, !output.is_empty())? {
+                let _ = chunks.send(text.clone());
+                output.push_str(&text);
+            }
+            stream_event_usage(&event, &mut usage);
+            Ok(())
+        };
+
+        while let Some(bytes) = response
+            .chunk()
+            .await
+            .context("Failed to read Claude stream")?
+        {
+            for line in lines.push(&bytes) {
+                handle_line(&line)?;
+            }
+        }
+        if let Some(line) = lines.finish() {
+            handle_line(&line)?;
+        }
 
-        Ok(text)
+        Ok(Completion {
+            text: output,
+            usage: Some(usage),
+            ..Default::default()
+        })
     }
 
-    async fn query_cli(&self, prompt: &str, cwd: &Path) -> Result<String> {
-        let (command, model) = match &self.mode {
-            ClaudeMode::Cli { command, model } => (command, model),
+    async fn query_cli(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let (command, model, permissions, prompt_via) = match &self.mode {
+            ClaudeMode::Cli {
+                command,
+                model,
+                permissions,
+                prompt_via,
+            } => (command, model, permissions, *prompt_via),
             ClaudeMode::Api { .. } => anyhow::bail!("CLI mode required for this operation"),
         };
 
         let mut cmd = Command::new(command);
         cmd.arg("-p") // print mode
             .arg("--output-format")
-            .arg("text");
+            .arg("json"); // single result object with session id and usage
 
         if let Some(m) = model {
             cmd.arg("--model").arg(m);
         }
 
-        cmd.arg("--") // Prevent prompt from being interpreted as flags
-            .arg(prompt)
-            .current_dir(cwd)
+        // --permission-mode: edits and commands need approval, which -p can't give
+        cmd.args(permissions.args(request.permissions)?);
+
+        // Keep Claude Code's own system prompt and add ours to it
+        if let Some(system) = request.system_with_format_hint() {
+            cmd.arg("--append-system-prompt").arg(system);
+        }
+
+        // A resumed session already holds the earlier turns
+        let prompt = match request.session {
+            Some(ref session) => {
+                cmd.arg("--resume").arg(session);
+                request.prompt_with_attachments()
+            }
+            None => request.prompt_with_history(),
+        };
+
+        // Without a prompt argument, `-p` reads it from stdin
+        let input = PromptInput::new(prompt_via, prompt)?;
+        if let Some(prompt) = input.arg() {
+            cmd.arg("--") // Prevent prompt from being interpreted as flags
+                .arg(prompt);
+        }
+        cmd.current_dir(cwd)
             .kill_on_drop(true)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
-        let output = cmd
-            .output()
+        let output = input
+            .output(&mut cmd)
             .await
             .context("Failed to execute claude command")?;
 
@@ -174,35 +288,208 @@ impl ClaudeBackend {
             anyhow::bail!("Claude CLI failed: {}", stderr);
         }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.trim().to_string())
+        parse_cli_output(&String::from_utf8_lossy(&output.stdout))
     }
+}
 
-    pub async fn query_with_system(&self, system: &str, prompt: &str) -> Result<String> {
-        match &self.mode {
-            ClaudeMode::Api { .. } => self.query_api(system, prompt).await,
-            ClaudeMode::Cli { .. } => {
-                // For CLI mode, prepend system prompt to user prompt
-                let full_prompt = format!("{}\n\n{}", system, prompt);
-                self.query_cli(&full_prompt, Path::new(".")).await
+/// Result object printed by `claude -p --output-format json`
+#[derive(Deserialize)]
+struct CliResult {
+    #[serde(default)]
+    result: String,
+    #[serde(default)]
+    is_error: bool,
+    session_id: Option<String>,
+    usage: Option<Usage>,
+}
+
+/// Parse CLI output, falling back to plain text for CLIs that ignore `--output-format`
+fn parse_cli_output(stdout: &str) -> Result<Completion> {
+    let stdout = stdout.trim();
+    let Ok(result) = serde_json::from_str::<CliResult>(stdout) else {
+        return Ok(stdout.to_string().into());
+    };
+
+    if result.is_error {
+        anyhow::bail!("Claude CLI failed: {}", result.result);
+    }
+
+    Ok(Completion {
+        text: result.result.trim().to_string(),
+        usage: result.usage,
+        session: result.session_id,
+        ..Default::default()
+    })
+}
+
+/// Messages API request body
+fn api_body(model: &str, request: &QueryRequest, stream: bool) -> serde_json::Value {
+    let system = request
+        .system_with_format_hint()
+        .unwrap_or_else(|| DEFAULT_SYSTEM.to_string());
+
+    let mut body = serde_json::json!({
+        "model": model,
+        "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "system": system,
+        "messages": api_messages(&request.messages())
+    });
+    if !request.tools.is_empty() {
+        body["tools"] = serde_json::json!(request.tools);
+    }
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = serde_json::json!(temperature);
+    }
+    if !request.stop.is_empty() {
+        body["stop_sequences"] = serde_json::json!(request.stop);
+    }
+    if stream {
+        body["stream"] = serde_json::Value::Bool(true);
+    }
+    body
+}
+
+/// Messages API turns: tool calls become `tool_use` blocks, and consecutive
+/// tool results are sent together as one user turn of `tool_result` blocks
+fn api_messages(messages: &[Message]) -> Vec<serde_json::Value> {
+    let mut turns: Vec<serde_json::Value> = Vec::new();
+    for message in messages {
+        match message.role {
+            Role::Tool => {
+                let block = serde_json::json!({
+                    "type": "tool_result",
+                    "tool_use_id": message.tool_call_id,
+                    "content": message.content,
+                });
+                let previous = turns
+                    .last_mut()
+                    .filter(|turn| turn["role"] == "user")
+                    .and_then(|turn| turn["content"].as_array_mut());
+                match previous {
+                    Some(blocks) => blocks.push(block),
+                    None => turns.push(serde_json::json!({ "role": "user", "content": [block] })),
+                }
             }
+            Role::Assistant if !message.tool_calls.is_empty() => {
+                let mut blocks = Vec::new();
+                if !message.content.is_empty() {
+                    blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+                }
+                blocks.extend(message.tool_calls.iter().map(|call| {
+                    serde_json::json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.name,
+                        "input": call.input,
+                    })
+                }));
+                turns.push(serde_json::json!({ "role": "assistant", "content": blocks }));
+            }
+            // Files and images go before the question, as Anthropic recommends
+            Role::User if !message.attachments.is_empty() => {
+                let mut blocks: Vec<serde_json::Value> = message
+                    .attachments
+                    .iter()
+                    .map(|attachment| match attachment {
+                        Attachment::Text { path, content } => serde_json::json!({
+                            "type": "document",
+                            "source": { "type": "text", "media_type": "text/plain", "data": content },
+                            "title": path,
+                        }),
+                        Attachment::Image {
+                            media_type, data, ..
+                        } => serde_json::json!({
+                            "type": "image",
+                            "source": { "type": "base64", "media_type": media_type, "data": data },
+                        }),
+                    })
+                    .collect();
+                if !message.content.is_empty() {
+                    blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+                }
+                turns.push(serde_json::json!({ "role": "user", "content": blocks }));
+            }
+            _ => turns.push(serde_json::json!({
+                "role": message.role.as_str(),
+                "content": message.content,
+            })),
         }
     }
+    turns
+}
+
+/// Text contributed by one Messages API stream event, if any.
+///
+/// `has_output` separates consecutive text blocks with a newline, matching how
+/// the non-streaming path joins them.
+fn stream_event_text(event: &serde_json::Value, has_output: bool) -> Result<Option<String>> {
+    match event.get("type").and_then(|t| t.as_str()) {
+        Some("content_block_start") if has_output => {
+            let is_text = event
+                .pointer("/content_block/type")
+                .and_then(|t| t.as_str())
+                == Some("text");
+            Ok(is_text.then(|| "\n".to_string()))
+        }
+        Some("content_block_delta") => Ok(event
+            .pointer("/delta/text")
+            .and_then(|t| t.as_str())
+            .map(|t| t.to_string())),
+        Some("error") => {
+            let message = event
+                .pointer("/error/message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error");
+            anyhow::bail!("Claude API error: {}", message)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Token counts arrive in two parts: input on `message_start`, output on `message_delta`
+fn stream_event_usage(event: &serde_json::Value, usage: &mut Usage) {
+    let count = |pointer: &str| event.pointer(pointer).and_then(|v| v.as_u64());
+    match event.get("type").and_then(|t| t.as_str()) {
+        Some("message_start") => {
+            if let Some(n) = count("/message/usage/input_tokens") {
+                usage.input_tokens = n;
+            }
+        }
+        Some("message_delta") => {
+            if let Some(n) = count("/usage/output_tokens") {
+                usage.output_tokens = n;
+            }
+        }
+        _ => {}
+    }
 }
 
 #[async_trait]
 impl super::Backend for ClaudeBackend {
     fn name(&self) -> &str {
-        "claude"
+        &self.name
     }
 
-    async fn query(&self, prompt: &str, cwd: &Path) -> Result<String> {
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
         match &self.mode {
-            ClaudeMode::Api { .. } => {
-                self.query_with_system("You are a helpful assistant.", prompt)
-                    .await
+            ClaudeMode::Api { .. } => self.query_api(request).await,
+            ClaudeMode::Cli { .. } => self.query_cli(request, cwd).await,
+        }
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        match &self.mode {
+            ClaudeMode::Api { .. } => self.query_api_stream(request, &chunks).await,
+            ClaudeMode::Cli { .. } => {
+                let completion = self.query_cli(request, cwd).await?;
+                let _ = chunks.send(completion.text.clone());
+                Ok(completion)
             }
-            ClaudeMode::Cli { .. } => self.query_cli(prompt, cwd).await,
         }
     }
 
@@ -212,4 +499,275 @@ impl super::Backend for ClaudeBackend {
             ClaudeMode::Cli { command, .. } => which::which(command).is_ok(),
         }
     }
+
+    fn supports_tools(&self) -> bool {
+        matches!(self.mode, ClaudeMode::Api { .. })
+    }
+
+    async fn health(&self) -> Health {
+        match &self.mode {
+            ClaudeMode::Cli { command, .. } => {
+                let min_version = self.min_version.as_deref().unwrap_or(MIN_CLI_VERSION);
+                health::probe_cli(
+                    command,
+                    &[],
+                    Some(min_version),
+                    "npm install -g @anthropic-ai/claude-code",
+                )
+                .await
+            }
+            ClaudeMode::Api {
+                api_key,
+                model,
+                client,
+            } => {
+                // Looking up the configured model checks the key and the model name at once
+                let url = format!("https://api.anthropic.com/v1/models/{}", model);
+                let response = client
+                    .get(&url)
+                    .header("x-api-key", api_key.expose_secret())
+                    .header("anthropic-version", "2023-06-01")
+                    .timeout(std::time::Duration::from_secs(10))
+                    .send()
+                    .await;
+
+                match response {
+                    Ok(r) if r.status().is_success() => Health::ready(),
+                    Ok(r) if r.status() == reqwest::StatusCode::UNAUTHORIZED => {
+                        Health::unavailable("API key rejected")
+                            .hint("Check ANTHROPIC_API_KEY (or the variable named by api_key_env)")
+                    }
+                    Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => {
+                        Health::unavailable(format!("model {} not found", model))
+                            .hint("Set `model` to a current Claude model ID in lok.toml")
+                    }
+                    Ok(r) => Health::unavailable(format!("Claude API returned {}", r.status()))
+                        .hint("Check https://status.anthropic.com"),
+                    Err(e) => health::connect_failed("api.anthropic.com", &e)
+                        .hint("Check your network connection or proxy settings"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_api_body_defaults() {
+        let body = api_body("claude-test", &QueryRequest::new("hi"), false);
+        assert_eq!(body["system"], DEFAULT_SYSTEM);
+        assert_eq!(body["max_tokens"], 4096);
+        assert_eq!(body["messages"][0]["content"], "hi");
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("stop_sequences").is_none());
+        assert!(body.get("stream").is_none());
+    }
+
+    #[test]
+    fn test_api_body_attachments() {
+        let request = QueryRequest {
+            attachments: vec![
+                Attachment::Text {
+                    path: "src/lib.rs".to_string(),
+                    content: "pub fn f() {}".to_string(),
+                },
+                Attachment::Image {
+                    path: "shot.png".to_string(),
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw==".to_string(),
+                },
+            ],
+            ..QueryRequest::new("review")
+        };
+        let body = api_body("claude-test", &request, false);
+        let blocks = &body["messages"][0]["content"];
+        assert_eq!(blocks[0]["type"], "document");
+        assert_eq!(blocks[0]["title"], "src/lib.rs");
+        assert_eq!(blocks[0]["source"]["data"], "pub fn f() {}");
+        assert_eq!(blocks[1]["type"], "image");
+        assert_eq!(blocks[1]["source"]["media_type"], "image/png");
+        assert_eq!(blocks[2]["text"], "review");
+    }
+
+    #[test]
+    fn test_api_body_sends_history() {
+        let request = QueryRequest {
+            history: vec![
+                crate::backend::Message::user("first"),
+                crate::backend::Message::assistant("answer"),
+            ],
+            ..QueryRequest::new("second")
+        };
+        let body = api_body("claude-test", &request, false);
+        assert_eq!(
+            body["messages"],
+            json!([
+                {"role": "user", "content": "first"},
+                {"role": "assistant", "content": "answer"},
+                {"role": "user", "content": "second"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_api_body_tool_turns() {
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: "query_backend".to_string(),
+            input: json!({"backend": "codex"}),
+        };
+        let request = QueryRequest {
+            tools: vec![crate::backend::Tool {
+                name: "query_backend".to_string(),
+                description: "Ask a backend".to_string(),
+                input_schema: json!({"type": "object"}),
+            }],
+            history: vec![
+                Message::user("task"),
+                Message::assistant_with_calls("Checking.", vec![call("t1"), call("t2")]),
+                Message::tool_result(&call("t1"), "one"),
+                Message::tool_result(&call("t2"), "two"),
+            ],
+            ..QueryRequest::new("")
+        };
+
+        let body = api_body("claude-test", &request, false);
+        assert_eq!(body["tools"][0]["name"], "query_backend");
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3, "tool results don't add a user prompt");
+        assert_eq!(messages[1]["content"][0]["text"], "Checking.");
+        assert_eq!(messages[1]["content"][2]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][2]["id"], "t2");
+        assert_eq!(
+            messages[2],
+            json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "t1", "content": "one"},
+                {"type": "tool_result", "tool_use_id": "t2", "content": "two"}
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_response_tool_use() {
+        let response: ClaudeResponse = serde_json::from_value(json!({
+            "content": [
+                {"type": "text", "text": "Let me ask codex."},
+                {"type": "tool_use", "id": "toolu_1", "name": "query_backend", "input": {"backend": "codex"}}
+            ],
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        }))
+        .unwrap();
+        let completion = response.into_completion();
+        assert_eq!(completion.text, "Let me ask codex.");
+        assert_eq!(completion.tool_calls[0].id, "toolu_1");
+        assert_eq!(completion.tool_calls[0].input["backend"], "codex");
+    }
+
+    #[test]
+    fn test_parse_cli_output_json() {
+        let stdout = r#"{"type":"result","subtype":"success","is_error":false,"result":"hello\n","session_id":"0f6c","usage":{"input_tokens":12,"cache_read_input_tokens":100,"output_tokens":3}}"#;
+        let completion = parse_cli_output(stdout).unwrap();
+        assert_eq!(completion.text, "hello");
+        assert_eq!(completion.session.as_deref(), Some("0f6c"));
+        assert_eq!(completion.usage, Some(Usage::new(12, 3)));
+    }
+
+    #[test]
+    fn test_parse_cli_output_error_and_plain_text() {
+        let err = parse_cli_output(r#"{"is_error":true,"result":"rate limited"}"#).unwrap_err();
+        assert!(err.to_string().contains("rate limited"));
+
+        let completion = parse_cli_output("just text\n").unwrap();
+        assert_eq!(completion.text, "just text");
+        assert!(completion.session.is_none());
+    }
+
+    #[test]
+    fn test_api_body_options() {
+        let request = QueryRequest {
+            system: Some("Be terse.".to_string()),
+            max_tokens: Some(100),
+            temperature: Some(0.5),
+            stop: vec!["END".to_string()],
+            response_format: crate::backend::ResponseFormat::Json,
+            ..QueryRequest::new("hi")
+        };
+
+        let body = api_body("claude-test", &request, true);
+        let system = body["system"].as_str().unwrap();
+        assert!(system.starts_with("Be terse."));
+        assert!(system.contains("JSON"));
+        assert_eq!(body["max_tokens"], 100);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["stop_sequences"], json!(["END"]));
+        assert_eq!(body["stream"], true);
+    }
+
+    #[test]
+    fn test_stream_event_text_delta() {
+        let event = json!({
+            "type": "content_block_delta",
+            "index": 0,
+            "delta": {"type": "text_delta", "text": "Hello"}
+        });
+        assert_eq!(
+            stream_event_text(&event, false).unwrap(),
+            Some("Hello".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stream_event_second_text_block_adds_newline() {
+        let event = json!({
+            "type": "content_block_start",
+            "index": 1,
+            "content_block": {"type": "text", "text": ""}
+        });
+        assert_eq!(stream_event_text(&event, false).unwrap(), None);
+        assert_eq!(
+            stream_event_text(&event, true).unwrap(),
+            Some("\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stream_event_ignores_bookkeeping() {
+        for event in [
+            json!({"type": "message_start", "message": {}}),
+            json!({"type": "ping"}),
+            json!({"type": "message_stop"}),
+        ] {
+            assert_eq!(stream_event_text(&event, true).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_stream_event_usage() {
+        let mut usage = Usage::default();
+        stream_event_usage(
+            &json!({"type": "message_start", "message": {"usage": {"input_tokens": 25, "output_tokens": 1}}}),
+            &mut usage,
+        );
+        stream_event_usage(
+            &json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 15}}),
+            &mut usage,
+        );
+        assert_eq!(usage, Usage::new(25, 15));
+    }
+
+    #[test]
+    fn test_stream_event_error() {
+        let event = json!({
+            "type": "error",
+            "error": {"type": "overloaded_error", "message": "Overloaded"}
+        });
+        let err = stream_event_text(&event, false).unwrap_err();
+        assert!(err.to_string().contains("Overloaded"));
+    }
 }
@@ -1,76 +1,207 @@
+use super::health::{self, Health};
+use super::permissions::CliPermissions;
+use super::prompt_via::{PromptInput, PromptVia};
+use super::stream::ChunkSender;
+use super::trace::{Trace, TraceEvent};
+use super::{Completion, QueryRequest};
 use crate::config::BackendConfig;
+use crate::usage::Usage;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use std::path::Path;
 use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::process::Command;
 
+/// First release with the `exec --json` item events parsed here
+const MIN_VERSION: &str = "0.44.0";
+
 pub struct CodexBackend {
+    name: String,
     command: String,
     args: Vec<String>,
+    permissions: CliPermissions,
+    prompt_via: Option<PromptVia>,
+    min_version: Option<String>,
 }
 
 impl CodexBackend {
-    pub fn new(config: &BackendConfig) -> Result<Self> {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
         let command = config
             .command
             .clone()
             .unwrap_or_else(|| "codex".to_string());
 
         let args = if config.args.is_empty() {
-            vec![
-                "exec".to_string(),
-                "--json".to_string(),
-                "-s".to_string(),
-                "read-only".to_string(),
-            ]
+            vec!["exec".to_string(), "--json".to_string()]
         } else {
             config.args.clone()
         };
 
-        Ok(Self { command, args })
+        let permissions = CliPermissions::new("codex", config, &args)?;
+
+        Ok(Self {
+            name: name.to_string(),
+            command,
+            args,
+            permissions,
+            prompt_via: config.prompt_via,
+            min_version: config.min_version.clone(),
+        })
     }
 
-    fn parse_output(&self, output: &str) -> String {
-        // Parse JSON output from codex
-        // Look for agent_message in item.completed events
-        for line in output.lines() {
-            if line.contains("\"type\":\"item.completed\"") && line.contains("agent_message") {
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(line) {
-                    if let Some(text) = json
-                        .get("item")
-                        .and_then(|i| i.get("text"))
-                        .and_then(|t| t.as_str())
-                    {
-                        return text.to_string();
+    fn parse_completion(&self, output: &str) -> Completion {
+        let trace = parse_trace(output);
+        // The last agent message is the answer; earlier ones stay in the trace
+        let text = trace
+            .events
+            .iter()
+            .rev()
+            .find_map(|event| match event {
+                TraceEvent::Message { text } => Some(text.clone()),
+                _ => None,
+            })
+            // Fallback: return raw output
+            .unwrap_or_else(|| output.to_string());
+
+        Completion {
+            text,
+            usage: parse_usage(&trace),
+            trace: (!trace.is_empty()).then_some(trace),
+            ..Default::default()
+        }
+    }
+
+    fn command(&self, request: &QueryRequest, cwd: &Path) -> Result<(Command, PromptInput)> {
+        let input = PromptInput::new(self.prompt_via, request.flattened_prompt())?;
+        let mut cmd = Command::new(&self.command);
+        cmd.args(&self.args)
+            .args(self.permissions.args(request.permissions)?); // -s <sandbox>
+        // Images are attached natively; text files are written into the prompt
+        for image in request.attachments.iter().filter(|a| a.is_image()) {
+            cmd.arg("--image").arg(image.path());
+        }
+        cmd.arg("--") // Prevent prompt from being interpreted as flags
+            .arg(input.arg().unwrap_or("-")) // `-` reads the prompt from stdin
+            .current_dir(cwd)
+            .kill_on_drop(true)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        Ok((cmd, input))
+    }
+}
+
+/// Sum the usage reported by `turn.completed` events
+fn parse_usage(trace: &Trace) -> Option<Usage> {
+    let mut total: Option<Usage> = None;
+    for event in &trace.events {
+        if let TraceEvent::TurnCompleted { usage: Some(usage) } = event {
+            *total.get_or_insert_with(Usage::default) += *usage;
+        }
+    }
+    total
+}
+
+/// Every event of a `--json` run that says what the agent did
+fn parse_trace(output: &str) -> Trace {
+    let mut trace = Trace::default();
+    for json in output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+    {
+        let str_field = |value: &serde_json::Value, key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        match json.get("type").and_then(|t| t.as_str()) {
+            Some("item.completed") => {
+                let Some(item) = json.get("item") else {
+                    continue;
+                };
+                // Releases before 0.44 called it `item_type`
+                let kind = item
+                    .get("type")
+                    .or_else(|| item.get("item_type"))
+                    .and_then(|t| t.as_str());
+                match kind {
+                    Some("agent_message") => trace.events.push(TraceEvent::Message {
+                        text: str_field(item, "text"),
+                    }),
+                    Some("reasoning") => trace.events.push(TraceEvent::Reasoning {
+                        text: str_field(item, "text"),
+                    }),
+                    Some("command_execution") => trace.events.push(TraceEvent::Command {
+                        command: str_field(item, "command"),
+                        exit_code: item.get("exit_code").and_then(|c| c.as_i64()),
+                        output: str_field(item, "aggregated_output"),
+                    }),
+                    Some("file_change") => {
+                        let changes = item.get("changes").and_then(|c| c.as_array());
+                        for change in changes.into_iter().flatten() {
+                            trace.events.push(TraceEvent::FileChange {
+                                path: str_field(change, "path"),
+                                kind: str_field(change, "kind"),
+                            });
+                        }
                     }
+                    Some("mcp_tool_call") => trace.events.push(TraceEvent::ToolCall {
+                        server: str_field(item, "server"),
+                        tool: str_field(item, "tool"),
+                    }),
+                    Some("web_search") => trace.events.push(TraceEvent::WebSearch {
+                        query: str_field(item, "query"),
+                    }),
+                    Some("error") => trace.events.push(TraceEvent::Error {
+                        message: str_field(item, "message"),
+                    }),
+                    _ => {}
                 }
             }
+            Some("turn.completed") => trace.events.push(TraceEvent::TurnCompleted {
+                usage: json
+                    .get("usage")
+                    .and_then(|u| serde_json::from_value::<Usage>(u.clone()).ok()),
+            }),
+            Some("turn.failed") => trace.events.push(TraceEvent::Error {
+                message: json
+                    .get("error")
+                    .map(|e| str_field(e, "message"))
+                    .unwrap_or_default(),
+            }),
+            Some("error") => trace.events.push(TraceEvent::Error {
+                message: str_field(&json, "message"),
+            }),
+            _ => {}
         }
+    }
+    trace
+}
 
-        // Fallback: return raw output
-        output.to_string()
+/// Text of an `item.completed` agent_message event line
+fn agent_message_text(line: &str) -> Option<String> {
+    if !(line.contains("\"type\":\"item.completed\"") && line.contains("agent_message")) {
+        return None;
     }
+    let json = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    json.get("item")
+        .and_then(|i| i.get("text"))
+        .and_then(|t| t.as_str())
+        .map(|t| t.to_string())
 }
 
 #[async_trait]
 impl super::Backend for CodexBackend {
     fn name(&self) -> &str {
-        "codex"
+        &self.name
     }
 
-    async fn query(&self, prompt: &str, cwd: &Path) -> Result<String> {
-        let mut cmd = Command::new(&self.command);
-        cmd.args(&self.args)
-            .arg("--") // Prevent prompt from being interpreted as flags
-            .arg(prompt)
-            .current_dir(cwd)
-            .kill_on_drop(true)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let output = cmd
-            .output()
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let (mut cmd, input) = self.command(request, cwd)?;
+        let output = input
+            .output(&mut cmd)
             .await
             .context("Failed to execute codex command")?;
 
@@ -80,10 +211,171 @@ impl super::Backend for CodexBackend {
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(self.parse_output(&stdout))
+        Ok(self.parse_completion(&stdout))
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        let (mut cmd, input) = self.command(request, cwd)?;
+        let mut child = cmd
+            .stdin(input.stdin()?)
+            .spawn()
+            .context("Failed to execute codex command")?;
+        let writer = input.feed(&mut child);
+
+        // Drain stderr concurrently so a chatty CLI can't block on a full pipe
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf).await;
+            buf
+        });
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut lines = BufReader::new(stdout).lines();
+        let mut raw = String::new();
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .context("Failed to read codex output")?
+        {
+            if let Some(text) = agent_message_text(&line) {
+                let _ = chunks.send(format!("{}\n", text));
+            }
+            raw.push_str(&line);
+            raw.push('\n');
+        }
+
+        let status = child
+            .wait()
+            .await
+            .context("Failed to wait for codex command")?;
+        if !status.success() {
+            let stderr = stderr_task.await.unwrap_or_default();
+            anyhow::bail!("Codex failed: {}", String::from_utf8_lossy(&stderr));
+        }
+        if let Some(writer) = writer {
+            writer
+                .await?
+                .context("Failed to write prompt to codex stdin")?;
+        }
+
+        Ok(self.parse_completion(&raw))
     }
 
     fn is_available(&self) -> bool {
         which::which(&self.command).is_ok()
     }
+
+    async fn health(&self) -> Health {
+        let min_version = self.min_version.as_deref().unwrap_or(MIN_VERSION);
+        health::probe_cli(
+            &self.command,
+            &[],
+            Some(min_version),
+            "npm install -g @openai/codex",
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Backend;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_stream_jsonl_events() {
+        let script = r#"echo '{"type":"thread.started"}'
+echo '{"type":"item.completed","item":{"type":"reasoning","text":"thinking"}}'
+echo '{"type":"item.completed","item":{"type":"agent_message","text":"done"}}'
+echo '{"type":"turn.completed","usage":{"input_tokens":120,"cached_input_tokens":100,"output_tokens":7}}'"#;
+        let config = BackendConfig {
+            command: Some("sh".to_string()),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..Default::default()
+        };
+        let backend = CodexBackend::new("codex", &config).unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let output = backend
+            .query_stream(&QueryRequest::new("ignored"), Path::new("."), tx)
+            .await
+            .unwrap();
+        assert_eq!(output.text, "done");
+        assert_eq!(output.usage, Some(Usage::new(120, 7)));
+        assert_eq!(rx.recv().await.as_deref(), Some("done\n"));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_large_prompt_on_stdin() {
+        // Reports how much of the prompt arrived on stdin, and its last argument
+        let script = r#"n=$(wc -c | tr -d ' ')
+for arg; do last=$arg; done
+echo "{\"type\":\"item.completed\",\"item\":{\"type\":\"agent_message\",\"text\":\"$n $last\"}}""#;
+        let config = BackendConfig {
+            command: Some("sh".to_string()),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..Default::default()
+        };
+        let backend = CodexBackend::new("codex", &config).unwrap();
+
+        // Too long for one argument
+        let prompt = "x".repeat(200 * 1024);
+        let output = backend
+            .complete(&QueryRequest::new(&prompt), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(output.text, format!("{} -", prompt.len()));
+
+        let output = backend
+            .complete(&QueryRequest::new("short"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(output.text, "0 short");
+    }
+
+    #[test]
+    fn test_parse_completion_keeps_trace() {
+        let output = r#"{"type":"thread.started","thread_id":"t1"}
+{"type":"turn.started"}
+{"type":"item.completed","item":{"id":"item_0","type":"reasoning","text":"**Looking at the entry point**"}}
+{"type":"item.completed","item":{"id":"item_1","type":"command_execution","command":"bash -lc 'sed -n 1,40p src/main.rs'","aggregated_output":"fn main() {}\n","exit_code":0,"status":"completed"}}
+{"type":"item.completed","item":{"id":"item_2","type":"agent_message","text":"Checking the config next"}}
+{"type":"item.completed","item":{"id":"item_3","type":"file_change","changes":[{"path":"src/config.rs","kind":"update"}],"status":"completed"}}
+{"type":"item.completed","item":{"id":"item_4","type":"agent_message","text":"main is empty"}}
+{"type":"turn.completed","usage":{"input_tokens":300,"cached_input_tokens":0,"output_tokens":20}}"#;
+        let backend = CodexBackend::new("codex", &BackendConfig::default()).unwrap();
+        let completion = backend.parse_completion(output);
+
+        assert_eq!(completion.text, "main is empty");
+        assert_eq!(completion.usage, Some(Usage::new(300, 20)));
+        let trace = completion.trace.unwrap();
+        assert_eq!(trace.events.len(), 6);
+        assert_eq!(
+            trace.events[1],
+            TraceEvent::Command {
+                command: "bash -lc 'sed -n 1,40p src/main.rs'".to_string(),
+                exit_code: Some(0),
+                output: "fn main() {}\n".to_string(),
+            }
+        );
+        assert_eq!(
+            trace.events[3],
+            TraceEvent::FileChange {
+                path: "src/config.rs".to_string(),
+                kind: "update".to_string(),
+            }
+        );
+
+        // Output that isn't an event stream comes back as is, without a trace
+        let completion = backend.parse_completion("plain answer");
+        assert_eq!(completion.text, "plain answer");
+        assert!(completion.trace.is_none());
+    }
 }
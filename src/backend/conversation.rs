@@ -0,0 +1,111 @@
+//! Multi-turn conversations with a single backend
+
+use super::{Backend, Completion, Message, QueryRequest};
+use anyhow::Result;
+use std::path::Path;
+
+/// Turns exchanged with one backend so far, plus its session handle.
+///
+/// Each request carries the earlier turns: message-array APIs (Claude API,
+/// Ollama, OpenAI-compatible, Bedrock) send them natively, backends with their
+/// own sessions (Claude CLI) resume the session and send only the new turn, and
+/// other CLIs get a transcript in the prompt.
+#[derive(Debug, Clone, Default)]
+pub struct Conversation {
+    /// System prompt and sampling options used for every turn
+    options: QueryRequest,
+    history: Vec<Message>,
+    session: Option<String>,
+}
+
+impl Conversation {
+    /// Use the system prompt and sampling options of `options` for every turn
+    pub fn with_options(options: QueryRequest) -> Self {
+        Self {
+            options: QueryRequest {
+                prompt: String::new(),
+                history: Vec::new(),
+                session: None,
+                ..options
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Request for the next user turn
+    pub fn request(&self, prompt: impl Into<String>) -> QueryRequest {
+        QueryRequest {
+            history: self.history.clone(),
+            session: self.session.clone(),
+            ..self.options.with_prompt(prompt)
+        }
+    }
+
+    /// Add a completed exchange and remember the backend's session, if it returned one
+    pub fn record(&mut self, prompt: impl Into<String>, completion: &Completion) {
+        self.history.push(Message::user(prompt));
+        self.history
+            .push(Message::assistant(completion.text.clone()));
+        if completion.session.is_some() {
+            self.session = completion.session.clone();
+        }
+    }
+
+    /// Send the next turn and record the answer
+    pub async fn send(
+        &mut self,
+        backend: &dyn Backend,
+        prompt: &str,
+        cwd: &Path,
+    ) -> Result<Completion> {
+        let completion = backend.complete(&self.request(prompt), cwd).await?;
+        self.record(prompt, &completion);
+        Ok(completion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Role;
+    use super::*;
+
+    #[test]
+    fn test_request_carries_history_and_session() {
+        let mut conversation = Conversation::default();
+        assert!(conversation.request("first").history.is_empty());
+
+        conversation.record(
+            "first",
+            &Completion {
+                text: "one".to_string(),
+                session: Some("abc".to_string()),
+                ..Default::default()
+            },
+        );
+        // A later answer without a session keeps the old handle
+        conversation.record("second", &Completion::from("two".to_string()));
+
+        let request = conversation.request("third");
+        assert_eq!(request.prompt, "third");
+        assert_eq!(request.session.as_deref(), Some("abc"));
+        assert_eq!(request.history.len(), 4);
+        assert_eq!(request.history[1].role, Role::Assistant);
+        assert_eq!(request.history[3].content, "two");
+    }
+
+    #[test]
+    fn test_with_options_applies_to_every_turn() {
+        let options = QueryRequest {
+            system: Some("Be terse.".to_string()),
+            max_tokens: Some(50),
+            ..QueryRequest::new("ignored")
+        };
+        let mut conversation = Conversation::with_options(options);
+        conversation.record("hi", &Completion::from("hello".to_string()));
+
+        let request = conversation.request("again");
+        assert_eq!(request.system.as_deref(), Some("Be terse."));
+        assert_eq!(request.max_tokens, Some(50));
+        assert_eq!(request.history.len(), 2);
+    }
+}
@@ -0,0 +1,315 @@
+//! Exec backend - wraps any CLI that takes a prompt and prints an answer
+//!
+//! The prompt goes into any argument containing `{prompt}`, or on stdin when no
+//! argument has the placeholder. `prompt_via` and long prompts move it to stdin
+//! like for the other CLIs, leaving out the arguments with the placeholder. The
+//! answer is pulled out of stdout with `skip_lines`, then `output_regex` or
+//! `output_json_pointer` if configured.
+
+use super::health::{self, Health};
+use super::prompt_via::{PromptInput, PromptVia};
+use super::{Completion, QueryRequest};
+use crate::config::BackendConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+pub struct ExecBackend {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    skip_lines: usize,
+    output_regex: Option<Regex>,
+    output_json_pointer: Option<String>,
+    min_version: Option<String>,
+    prompt_via: Option<PromptVia>,
+}
+
+impl ExecBackend {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
+        let command = config
+            .command
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Exec backend '{}' requires 'command'", name))?;
+
+        let output_regex = config
+            .output_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .with_context(|| format!("Invalid output_regex for backend '{}'", name))?;
+
+        if let Some(ref pointer) = config.output_json_pointer {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                anyhow::bail!(
+                    "Invalid output_json_pointer for backend '{}': must start with '/' (got '{}')",
+                    name,
+                    pointer
+                );
+            }
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            command,
+            args: config.args.clone(),
+            skip_lines: config.skip_lines,
+            output_regex,
+            output_json_pointer: config.output_json_pointer.clone(),
+            min_version: config.min_version.clone(),
+            prompt_via: config.prompt_via,
+        })
+    }
+
+    /// Whether the prompt is passed as an argument (vs. stdin)
+    fn prompt_in_args(&self) -> bool {
+        self.args.iter().any(|a| a.contains(PROMPT_PLACEHOLDER))
+    }
+
+    /// The arguments with `prompt` in place of `{prompt}`, or without the
+    /// arguments that have it when the prompt goes on stdin
+    fn render_args(&self, prompt: Option<&str>) -> Vec<String> {
+        match prompt {
+            Some(prompt) => self
+                .args
+                .iter()
+                .map(|a| a.replace(PROMPT_PLACEHOLDER, prompt))
+                .collect(),
+            None => self
+                .args
+                .iter()
+                .filter(|a| !a.contains(PROMPT_PLACEHOLDER))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn parse_output(&self, output: &str) -> Result<String> {
+        let output = output
+            .lines()
+            .skip(self.skip_lines)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if let Some(ref pointer) = self.output_json_pointer {
+            return extract_json_pointer(&output, pointer).ok_or_else(|| {
+                anyhow::anyhow!("{} output has no JSON value at '{}'", self.name, pointer)
+            });
+        }
+
+        if let Some(ref re) = self.output_regex {
+            let caps = re.captures(&output).ok_or_else(|| {
+                anyhow::anyhow!("{} output did not match output_regex", self.name)
+            })?;
+            // Prefer the first capture group, fall back to the whole match
+            let m = caps
+                .get(1)
+                .or_else(|| caps.get(0))
+                .map(|m| m.as_str())
+                .unwrap_or_default();
+            return Ok(m.trim().to_string());
+        }
+
+        Ok(output.trim().to_string())
+    }
+}
+
+/// Look up a JSON pointer in the whole output, or in the last JSONL line that has it
+fn extract_json_pointer(output: &str, pointer: &str) -> Option<String> {
+    let render = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
+        return json.pointer(pointer).map(render);
+    }
+
+    output.lines().rev().find_map(|line| {
+        serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|json| json.pointer(pointer).map(render))
+    })
+}
+
+#[async_trait]
+impl super::Backend for ExecBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        // No separate system prompt on the command line
+        let prompt = request.flattened_prompt();
+        let via = match PromptVia::choose(self.prompt_via, &prompt) {
+            // Without the placeholder there's no argument to put it in
+            PromptVia::Arg if !self.prompt_in_args() => PromptVia::Stdin,
+            via => via,
+        };
+        let input = PromptInput::new(Some(via), prompt)?;
+
+        let mut cmd = Command::new(&self.command);
+        cmd.args(self.render_args(input.arg()))
+            .current_dir(cwd)
+            .kill_on_drop(true)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // Written while stdout is read, so a CLI answering as it reads can't block
+        let output = input
+            .output(&mut cmd)
+            .await
+            .with_context(|| format!("Failed to execute {} command", self.name))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("{} failed: {}", self.name, stderr);
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        self.parse_output(&stdout).map(Completion::from)
+    }
+
+    fn is_available(&self) -> bool {
+        which::which(&self.command).is_ok()
+    }
+
+    async fn health(&self) -> Health {
+        let install_hint = format!("Install {} or fix `command` in lok.toml", self.command);
+        // Arbitrary CLIs may not understand --version, so only run it when asked to check
+        match self.min_version {
+            Some(ref min) => health::probe_cli(&self.command, &[], Some(min), &install_hint).await,
+            None if self.is_available() => Health::ready(),
+            None => Health::unavailable(format!("{} not found in PATH", self.command))
+                .hint(install_hint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::prompt_via::ARG_LIMIT;
+    use super::super::Backend;
+    use super::*;
+
+    fn exec_config(command: &str, args: &[&str]) -> BackendConfig {
+        BackendConfig {
+            kind: Some("exec".to_string()),
+            command: Some(command.to_string()),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompt_on_stdin() {
+        let backend = ExecBackend::new("echo-stdin", &exec_config("cat", &[])).unwrap();
+        let output = backend.query("hello stdin", Path::new(".")).await.unwrap();
+        assert_eq!(output, "hello stdin");
+    }
+
+    #[tokio::test]
+    async fn test_large_prompt_on_stdin() {
+        // Far bigger than a pipe buffer, echoed back while it is still being written
+        let prompt = "x".repeat(1024 * 1024);
+        let backend = ExecBackend::new("echo-stdin", &exec_config("cat", &[])).unwrap();
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            backend.query(&prompt, Path::new(".")),
+        )
+        .await
+        .expect("cat should not block on a large prompt")
+        .unwrap();
+        assert_eq!(output.len(), prompt.len());
+    }
+
+    #[tokio::test]
+    async fn test_prompt_placeholder() {
+        let backend =
+            ExecBackend::new("echo-arg", &exec_config("echo", &["answer:", "{prompt}"])).unwrap();
+        let output = backend.query("it's 42", Path::new(".")).await.unwrap();
+        assert_eq!(output, "answer: it's 42");
+    }
+
+    #[tokio::test]
+    async fn test_prompt_via_stdin_drops_placeholder() {
+        // `cat {prompt}` would look for a file named after the prompt
+        let mut config = exec_config("cat", &["{prompt}"]);
+        config.prompt_via = Some(PromptVia::Stdin);
+        let backend = ExecBackend::new("cat", &config).unwrap();
+        let output = backend.query("hello stdin", Path::new(".")).await.unwrap();
+        assert_eq!(output, "hello stdin");
+
+        // Unset, a prompt too long for an argument goes on stdin too
+        let prompt = "x".repeat(ARG_LIMIT + 1);
+        let backend = ExecBackend::new("cat", &exec_config("cat", &["{prompt}"])).unwrap();
+        let output = backend.query(&prompt, Path::new(".")).await.unwrap();
+        assert_eq!(output, prompt);
+    }
+
+    #[tokio::test]
+    async fn test_skip_lines_and_regex() {
+        let mut config = exec_config("sh", &["-c", "printf 'banner\\nresult: {prompt}\\n'"]);
+        config.skip_lines = 1;
+        config.output_regex = Some(r"result: (\w+)".to_string());
+        let backend = ExecBackend::new("regex", &config).unwrap();
+        let output = backend.query("yes", Path::new(".")).await.unwrap();
+        assert_eq!(output, "yes");
+    }
+
+    #[tokio::test]
+    async fn test_json_pointer_jsonl() {
+        let mut config = exec_config(
+            "sh",
+            &[
+                "-c",
+                r#"echo '{"type":"start"}'; echo '{"type":"done","result":{"text":"final"}}'"#,
+            ],
+        );
+        config.output_json_pointer = Some("/result/text".to_string());
+        let backend = ExecBackend::new("json", &config).unwrap();
+        let output = backend.query("ignored", Path::new(".")).await.unwrap();
+        assert_eq!(output, "final");
+    }
+
+    #[tokio::test]
+    async fn test_regex_no_match_is_error() {
+        let mut config = exec_config("echo", &["{prompt}"]);
+        config.output_regex = Some(r"^ANSWER=(.*)$".to_string());
+        let backend = ExecBackend::new("strict", &config).unwrap();
+        let err = backend.query("nope", Path::new(".")).await.unwrap_err();
+        assert!(err.to_string().contains("did not match"));
+    }
+
+    #[tokio::test]
+    async fn test_nonzero_exit_is_error() {
+        let backend = ExecBackend::new(
+            "fails",
+            &exec_config("sh", &["-c", "echo boom >&2; exit 3"]),
+        )
+        .unwrap();
+        let err = backend.query("x", Path::new(".")).await.unwrap_err();
+        assert!(err.to_string().contains("boom"));
+    }
+
+    #[test]
+    fn test_requires_command() {
+        let config = BackendConfig {
+            kind: Some("exec".to_string()),
+            ..Default::default()
+        };
+        assert!(ExecBackend::new("missing", &config).is_err());
+    }
+
+    #[test]
+    fn test_invalid_json_pointer() {
+        let mut config = exec_config("cat", &[]);
+        config.output_json_pointer = Some("result.text".to_string());
+        assert!(ExecBackend::new("bad", &config).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Fallback chains - re-issue a failed query to the next backend in `fallback`
+//!
+//! Only errors that another backend could plausibly avoid (rate limits,
+//! capacity, auth, network, timeouts, missing CLI, an open circuit) move down
+//! the chain. Anything else is
+//! likely a problem with the request itself and is returned as-is.
+//!
+//! Each backend in the chain times out on its own `timeout` (see
+//! [`TimeLimitedBackend`](super::limit::TimeLimitedBackend)), so a primary that hangs leaves the fallbacks their
+//! full time. Queries through a chain are limited to the sum.
+
+use super::circuit::error_kind;
+use super::stream::ChunkSender;
+use super::{Backend, Completion, Health, QueryRequest};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Records that a query was answered by a fallback backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fallback {
+    /// Backend that actually answered
+    pub backend: String,
+    /// Why the earlier backends were skipped, e.g. "claude: rate limited"
+    pub reason: String,
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "answered by {} ({})", self.backend, self.reason)
+    }
+}
+
+/// A backend followed by the backends to try when it fails
+pub struct FallbackBackend {
+    primary: Arc<dyn Backend>,
+    chain: Vec<Arc<dyn Backend>>,
+}
+
+impl FallbackBackend {
+    pub fn new(primary: Arc<dyn Backend>, chain: Vec<Arc<dyn Backend>>) -> Self {
+        Self { primary, chain }
+    }
+
+    async fn run(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: Option<&ChunkSender>,
+    ) -> Result<Completion> {
+        // Session handles only mean something to the backend that issued them
+        let fallback_request = QueryRequest {
+            session: None,
+            ..request.clone()
+        };
+
+        let mut reasons: Vec<String> = Vec::new();
+        let mut last_error = None;
+
+        for (i, backend) in std::iter::once(&self.primary)
+            .chain(&self.chain)
+            .enumerate()
+        {
+            let request = if i == 0 { request } else { &fallback_request };
+            // Fallbacks without native tool use get the tools in their prompt
+            let result = match chunks {
+                Some(chunks) => backend.query_stream(request, cwd, chunks.clone()).await,
+                None => super::tools::complete_with_tools(backend.as_ref(), request, cwd).await,
+            };
+
+            match result {
+                Ok(mut completion) => {
+                    if i > 0 {
+                        completion.session = None;
+                        completion.fallback = Some(Fallback {
+                            backend: backend.name().to_string(),
+                            reason: reasons.join("; "),
+                        });
+                    }
+                    return Ok(completion);
+                }
+                Err(e) => {
+                    let kind = error_kind(&e);
+                    if !kind.should_fall_back() {
+                        return Err(e);
+                    }
+                    reasons.push(format!("{}: {}", backend.name(), kind.description()));
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let error = last_error.expect("fallback chain always has a primary backend");
+        Err(error.context(format!("all fallbacks failed ({})", reasons.join("; "))))
+    }
+}
+
+#[async_trait]
+impl Backend for FallbackBackend {
+    fn name(&self) -> &str {
+        self.primary.name()
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        self.run(request, cwd, None).await
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        self.run(request, cwd, Some(&chunks)).await
+    }
+
+    fn is_available(&self) -> bool {
+        self.primary.is_available() || self.chain.iter().any(|b| b.is_available())
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.primary.supports_tools()
+    }
+
+    async fn health(&self) -> Health {
+        self.primary.health().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::limit::TimeLimitedBackend;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Backend that always fails with `error`, or answers with its name,
+    /// after `delay`
+    struct Stub {
+        name: &'static str,
+        error: Option<&'static str>,
+        calls: AtomicUsize,
+        delay: Duration,
+    }
+
+    impl Stub {
+        fn new(name: &'static str, error: Option<&'static str>) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                error,
+                calls: AtomicUsize::new(0),
+                delay: Duration::ZERO,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Backend for Stub {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn complete(&self, request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            match self.error {
+                Some(error) => anyhow::bail!("{}", error),
+                None => Ok(Completion {
+                    text: format!("{} answered", self.name),
+                    session: request.session.clone(),
+                    ..Default::default()
+                }),
+            }
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_on_rate_limit() {
+        let claude = Stub::new("claude", Some("Claude API error 429: rate limit"));
+        let gemini = Stub::new("gemini", Some("sh: npx: command not found"));
+        let ollama = Stub::new("ollama", None);
+        let backend = FallbackBackend::new(claude, vec![gemini, ollama]);
+
+        let request = QueryRequest {
+            session: Some("claude-session".to_string()),
+            ..QueryRequest::new("hi")
+        };
+        let completion = backend.complete(&request, Path::new(".")).await.unwrap();
+        assert_eq!(completion.text, "ollama answered");
+        assert!(completion.session.is_none());
+        assert_eq!(
+            completion.fallback,
+            Some(Fallback {
+                backend: "ollama".to_string(),
+                reason: "claude: rate limited; gemini: not installed".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_error_does_not_fall_back() {
+        let claude = Stub::new("claude", Some("prompt is malformed"));
+        let ollama = Stub::new("ollama", None);
+        let backend = FallbackBackend::new(claude, vec![ollama.clone()]);
+
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        assert!(err.to_string().contains("malformed"));
+        assert_eq!(ollama.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_primary_success_has_no_fallback() {
+        let backend = FallbackBackend::new(Stub::new("claude", None), vec![]);
+        let completion = backend
+            .complete(&QueryRequest::new("hi"), Path::new("."))
+            .await
+            .unwrap();
+        assert!(completion.fallback.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_slow_primary_times_out_to_fallback() {
+        let claude = Arc::new(Stub {
+            name: "claude",
+            error: None,
+            calls: AtomicUsize::new(0),
+            delay: Duration::from_secs(60),
+        });
+        let claude = Arc::new(TimeLimitedBackend::new(claude, Duration::from_millis(50)));
+        let ollama = Stub::new("ollama", None);
+        let backend = FallbackBackend::new(claude, vec![ollama]);
+
+        let completion = backend
+            .complete(&QueryRequest::new("hi"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "ollama answered");
+        assert_eq!(completion.fallback.unwrap().reason, "claude: timed out");
+    }
+
+    #[tokio::test]
+    async fn test_all_fallbacks_failed() {
+        let claude = Stub::new("claude", Some("overloaded"));
+        let ollama = Stub::new("ollama", Some("connection refused"));
+        let backend = FallbackBackend::new(claude, vec![ollama]);
+
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("all fallbacks failed"), "got: {}", msg);
+        assert!(msg.contains("ollama: network error"), "got: {}", msg);
+    }
+}
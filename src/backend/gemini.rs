@@ -1,18 +1,140 @@
+use super::health::{self, Health};
+use super::permissions::{CliPermissions, Permissions};
+use super::prompt_via::{PromptInput, PromptVia};
+use super::retry::HttpError;
+use super::{Completion, QueryRequest};
 use crate::config::BackendConfig;
+use crate::usage::Usage;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::Stdio;
+use std::sync::{Arc, LazyLock, Mutex};
 use tokio::process::Command;
+use tokio::sync::OnceCell;
+
+/// First release with `--output-format json` and `--approval-mode`. Older CLIs
+/// print plain text after a banner, which `skip_lines` strips, and reject the
+/// flag, so they only run read-only.
+const CURRENT_VERSION: &str = "0.6.0";
+
+/// Whether each CLI (command and args) is at least `CURRENT_VERSION`, checked
+/// once per process since workflows create a backend instance per step
+static CURRENT_CLI: LazyLock<Mutex<HashMap<String, Arc<OnceCell<bool>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
 
 pub struct GeminiBackend {
+    name: String,
     command: String,
     args: Vec<String>,
     skip_lines: usize,
+    permissions: CliPermissions,
+    prompt_via: Option<PromptVia>,
+    min_version: Option<String>,
+}
+
+/// `--output-format json` output: the answer, or the error that replaced it
+#[derive(Debug, Deserialize)]
+struct JsonOutput {
+    #[serde(default)]
+    response: Option<String>,
+    #[serde(default)]
+    stats: Option<JsonStats>,
+    #[serde(default)]
+    error: Option<JsonError>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct JsonStats {
+    #[serde(default)]
+    models: HashMap<String, ModelStats>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ModelStats {
+    #[serde(default)]
+    tokens: TokenStats,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TokenStats {
+    #[serde(default)]
+    prompt: u64,
+    #[serde(default)]
+    candidates: u64,
+    #[serde(default)]
+    thoughts: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonError {
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(default)]
+    message: String,
+    /// HTTP status for API errors, e.g. 429
+    #[serde(default)]
+    code: Option<serde_json::Value>,
+}
+
+impl JsonStats {
+    /// Tokens summed over every model the CLI called; thinking counts as output
+    fn usage(&self) -> Option<Usage> {
+        if self.models.is_empty() {
+            return None;
+        }
+        let mut total = Usage::default();
+        for model in self.models.values() {
+            total += Usage::new(
+                model.tokens.prompt,
+                model.tokens.candidates + model.tokens.thoughts,
+            );
+        }
+        Some(total)
+    }
+}
+
+impl JsonError {
+    fn into_error(self) -> anyhow::Error {
+        let message = match self.kind {
+            Some(kind) if !kind.is_empty() => format!("{}: {}", kind, self.message),
+            _ => self.message,
+        };
+        let status = self.code.as_ref().and_then(|code| match code {
+            serde_json::Value::Number(n) => n.as_u64(),
+            serde_json::Value::String(s) => s.parse().ok(),
+            _ => None,
+        });
+        match status {
+            Some(status @ 400..=599) => HttpError {
+                provider: "Gemini".to_string(),
+                status: status as u16,
+                body: message,
+                retry_after: None,
+            }
+            .into(),
+            _ => anyhow::anyhow!("Gemini failed: {}", message),
+        }
+    }
+}
+
+/// The JSON document in `output`, skipping anything printed before it
+fn find_json(output: &str) -> Option<JsonOutput> {
+    let trimmed = output.trim();
+    if let Ok(json) = serde_json::from_str(trimmed) {
+        return Some(json);
+    }
+    let start = trimmed
+        .match_indices('\n')
+        .map(|(i, _)| i + 1)
+        .find(|&i| trimmed[i..].starts_with('{'))?;
+    serde_json::from_str(&trimmed[start..]).ok()
 }
 
 impl GeminiBackend {
-    pub fn new(config: &BackendConfig) -> Result<Self> {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
         let command = config.command.clone().unwrap_or_else(|| "npx".to_string());
 
         let args = if config.args.is_empty() {
@@ -21,13 +143,61 @@ impl GeminiBackend {
             config.args.clone()
         };
 
+        let permissions = CliPermissions::new("gemini", config, &args)?;
+
         Ok(Self {
+            name: name.to_string(),
             command,
             args,
             skip_lines: config.skip_lines,
+            permissions,
+            prompt_via: config.prompt_via,
+            min_version: config.min_version.clone(),
         })
     }
 
+    /// Whether to ask for JSON output: set by `--output-format` in args,
+    /// otherwise decided by the CLI's version
+    async fn json_output(&self) -> bool {
+        if let Some(i) = self.args.iter().position(|a| a == "--output-format") {
+            return self.args.get(i + 1).is_some_and(|f| f == "json");
+        }
+        self.is_current().await
+    }
+
+    /// Whether the CLI is at least `CURRENT_VERSION`
+    async fn is_current(&self) -> bool {
+        let key = format!("{} {}", self.command, self.args.join(" "));
+        let cell = {
+            let mut cells = CURRENT_CLI.lock().unwrap_or_else(|e| e.into_inner());
+            Arc::clone(cells.entry(key).or_default())
+        };
+        *cell
+            .get_or_init(|| async {
+                health::cli_version(&self.command, &self.args)
+                    .await
+                    .is_some_and(|version| health::at_least(&version, CURRENT_VERSION))
+            })
+            .await
+    }
+
+    /// `--approval-mode` for a request, left out on CLIs too old to know it
+    async fn permission_args(&self, requested: Option<Permissions>) -> Result<Vec<String>> {
+        let args = self.permissions.args(requested)?;
+        if args.is_empty() || self.is_current().await {
+            return Ok(args);
+        }
+        // Without the flag the CLI can't approve tools unattended: read-only
+        match self.permissions.level(requested) {
+            Permissions::ReadOnly => Ok(Vec::new()),
+            level => anyhow::bail!(
+                "permissions = \"{}\" needs gemini CLI {} or newer for --approval-mode",
+                level,
+                CURRENT_VERSION
+            ),
+        }
+    }
+
     fn parse_output(&self, output: &str) -> String {
         output
             .lines()
@@ -35,24 +205,57 @@ impl GeminiBackend {
             .collect::<Vec<_>>()
             .join("\n")
     }
+
+    /// Answer, usage and errors from JSON output. Errors may be on either stream.
+    fn parse_json(&self, stdout: &str, stderr: &str, success: bool) -> Result<Completion> {
+        let json = find_json(stdout).or_else(|| find_json(stderr));
+        match json {
+            Some(JsonOutput {
+                error: Some(error), ..
+            }) => Err(error.into_error()),
+            Some(json) if success => Ok(Completion {
+                text: json.response.unwrap_or_default(),
+                usage: json.stats.and_then(|s| s.usage()),
+                ..Default::default()
+            }),
+            _ if success => anyhow::bail!(
+                "Gemini returned no JSON output: {}",
+                stdout.lines().next().unwrap_or_default()
+            ),
+            _ => anyhow::bail!("Gemini failed: {}", stderr),
+        }
+    }
 }
 
 #[async_trait]
 impl super::Backend for GeminiBackend {
     fn name(&self) -> &str {
-        "gemini"
-    }
-
-    async fn query(&self, prompt: &str, cwd: &Path) -> Result<String> {
-        // Gemini CLI requires stdin to be a pipe (not null/tty), so we use shell
-        // to pipe empty input: echo '' | npx @google/gemini-cli 'prompt'
-        let escaped_prompt = prompt.replace("'", "'\\''");
-        let shell_cmd = format!(
-            "echo '' | {} {} '{}'",
-            self.command,
-            self.args.join(" "),
-            escaped_prompt
-        );
+        &self.name
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let json_output = self.json_output().await;
+        let mut args = self.args.join(" ");
+        if json_output && !self.args.iter().any(|a| a == "--output-format") {
+            args.push_str(" --output-format json");
+        }
+        for arg in self.permission_args(request.permissions).await? {
+            args.push(' ');
+            args.push_str(&arg);
+        }
+
+        // No separate system prompt on the command line
+        let input = PromptInput::new(self.prompt_via, request.flattened_prompt())?;
+        let shell_cmd = match input.arg() {
+            // Gemini CLI requires stdin to be a pipe (not null/tty), so we use shell
+            // to pipe empty input: echo '' | npx @google/gemini-cli 'prompt'
+            Some(prompt) => {
+                let escaped_prompt = prompt.replace("'", "'\\''");
+                format!("echo '' | {} {} '{}'", self.command, args, escaped_prompt)
+            }
+            // Without a prompt argument it answers what's on stdin
+            None => format!("{} {}", self.command, args),
+        };
 
         let mut cmd = Command::new("sh");
         cmd.arg("-c")
@@ -62,22 +265,134 @@ impl super::Backend for GeminiBackend {
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
-        let output = cmd
-            .output()
+        let output = input
+            .output(&mut cmd)
             .await
             .context("Failed to execute gemini command")?;
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         let stderr = String::from_utf8_lossy(&output.stderr);
 
+        if json_output {
+            return self.parse_json(&stdout, &stderr, output.status.success());
+        }
+
         if !output.status.success() {
             anyhow::bail!("Gemini failed: {}", stderr);
         }
 
-        Ok(self.parse_output(&stdout))
+        Ok(self.parse_output(&stdout).into())
     }
 
     fn is_available(&self) -> bool {
         which::which(&self.command).is_ok()
     }
+
+    async fn health(&self) -> Health {
+        let install_hint = if self.command == "npx" {
+            "Install Node.js (npx comes with npm)"
+        } else {
+            "npm install -g @google/gemini-cli"
+        };
+        // Runs `npx @google/gemini-cli --version` with the default config
+        health::probe_cli(
+            &self.command,
+            &self.args,
+            self.min_version.as_deref(),
+            install_hint,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Backend;
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A fake gemini CLI that reports `version` and prints `output`, and like
+    /// the real ones rejects `--approval-mode` before `CURRENT_VERSION`
+    fn fake_cli(dir: &Path, version: &str, output: &str) -> BackendConfig {
+        let path = dir.join(format!("gemini-{}", version));
+        let reject = if health::at_least(version, CURRENT_VERSION) {
+            ""
+        } else {
+            "*\" --approval-mode \"*) echo 'Unknown argument: approval-mode' >&2; exit 1;; "
+        };
+        let script = format!(
+            "#!/bin/sh\ncase \" $* \" in *\" --version \"*) echo {}; exit 0;; {}esac\ncat <<'EOF'\n{}\nEOF\n",
+            version, reject, output
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        BackendConfig {
+            command: Some(path.to_string_lossy().into_owned()),
+            skip_lines: 1,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_output_on_new_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = r#"Loaded cached credentials.
+{"response":"4","stats":{"models":{"gemini-2.5-pro":{"tokens":{"prompt":120,"candidates":1,"thoughts":30}}}}}"#;
+        let config = fake_cli(dir.path(), "0.8.2", output);
+        let backend = GeminiBackend::new("gemini", &config).unwrap();
+
+        let completion = backend
+            .complete(&QueryRequest::new("2+2?"), dir.path())
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "4");
+        assert_eq!(completion.usage, Some(Usage::new(120, 31)));
+    }
+
+    #[tokio::test]
+    async fn test_skip_lines_on_old_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = fake_cli(dir.path(), "0.5.4", "Gemini CLI banner\n4");
+        let backend = GeminiBackend::new("gemini", &config).unwrap();
+
+        let completion = backend
+            .complete(&QueryRequest::new("2+2?"), dir.path())
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "4");
+        assert!(completion.usage.is_none());
+
+        // Old CLIs can't be given more than read-only
+        let request = QueryRequest {
+            permissions: Some(Permissions::WorkspaceWrite),
+            ..QueryRequest::new("2+2?")
+        };
+        let err = backend.complete(&request, dir.path()).await.unwrap_err();
+        assert!(
+            err.to_string().contains("needs gemini CLI 0.6.0 or newer"),
+            "got: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_json_error_keeps_status() {
+        let backend = GeminiBackend::new("gemini", &BackendConfig::default()).unwrap();
+        let stderr = r#"{"error":{"type":"ApiError","message":"Quota exceeded","code":429}}"#;
+
+        let error = backend.parse_json("", stderr, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Gemini error 429: ApiError: Quota exceeded"
+        );
+        assert!(error.downcast_ref::<HttpError>().is_some());
+
+        let stdout =
+            r#"{"error":{"type":"FatalInputError","message":"No input provided","code":1}}"#;
+        let error = backend.parse_json(stdout, "", false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Gemini failed: FatalInputError: No input provided"
+        );
+    }
 }
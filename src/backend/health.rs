@@ -0,0 +1,247 @@
+//! Health probes used by `lok doctor`
+//!
+//! Unlike `Backend::is_available`, which only checks that a binary exists, a
+//! probe actually talks to the backend: CLIs report their version, servers are
+//! asked for their model list, and hosted APIs get a cheap authenticated call.
+
+use super::Backend;
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Upper bound for one probe; `npx` may need to fetch a package on first run
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Regex for the first dotted version number in `--version` output
+static VERSION_RE: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"\d+\.\d+(?:\.\d+)?").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    Ready,
+    /// Usable, but something needs attention (e.g. an old CLI version)
+    Warning,
+    Unavailable,
+}
+
+/// Result of probing one backend
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Version reported by the CLI or server
+    pub version: Option<String>,
+    /// What the probe found, e.g. "model llama3.2 not pulled"
+    pub detail: Option<String>,
+    /// Concrete step that fixes the problem
+    pub hint: Option<String>,
+    /// Round-trip time of the probe
+    pub latency: Option<Duration>,
+}
+
+impl Health {
+    fn with_status(status: HealthStatus, detail: Option<String>) -> Self {
+        Self {
+            status,
+            version: None,
+            detail,
+            hint: None,
+            latency: None,
+        }
+    }
+
+    pub fn ready() -> Self {
+        Self::with_status(HealthStatus::Ready, None)
+    }
+
+    pub fn warning(detail: impl Into<String>) -> Self {
+        Self::with_status(HealthStatus::Warning, Some(detail.into()))
+    }
+
+    pub fn unavailable(detail: impl Into<String>) -> Self {
+        Self::with_status(HealthStatus::Unavailable, Some(detail.into()))
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Extra information, e.g. the model's context length on a ready backend
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.status == HealthStatus::Ready
+    }
+}
+
+/// Run a backend's health probe with a timeout, recording its latency
+pub async fn probe(backend: &dyn Backend) -> Health {
+    let start = Instant::now();
+    let mut health = match tokio::time::timeout(PROBE_TIMEOUT, backend.health()).await {
+        Ok(health) => health,
+        Err(_) => Health::unavailable(format!(
+            "health check timed out after {}s",
+            PROBE_TIMEOUT.as_secs()
+        ))
+        .hint("Check that the backend isn't waiting for a login or network access"),
+    };
+    health.latency = Some(start.elapsed());
+    health
+}
+
+/// Probe a CLI by running `<command> <args> --version`.
+///
+/// `install_hint` is shown when the CLI is missing or older than `min_version`.
+pub async fn probe_cli(
+    command: &str,
+    args: &[String],
+    min_version: Option<&str>,
+    install_hint: &str,
+) -> Health {
+    if which::which(command).is_err() {
+        return Health::unavailable(format!("{} not found in PATH", command)).hint(install_hint);
+    }
+
+    let output = Command::new(command)
+        .args(args)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output()
+        .await;
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            return Health::unavailable(format!("failed to run {}: {}", command, e))
+                .hint(install_hint)
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let first_line = stderr.lines().next().unwrap_or("").trim();
+        return Health::unavailable(format!("`{} --version` failed: {}", command, first_line))
+            .hint(install_hint);
+    }
+
+    // Some CLIs print their version on stderr
+    let Some(version) = find_version(&stdout).or_else(|| find_version(&stderr)) else {
+        return Health::ready();
+    };
+
+    match min_version {
+        Some(min) if !at_least(version, min) => Health::warning(format!(
+            "version {} is older than the minimum supported {}",
+            version, min
+        ))
+        .version(version)
+        .hint(format!("Upgrade: {}", install_hint)),
+        _ => Health::ready().version(version),
+    }
+}
+
+/// Health for an HTTP request that never got a response
+pub fn connect_failed(url: &str, err: &reqwest::Error) -> Health {
+    let detail = if err.is_timeout() {
+        format!("no response from {}", url)
+    } else {
+        format!("cannot connect to {}", url)
+    };
+    Health::unavailable(detail)
+}
+
+/// Version printed by `<command> <args> --version`, if it runs and prints one
+pub async fn cli_version(command: &str, args: &[String]) -> Option<String> {
+    let output = Command::new(command)
+        .args(args)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(PROBE_TIMEOUT, output).await.ok()?.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    find_version(&stdout)
+        .or_else(|| find_version(&stderr))
+        .map(str::to_string)
+}
+
+/// Whether `version` is `min` or newer
+pub fn at_least(version: &str, min: &str) -> bool {
+    version_key(version) >= version_key(min)
+}
+
+/// First dotted version number in `text`, e.g. "1.0.33" from "1.0.33 (Claude Code)"
+pub fn find_version(text: &str) -> Option<&str> {
+    VERSION_RE.find(text).map(|m| m.as_str())
+}
+
+/// Numeric components for comparison; missing parts count as zero
+fn version_key(version: &str) -> [u64; 3] {
+    let mut key = [0; 3];
+    for (slot, part) in key.iter_mut().zip(version.split('.')) {
+        *slot = part.parse().unwrap_or(0);
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_version() {
+        assert_eq!(find_version("1.0.33 (Claude Code)"), Some("1.0.33"));
+        assert_eq!(find_version("codex-cli 0.46.0\n"), Some("0.46.0"));
+        assert_eq!(find_version("v20.1"), Some("20.1"));
+        assert_eq!(find_version("unknown"), None);
+    }
+
+    #[test]
+    fn test_version_key_ordering() {
+        assert!(version_key("0.9.10") < version_key("0.10.0"));
+        assert!(version_key("1.2") < version_key("1.2.1"));
+        assert_eq!(version_key("2.0"), version_key("2.0.0"));
+        assert!(at_least("0.10.0", "0.6.0"));
+        assert!(!at_least("0.5.9", "0.6.0"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_cli_missing_binary() {
+        let health = probe_cli("lok-no-such-binary", &[], None, "install it").await;
+        assert_eq!(health.status, HealthStatus::Unavailable);
+        assert_eq!(health.hint.as_deref(), Some("install it"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_cli_version_check() {
+        // `sh -c 'echo tool 1.2.3' --version`: the extra arg becomes $0
+        let args = vec!["-c".to_string(), "echo tool 1.2.3".to_string()];
+
+        let health = probe_cli("sh", &args, Some("1.2.0"), "upgrade").await;
+        assert_eq!(health.status, HealthStatus::Ready);
+        assert_eq!(health.version.as_deref(), Some("1.2.3"));
+
+        let health = probe_cli("sh", &args, Some("1.10"), "npm i -g tool").await;
+        assert_eq!(health.status, HealthStatus::Warning);
+        assert_eq!(health.hint.as_deref(), Some("Upgrade: npm i -g tool"));
+    }
+}
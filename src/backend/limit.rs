@@ -0,0 +1,260 @@
+//! Per-backend concurrency and rate limits, shared by the whole process
+//!
+//! Workflow steps, `for_each` items, spawned agents and multi-backend queries
+//! all create their own backend instances, so the limits live in a registry
+//! keyed by backend name rather than on the instances themselves.
+//! [`TimeLimitedBackend`] bounds each query by the backend's `timeout`.
+
+use super::stream::ChunkSender;
+use super::{Backend, Completion, Health, QueryRequest};
+use crate::config::BackendConfig;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+static LIMITERS: LazyLock<Mutex<HashMap<String, Arc<Limiter>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `max_concurrent` and `requests_per_minute` for one backend
+pub struct Limiter {
+    concurrency: Option<Arc<Semaphore>>,
+    /// Minimum spacing between request starts
+    interval: Option<Duration>,
+    next_slot: Mutex<Instant>,
+}
+
+/// Held while a request is in flight; dropping it frees the concurrency slot
+pub struct Permit {
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl Limiter {
+    fn new(config: &BackendConfig) -> Option<Self> {
+        let max_concurrent = config.max_concurrent.filter(|&n| n > 0);
+        let rpm = config.requests_per_minute.filter(|&n| n > 0);
+        if max_concurrent.is_none() && rpm.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            concurrency: max_concurrent.map(|n| Arc::new(Semaphore::new(n))),
+            interval: rpm.map(|n| Duration::from_secs(60) / n),
+            next_slot: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// The process-wide limiter for `name`, or `None` if it has no limits.
+    /// The first call for a name decides its limits.
+    pub fn for_backend(name: &str, config: &BackendConfig) -> Option<Arc<Limiter>> {
+        let mut limiters = LIMITERS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(limiter) = limiters.get(name) {
+            return Some(Arc::clone(limiter));
+        }
+        let limiter = Arc::new(Self::new(config)?);
+        limiters.insert(name.to_string(), Arc::clone(&limiter));
+        Some(limiter)
+    }
+
+    /// Wait for a concurrency slot, then for the next free rate-limit slot
+    pub async fn acquire(&self) -> Permit {
+        let slot = match &self.concurrency {
+            Some(semaphore) => Some(
+                Arc::clone(semaphore)
+                    .acquire_owned()
+                    .await
+                    .expect("limiter semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        if let Some(interval) = self.interval {
+            let start = {
+                let mut next = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+                let start = (*next).max(Instant::now());
+                *next = start + interval;
+                start
+            };
+            tokio::time::sleep_until(start).await;
+        }
+
+        Permit { _slot: slot }
+    }
+}
+
+/// A backend whose queries wait for its limiter
+pub struct LimitedBackend {
+    inner: Arc<dyn Backend>,
+    limiter: Arc<Limiter>,
+}
+
+impl LimitedBackend {
+    pub fn new(inner: Arc<dyn Backend>, limiter: Arc<Limiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl Backend for LimitedBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let _permit = self.limiter.acquire().await;
+        self.inner.complete(request, cwd).await
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        let _permit = self.limiter.acquire().await;
+        self.inner.query_stream(request, cwd, chunks).await
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    async fn health(&self) -> Health {
+        self.inner.health().await
+    }
+}
+
+/// A backend whose queries fail with a timeout error after its `timeout`.
+/// It sits beneath the circuit breaker, so the breaker counts the timeout,
+/// and a fallback chain moves on to its next backend.
+pub struct TimeLimitedBackend {
+    inner: Arc<dyn Backend>,
+    limit: Duration,
+}
+
+impl TimeLimitedBackend {
+    pub fn new(inner: Arc<dyn Backend>, limit: Duration) -> Self {
+        Self { inner, limit }
+    }
+
+    async fn run<F>(&self, query: F) -> Result<Completion>
+    where
+        F: std::future::Future<Output = Result<Completion>>,
+    {
+        tokio::time::timeout(self.limit, query)
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "{} timed out after {}s",
+                    self.inner.name(),
+                    self.limit.as_secs()
+                ))
+            })
+    }
+}
+
+#[async_trait]
+impl Backend for TimeLimitedBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        self.run(self.inner.complete(request, cwd)).await
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        self.run(self.inner.query_stream(request, cwd, chunks))
+            .await
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    async fn health(&self) -> Health {
+        self.inner.health().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn limits(max_concurrent: Option<usize>, rpm: Option<u32>) -> BackendConfig {
+        BackendConfig {
+            max_concurrent,
+            requests_per_minute: rpm,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_no_limits_means_no_limiter() {
+        assert!(Limiter::for_backend("limit-test-none", &limits(None, None)).is_none());
+        assert!(Limiter::for_backend("limit-test-zero", &limits(Some(0), Some(0))).is_none());
+    }
+
+    #[test]
+    fn test_limiter_is_shared_per_name() {
+        let config = limits(Some(1), None);
+        let a = Limiter::for_backend("limit-test-shared", &config).unwrap();
+        let b = Limiter::for_backend("limit-test-shared", &config).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent() {
+        let limiter =
+            Limiter::for_backend("limit-test-concurrent", &limits(Some(2), None)).unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..6)
+            .map(|_| {
+                let (limiter, running, peak) = (limiter.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await;
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_requests_per_minute_spaces_requests() {
+        // 1200 rpm = one request every 50ms
+        let limiter = Limiter::for_backend("limit-test-rpm", &limits(None, Some(1200))).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
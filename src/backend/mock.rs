@@ -0,0 +1,330 @@
+//! Mock backend - answers from a fixtures file, for testing workflows offline
+//!
+//! Fixtures come from a TOML file with `[[fixtures]]` tables, or a JSONL file
+//! with one fixture object per line. Each fixture matches the prompt exactly
+//! (`prompt`), by SHA-256 hex digest (`prompt_hash`) or by regex
+//! (`prompt_regex`); a fixture with no matcher matches every prompt. The first
+//! matching fixture that hasn't used up its `times` answers.
+//!
+//! ```toml
+//! [[fixtures]]
+//! prompt_regex = "(?i)review"
+//! error = "HTTP 503: model overloaded"   # Fail the first call...
+//! times = 1
+//!
+//! [[fixtures]]
+//! prompt_regex = "(?i)review"
+//! response = "LGTM"                      # ...then answer
+//! latency_ms = 200
+//! ```
+//!
+//! An `error` with `retry_after_ms` fails like a rate-limited HTTP backend
+//! that asked to be retried after that long.
+
+use super::retry::HttpError;
+use super::trace::Trace;
+use super::{Completion, QueryRequest};
+use crate::config::BackendConfig;
+use crate::usage::Usage;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+/// Fixture files are loaded once per process so `times` counts carry across
+/// the many backend instances a workflow creates
+static FIXTURE_SETS: LazyLock<Mutex<HashMap<PathBuf, Arc<FixtureSet>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    /// Match the prompt exactly
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// Match the SHA-256 hex digest of the prompt
+    #[serde(default)]
+    pub prompt_hash: Option<String>,
+    /// Match the prompt against a regex
+    #[serde(default)]
+    pub prompt_regex: Option<String>,
+    /// Answer to return
+    #[serde(default)]
+    pub response: String,
+    /// Fail with this error message instead of answering
+    #[serde(default)]
+    pub error: Option<String>,
+    /// With `error`, fail like a rate-limited HTTP backend that asked to wait this long
+    #[serde(default)]
+    pub retry_after_ms: Option<u64>,
+    /// Never answer, so the caller's timeout fires
+    #[serde(default)]
+    pub timeout: bool,
+    /// Delay before answering or failing
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Token usage to report
+    #[serde(default)]
+    pub usage: Option<Usage>,
+    /// Agent trace to report, like Codex's (see `backend::trace`)
+    #[serde(default)]
+    pub trace: Option<Trace>,
+    /// Match at most this many times, then let later fixtures answer
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct FixtureFile {
+    #[serde(default)]
+    fixtures: Vec<Fixture>,
+}
+
+struct FixtureSet {
+    fixtures: Vec<(Fixture, Option<Regex>)>,
+    /// Times each fixture has matched
+    used: Mutex<Vec<u32>>,
+}
+
+impl FixtureSet {
+    fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read fixtures file: {}", path.display()))?;
+        let fixtures = parse_fixtures(path, &content)?;
+
+        let fixtures = fixtures
+            .into_iter()
+            .map(|fixture| {
+                let regex = fixture
+                    .prompt_regex
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .with_context(|| format!("Invalid prompt_regex in {}", path.display()))?;
+                Ok((fixture, regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            used: Mutex::new(vec![0; fixtures.len()]),
+            fixtures,
+        })
+    }
+
+    /// Find the fixture for `prompt`, counting the match
+    fn take(&self, prompt: &str) -> Option<&Fixture> {
+        let hash = prompt_hash(prompt);
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        for (i, (fixture, regex)) in self.fixtures.iter().enumerate() {
+            if fixture.times.is_some_and(|times| used[i] >= times) {
+                continue;
+            }
+            let matches = fixture.prompt.as_deref().map_or(true, |p| p == prompt)
+                && fixture.prompt_hash.as_deref().map_or(true, |h| h == hash)
+                && regex.as_ref().map_or(true, |r| r.is_match(prompt));
+            if matches {
+                used[i] += 1;
+                return Some(fixture);
+            }
+        }
+        None
+    }
+}
+
+fn parse_fixtures(path: &Path, content: &str) -> Result<Vec<Fixture>> {
+    let is_jsonl = path
+        .extension()
+        .is_some_and(|ext| ext == "jsonl" || ext == "json");
+    if !is_jsonl {
+        let file: FixtureFile = toml::from_str(content)
+            .with_context(|| format!("Failed to parse fixtures file: {}", path.display()))?;
+        return Ok(file.fixtures);
+    }
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid fixture at {}:{}", path.display(), n + 1))
+        })
+        .collect()
+}
+
+/// SHA-256 hex digest of a prompt, as matched by `prompt_hash`
+pub fn prompt_hash(prompt: &str) -> String {
+    format!("{:x}", Sha256::digest(prompt.as_bytes()))
+}
+
+pub struct MockBackend {
+    name: String,
+    fixtures: Arc<FixtureSet>,
+}
+
+impl MockBackend {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
+        let path = config
+            .fixtures
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Mock backend '{}' requires 'fixtures'", name))?;
+        let path = PathBuf::from(path);
+
+        let mut sets = FIXTURE_SETS.lock().unwrap_or_else(|e| e.into_inner());
+        let fixtures = match sets.get(&path) {
+            Some(set) => Arc::clone(set),
+            None => {
+                let set = Arc::new(FixtureSet::load(&path)?);
+                sets.insert(path, Arc::clone(&set));
+                set
+            }
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            fixtures,
+        })
+    }
+}
+
+#[async_trait]
+impl super::Backend for MockBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn complete(&self, request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+        // Attached files are matched as part of the prompt
+        let prompt = request.prompt_with_attachments();
+        let Some(fixture) = self.fixtures.take(&prompt) else {
+            anyhow::bail!(
+                "{}: no fixture matches prompt (prompt_hash = \"{}\")",
+                self.name,
+                prompt_hash(&prompt)
+            );
+        };
+
+        if fixture.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(fixture.latency_ms)).await;
+        }
+        if fixture.timeout {
+            std::future::pending::<()>().await;
+        }
+        if let Some(ref error) = fixture.error {
+            if let Some(ms) = fixture.retry_after_ms {
+                return Err(HttpError {
+                    provider: self.name.clone(),
+                    status: 429,
+                    body: error.clone(),
+                    retry_after: Some(Duration::from_millis(ms)),
+                }
+                .into());
+            }
+            anyhow::bail!("{}", error);
+        }
+
+        Ok(Completion {
+            text: fixture.response.clone(),
+            usage: fixture.usage,
+            trace: fixture.trace.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Backend;
+    use super::*;
+    use std::io::Write;
+
+    fn mock_from(ext: &str, content: &str) -> (MockBackend, tempfile::NamedTempFile) {
+        let mut file = tempfile::Builder::new().suffix(ext).tempfile().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        let config = BackendConfig {
+            kind: Some("mock".to_string()),
+            fixtures: Some(file.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        (MockBackend::new("mock", &config).unwrap(), file)
+    }
+
+    #[tokio::test]
+    async fn test_matching_and_times() {
+        let hash = prompt_hash("hashed");
+        let (mock, _file) = mock_from(
+            ".toml",
+            &format!(
+                r#"
+[[fixtures]]
+prompt = "exact"
+response = "by prompt"
+
+[[fixtures]]
+prompt_hash = "{hash}"
+response = "by hash"
+usage = {{ input_tokens = 10, output_tokens = 2 }}
+
+[[fixtures]]
+prompt_regex = "^retry"
+error = "HTTP 429 Too Many Requests"
+times = 1
+
+[[fixtures]]
+prompt_regex = "^retry"
+response = "recovered"
+"#
+            ),
+        );
+        let cwd = Path::new(".");
+
+        assert_eq!(mock.query("exact", cwd).await.unwrap(), "by prompt");
+        let hashed = mock
+            .complete(&QueryRequest::new("hashed"), cwd)
+            .await
+            .unwrap();
+        assert_eq!(hashed.text, "by hash");
+        assert_eq!(hashed.usage, Some(Usage::new(10, 2)));
+
+        let err = mock.query("retry please", cwd).await.unwrap_err();
+        assert!(err.to_string().contains("429"));
+        assert_eq!(mock.query("retry please", cwd).await.unwrap(), "recovered");
+
+        let err = mock.query("unmatched", cwd).await.unwrap_err();
+        assert!(err.to_string().contains(&prompt_hash("unmatched")));
+    }
+
+    #[tokio::test]
+    async fn test_jsonl_fixtures_and_timeout() {
+        let (mock, _file) = mock_from(
+            ".jsonl",
+            "{\"prompt\": \"slow\", \"timeout\": true}\n\n{\"response\": \"anything\"}\n",
+        );
+        let cwd = Path::new(".");
+
+        let slow = mock.query("slow", cwd);
+        assert!(tokio::time::timeout(Duration::from_millis(20), slow)
+            .await
+            .is_err());
+        assert_eq!(mock.query("other", cwd).await.unwrap(), "anything");
+    }
+
+    #[test]
+    fn test_missing_fixtures_setting() {
+        let config = BackendConfig {
+            kind: Some("mock".to_string()),
+            ..Default::default()
+        };
+        let err = MockBackend::new("mock", &config).err().unwrap();
+        assert!(err.to_string().contains("requires 'fixtures'"));
+    }
+}
@@ -1,15 +1,44 @@
+pub mod attachment;
 #[cfg(feature = "bedrock")]
 mod bedrock;
+pub mod budget;
+mod circuit;
 mod claude;
 mod codex;
+mod conversation;
+mod exec;
+mod fallback;
 mod gemini;
+pub mod health;
+pub mod limit;
+mod mock;
 mod ollama;
-
+mod openai;
+pub mod permissions;
+mod prompt_via;
+mod request;
+pub mod retry;
+mod stream;
+pub mod tools;
+pub mod trace;
+pub mod traffic;
+
+pub use attachment::Attachment;
 #[cfg(feature = "bedrock")]
 pub use bedrock::BedrockBackend;
-pub use claude::ClaudeBackend;
+pub use circuit::CircuitBreaker;
+pub use conversation::Conversation;
+pub use fallback::Fallback;
+pub use health::Health;
+pub use permissions::Permissions;
+pub use prompt_via::PromptVia;
+pub use request::{Message, QueryRequest, ResponseFormat, Role, Tool, ToolCall};
+pub use stream::ChunkSender;
+pub use trace::Trace;
+pub use traffic::Traffic;
 
 use crate::config::{BackendConfig, Config};
+use crate::usage::{self, Usage, UsageByBackend};
 use anyhow::Result;
 use async_trait::async_trait;
 use colored::Colorize;
@@ -22,45 +51,271 @@ use std::time::{Duration, Instant};
 #[async_trait]
 pub trait Backend: Send + Sync {
     fn name(&self) -> &str;
-    async fn query(&self, prompt: &str, cwd: &Path) -> Result<String>;
+
+    /// Run a request and return the answer along with token usage, if the backend reports it
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion>;
+
+    /// Send a bare prompt and return just the answer text
+    async fn query(&self, prompt: &str, cwd: &Path) -> Result<String> {
+        Ok(self.complete(&QueryRequest::new(prompt), cwd).await?.text)
+    }
+
+    /// Run a request, sending output chunks to `chunks` as they arrive. Returns the full output.
+    ///
+    /// Backends without native streaming send the whole answer as a single chunk.
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        let completion = self.complete(request, cwd).await?;
+        let _ = chunks.send(completion.text.clone());
+        Ok(completion)
+    }
+
     fn is_available(&self) -> bool;
+
+    /// Whether `complete` sends `QueryRequest::tools` natively and returns the
+    /// model's calls in `Completion::tool_calls`. Other backends are given the
+    /// tools in their prompt by `tools::complete_with_tools`.
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    /// Check that the backend can actually answer: CLI installed and recent
+    /// enough, server reachable, model present, credentials accepted
+    async fn health(&self) -> Health {
+        if self.is_available() {
+            Health::ready()
+        } else {
+            Health::unavailable("not available")
+        }
+    }
+}
+
+/// A backend's answer plus the metadata it reports
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub text: String,
+    pub usage: Option<Usage>,
+    /// Backend-side session that can be resumed with `QueryRequest::session`
+    pub session: Option<String>,
+    /// Set when a backend from the `fallback` chain answered instead
+    pub fallback: Option<Fallback>,
+    /// Tools the model wants called before it answers (see `QueryRequest::tools`)
+    pub tool_calls: Vec<ToolCall>,
+    /// What an agentic backend did to get the answer (see `trace`)
+    pub trace: Option<Trace>,
+}
+
+impl Completion {
+    /// Name of the backend that produced this answer
+    pub fn answered_by<'a>(&'a self, requested: &'a str) -> &'a str {
+        self.fallback
+            .as_ref()
+            .map_or(requested, |f| f.backend.as_str())
+    }
 }
 
+impl From<String> for Completion {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct QueryResult {
     pub backend: String,
     pub output: String,
     pub success: bool,
     pub elapsed_ms: u64,
+    /// Token usage, when the backend reports it
+    pub usage: Option<Usage>,
+    /// Backend session to continue the conversation, if the backend has one
+    pub session: Option<String>,
+    /// Set when a backend from the `fallback` chain answered instead of `backend`
+    pub fallback: Option<Fallback>,
+    /// Commands, file changes and reasoning reported by an agentic backend
+    pub trace: Option<Trace>,
 }
 
+impl QueryResult {
+    /// Name of the backend that actually produced the output
+    pub fn answered_by(&self) -> &str {
+        self.fallback
+            .as_ref()
+            .map_or(self.backend.as_str(), |f| f.backend.as_str())
+    }
+}
+
+/// Create a backend instance. `name` is the instance name from `[backends.<name>]`;
+/// the implementation is chosen by `kind`, falling back to the name itself.
 pub fn create_backend(name: &str, config: &BackendConfig) -> Result<Arc<dyn Backend>> {
-    match name {
-        "codex" => Ok(Arc::new(codex::CodexBackend::new(config)?)),
-        "gemini" => Ok(Arc::new(gemini::GeminiBackend::new(config)?)),
-        "claude" => Ok(Arc::new(claude::ClaudeBackend::new(config)?)),
-        "ollama" => Ok(Arc::new(ollama::OllamaBackend::new(config)?)),
+    let kind = config.kind.as_deref().unwrap_or(name);
+    match kind {
+        "codex" => Ok(Arc::new(codex::CodexBackend::new(name, config)?)),
+        "gemini" => Ok(Arc::new(gemini::GeminiBackend::new(name, config)?)),
+        "claude" => Ok(Arc::new(claude::ClaudeBackend::new(name, config)?)),
+        "ollama" => Ok(Arc::new(ollama::OllamaBackend::new(name, config)?)),
+        "openai" => Ok(Arc::new(openai::OpenAiBackend::new(name, config)?)),
+        "exec" => Ok(Arc::new(exec::ExecBackend::new(name, config)?)),
+        "mock" => Ok(Arc::new(mock::MockBackend::new(name, config)?)),
         #[cfg(feature = "bedrock")]
         "bedrock" => {
             // BedrockBackend::new is async, need runtime
             let rt = tokio::runtime::Handle::current();
             let config = config.clone();
-            rt.block_on(async { Ok(Arc::new(bedrock::BedrockBackend::new(&config).await?) as Arc<dyn Backend>) })
+            rt.block_on(async { Ok(Arc::new(bedrock::BedrockBackend::new(name, &config).await?) as Arc<dyn Backend>) })
         }
         #[cfg(not(feature = "bedrock"))]
         "bedrock" => anyhow::bail!("Bedrock backend requires the 'bedrock' feature. Rebuild with: cargo build --features bedrock"),
-        _ => anyhow::bail!("Unknown backend: {}", name),
+        _ if config.kind.is_some() => {
+            anyhow::bail!("Unknown backend kind '{}' for backend '{}'", kind, name)
+        }
+        _ => anyhow::bail!(
+            "Unknown backend: {} (set `kind` to one of: {})",
+            name,
+            BACKEND_KINDS.join(", ")
+        ),
     }
 }
 
-pub fn create_claude_backend(config: &Config) -> Result<ClaudeBackend> {
+/// Backend implementations selectable with `kind = "..."`
+pub const BACKEND_KINDS: &[&str] = &[
+    "codex", "gemini", "claude", "ollama", "openai", "exec", "mock", "bedrock",
+];
+
+/// Create a backend with everything `lok.toml` and the command line layer on
+/// top of it: its request limits, the run's circuit breaker, its `fallback`
+/// chain and `--record`/`--replay`. Use this for queries; `create_backend`
+/// builds the bare backend only.
+pub fn build_backend(
+    name: &str,
+    config: &Config,
+    breaker: Option<&CircuitBreaker>,
+) -> Result<Arc<dyn Backend>> {
     let backend_config = config
         .backends
-        .get("claude")
-        .ok_or_else(|| anyhow::anyhow!("Claude backend not configured"))?;
-    ClaudeBackend::new(backend_config)
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("Backend not found: {}", name))?;
+
+    match &config.traffic {
+        // Replays never touch the real backend, so it needn't be installed
+        Some(Traffic::Replay(replayer)) => Ok(Arc::new(traffic::ReplayBackend::new(
+            name,
+            Arc::clone(replayer),
+        ))),
+        Some(Traffic::Record(recorder)) => Ok(Arc::new(traffic::RecordingBackend::new(
+            build_live_backend(name, backend_config, config, breaker)?,
+            Arc::clone(recorder),
+        ))),
+        None => build_live_backend(name, backend_config, config, breaker),
+    }
+}
+
+fn build_live_backend(
+    name: &str,
+    backend_config: &BackendConfig,
+    config: &Config,
+    breaker: Option<&CircuitBreaker>,
+) -> Result<Arc<dyn Backend>> {
+    let backend = create_limited_backend(name, backend_config, config, breaker)?;
+    if backend_config.fallback.is_empty() {
+        return Ok(backend);
+    }
+
+    let mut chain = Vec::new();
+    for fallback in &backend_config.fallback {
+        // Fallbacks are used without their own chains, so chains never loop
+        let created = match config.backends.get(fallback) {
+            Some(cfg) if cfg.enabled => create_limited_backend(fallback, cfg, config, breaker),
+            Some(_) => Err(anyhow::anyhow!("backend is disabled")),
+            None => Err(anyhow::anyhow!("backend is not configured")),
+        };
+        match created {
+            Ok(b) => chain.push(b),
+            Err(e) => eprintln!(
+                "{} Skipping fallback {} for {}: {}",
+                "warning:".yellow(),
+                fallback,
+                name,
+                e
+            ),
+        }
+    }
+    Ok(Arc::new(fallback::FallbackBackend::new(backend, chain)))
+}
+
+/// How long one query to `name` may take: its `timeout`, or
+/// `defaults.timeout`. 0 means no limit.
+fn query_timeout(name: &str, config: &Config) -> Duration {
+    let secs = config
+        .backends
+        .get(name)
+        .and_then(|b| b.timeout)
+        .unwrap_or(config.defaults.timeout);
+    if secs == 0 {
+        Duration::from_secs(365 * 24 * 60 * 60) // 1 year = effectively no timeout
+    } else {
+        Duration::from_secs(secs)
+    }
+}
+
+/// How long a query to `name` may take, fallbacks included: each backend in
+/// its chain limits its own attempt, so the chain may take their sum
+pub fn chain_timeout(name: &str, config: &Config) -> Duration {
+    let fallbacks = config.backends.get(name).map(|b| b.fallback.as_slice());
+    fallbacks
+        .unwrap_or_default()
+        .iter()
+        .map(|fallback| query_timeout(fallback, config))
+        .fold(query_timeout(name, config), Duration::saturating_add)
+}
+
+/// A bare backend that fails after its `timeout`, behind the circuit breaker
+/// (which so counts its timeouts) and its process-wide
+/// `max_concurrent`/`requests_per_minute` limiter
+fn create_limited_backend(
+    name: &str,
+    backend_config: &BackendConfig,
+    config: &Config,
+    breaker: Option<&CircuitBreaker>,
+) -> Result<Arc<dyn Backend>> {
+    let mut backend: Arc<dyn Backend> = Arc::new(limit::TimeLimitedBackend::new(
+        create_backend(name, backend_config)?,
+        query_timeout(name, config),
+    ));
+    if let Some(breaker) = breaker {
+        backend = breaker.wrap(backend);
+    }
+    Ok(match limit::Limiter::for_backend(name, backend_config) {
+        Some(limiter) => Arc::new(limit::LimitedBackend::new(backend, limiter)),
+        None => backend,
+    })
 }
 
 pub fn get_backends(config: &Config, filter: Option<&str>) -> Result<Vec<Arc<dyn Backend>>> {
+    select_backends(config, filter, None)
+}
+
+/// Like `get_backends`, for backends that are queried repeatedly during one run
+pub fn get_backends_with_breaker(
+    config: &Config,
+    filter: Option<&str>,
+    breaker: &CircuitBreaker,
+) -> Result<Vec<Arc<dyn Backend>>> {
+    select_backends(config, filter, Some(breaker))
+}
+
+fn select_backends(
+    config: &Config,
+    filter: Option<&str>,
+    breaker: Option<&CircuitBreaker>,
+) -> Result<Vec<Arc<dyn Backend>>> {
     let mut backends = Vec::new();
 
     let filter_names: Option<Vec<&str>> = filter.map(|f| f.split(',').collect());
@@ -76,7 +331,7 @@ pub fn get_backends(config: &Config, filter: Option<&str>) -> Result<Vec<Arc<dyn
             }
         }
 
-        match create_backend(name, backend_config) {
+        match build_backend(name, config, breaker) {
             Ok(backend) => {
                 if backend.is_available() {
                     backends.push(backend);
@@ -116,14 +371,83 @@ pub async fn run_query_with_config(
     prompt: &str,
     cwd: &Path,
     config: &Config,
+) -> Result<Vec<QueryResult>> {
+    run_query_inner(backends, QueryRequest::new(prompt), cwd, config, None).await
+}
+
+/// Like `run_query_with_config`, for a request with more than a prompt (e.g. attachments)
+pub async fn run_request(
+    backends: &[Arc<dyn Backend>],
+    request: QueryRequest,
+    cwd: &Path,
+    config: &Config,
+) -> Result<Vec<QueryResult>> {
+    run_query_inner(backends, request, cwd, config, None).await
+}
+
+/// Like `run_query_with_config`, but prints each backend's output live as it arrives.
+/// With `prefix`, every line is tagged with the backend name.
+pub async fn run_query_streaming(
+    backends: &[Arc<dyn Backend>],
+    prompt: &str,
+    cwd: &Path,
+    config: &Config,
+    prefix: bool,
+) -> Result<Vec<QueryResult>> {
+    run_request_streaming(backends, QueryRequest::new(prompt), cwd, config, prefix).await
+}
+
+/// Like `run_request`, but prints each backend's output live as it arrives
+pub async fn run_request_streaming(
+    backends: &[Arc<dyn Backend>],
+    request: QueryRequest,
+    cwd: &Path,
+    config: &Config,
+    prefix: bool,
+) -> Result<Vec<QueryResult>> {
+    run_query_inner(backends, request, cwd, config, Some(prefix)).await
+}
+
+/// Run a request against a single backend, printing its output live. Returns the full output.
+pub async fn query_live(
+    backend: &dyn Backend,
+    request: &QueryRequest,
+    cwd: &Path,
+    prefix: bool,
+) -> Result<Completion> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let printer = crate::output::spawn_stream_printer(backend.name(), prefix, rx);
+    let result = backend.query_stream(request, cwd, tx).await;
+    // The sender is gone now, so the printer drains and exits
+    let _ = printer.await;
+    result
+}
+
+/// Times `run_query_with_config` retries a backend that said how long to wait
+const MAX_HINTED_RETRIES: u32 = 2;
+
+/// How long `run_query_with_config` waits past a backend's own timeout
+const TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+/// `stream` is `Some(prefix)` to print output live instead of showing a progress bar
+async fn run_query_inner(
+    backends: &[Arc<dyn Backend>],
+    request: QueryRequest,
+    cwd: &Path,
+    config: &Config,
+    stream: Option<bool>,
 ) -> Result<Vec<QueryResult>> {
     let cwd = crate::utils::canonicalize_async(cwd).await;
-    let prompt: Arc<str> = Arc::from(prompt);
+    let request = Arc::new(request);
     let cwd: Arc<Path> = Arc::from(cwd.as_path());
-    let default_timeout = config.defaults.timeout;
     let parallel = config.defaults.parallel;
 
-    let pb = ProgressBar::new(backends.len() as u64);
+    // A progress bar would garble live output
+    let pb = if stream.is_some() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(backends.len() as u64)
+    };
     pb.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")
@@ -132,63 +456,88 @@ pub async fn run_query_with_config(
     );
 
     let query_one = |backend: Arc<dyn Backend>,
-                     prompt: Arc<str>,
+                     request: Arc<QueryRequest>,
                      cwd: Arc<Path>,
                      pb: ProgressBar,
-                     timeout: u64| async move {
+                     timeout: Duration| async move {
         pb.set_message(format!("Querying {}...", backend.name()));
 
         let start = Instant::now();
-        let result =
-            tokio::time::timeout(Duration::from_secs(timeout), backend.query(&prompt, &cwd)).await;
+        let query = async {
+            let mut hinted_retries = 0;
+            loop {
+                let result = match stream {
+                    Some(prefix) => query_live(backend.as_ref(), &request, &cwd, prefix).await,
+                    None => backend.complete(&request, &cwd).await,
+                };
+                // Retry only when the provider said how long to wait
+                let wait = match result {
+                    Err(ref e) if hinted_retries < MAX_HINTED_RETRIES => retry::retry_after(e),
+                    _ => None,
+                };
+                match wait {
+                    Some(wait) => {
+                        hinted_retries += 1;
+                        pb.set_message(format!(
+                            "{} asked to wait {:.1}s...",
+                            backend.name(),
+                            wait.as_secs_f64()
+                        ));
+                        tokio::time::sleep(wait).await;
+                    }
+                    None => return result,
+                }
+            }
+        };
+        // A backstop: each attempt's own timeout fires first, so the circuit
+        // breaker counts it instead of seeing a cancelled request
+        let result = tokio::time::timeout(timeout + TIMEOUT_GRACE, query).await;
         let elapsed_ms = start.elapsed().as_millis() as u64;
 
         pb.inc(1);
 
         match result {
-            Ok(Ok(output)) => QueryResult {
+            Ok(Ok(completion)) => QueryResult {
                 backend: backend.name().to_string(),
-                output,
+                output: completion.text,
                 success: true,
                 elapsed_ms,
+                usage: completion.usage,
+                session: completion.session,
+                fallback: completion.fallback,
+                trace: completion.trace,
             },
             Ok(Err(e)) => QueryResult {
                 backend: backend.name().to_string(),
                 output: format!("Error: {}", e),
                 success: false,
                 elapsed_ms,
+                usage: None,
+                session: None,
+                fallback: None,
+                trace: None,
             },
             Err(_) => QueryResult {
                 backend: backend.name().to_string(),
-                output: format!("Error: Timeout ({}s)", timeout),
+                output: format!("Error: Timeout ({}s)", timeout.as_secs()),
                 success: false,
                 elapsed_ms,
+                usage: None,
+                session: None,
+                fallback: None,
+                trace: None,
             },
         }
     };
 
-    // Helper to get timeout for a backend (0 means no timeout)
-    let get_timeout = |backend_name: &str| -> u64 {
-        let timeout = config
-            .backends
-            .get(backend_name)
-            .and_then(|b| b.timeout)
-            .unwrap_or(default_timeout);
-        if timeout == 0 {
-            365 * 24 * 60 * 60 // 1 year = effectively no timeout
-        } else {
-            timeout
-        }
-    };
-
     let results = if parallel {
         let futures: Vec<_> = backends
             .iter()
             .map(|backend| {
-                let timeout = get_timeout(backend.name());
+                let timeout = chain_timeout(backend.name(), config);
                 query_one(
                     Arc::clone(backend),
-                    Arc::clone(&prompt),
+                    Arc::clone(&request),
                     Arc::clone(&cwd),
                     pb.clone(),
                     timeout,
@@ -199,10 +548,10 @@ pub async fn run_query_with_config(
     } else {
         let mut results = Vec::new();
         for backend in backends {
-            let timeout = get_timeout(backend.name());
+            let timeout = chain_timeout(backend.name(), config);
             let result = query_one(
                 Arc::clone(backend),
-                Arc::clone(&prompt),
+                Arc::clone(&request),
                 Arc::clone(&cwd),
                 pb.clone(),
                 timeout,
@@ -218,8 +567,14 @@ pub async fn run_query_with_config(
     Ok(results)
 }
 
-/// Print verbose debug info before running a query
-pub fn print_verbose_header(prompt: &str, backends: &[Arc<dyn Backend>], cwd: &Path) {
+/// Print verbose debug info before running a query, with the prompt's
+/// context budget on backends whose window is known
+pub fn print_verbose_header(
+    prompt: &str,
+    backends: &[Arc<dyn Backend>],
+    cwd: &Path,
+    config: &Config,
+) {
     println!("{}", "=== VERBOSE MODE ===".cyan().bold());
     println!();
     println!("{} {}", "Working directory:".dimmed(), cwd.display());
@@ -232,6 +587,12 @@ pub fn print_verbose_header(prompt: &str, backends: &[Arc<dyn Backend>], cwd: &P
             .collect::<Vec<_>>()
             .join(", ")
     );
+    let tokens = budget::estimate_tokens(prompt);
+    for backend in backends {
+        if let Some(budget) = budget::Budget::for_backend(backend.name(), config, None) {
+            println!("{} {}", "Budget:".dimmed(), budget.describe(tokens));
+        }
+    }
     println!();
     println!("{}", "Prompt:".dimmed());
     println!("{}", "-".repeat(50).dimmed());
@@ -240,8 +601,8 @@ pub fn print_verbose_header(prompt: &str, backends: &[Arc<dyn Backend>], cwd: &P
     println!();
 }
 
-/// Print verbose timing info after results
-pub fn print_verbose_timing(results: &[QueryResult]) {
+/// Print verbose timing info after results, with token usage and estimated cost
+pub fn print_verbose_timing(results: &[QueryResult], config: &Config) {
     println!();
     println!("{}", "=== TIMING ===".cyan().bold());
     for result in results {
@@ -252,13 +613,38 @@ pub fn print_verbose_timing(results: &[QueryResult]) {
         };
         let time = format_duration(result.elapsed_ms);
         let chars = result.output.len();
-        println!(
-            "  {} {} ({}, {} chars)",
-            result.backend.bold(),
-            status,
-            time,
-            chars
-        );
+        let mut details = format!("{}, {} chars", time, chars);
+        if let Some(ref u) = result.usage {
+            details.push_str(&format!(", {}", usage::format_tokens(u)));
+            if let Some(cost) = usage::estimate_cost(config, result.answered_by(), u) {
+                details.push_str(&format!(", {}", usage::format_cost(cost)));
+            }
+        }
+        println!("  {} {} ({})", result.backend.bold(), status, details);
+    }
+
+    let traced: Vec<_> = results
+        .iter()
+        .filter_map(|r| r.trace.as_ref().map(|t| (r.backend.as_str(), t)))
+        .collect();
+    if !traced.is_empty() {
+        println!();
+        println!("{}", "=== TRACE ===".cyan().bold());
+        for (backend, trace) in traced {
+            println!("  {}", backend.bold());
+            for line in trace.to_string().lines() {
+                println!("    {}", line.dimmed());
+            }
+        }
+    }
+
+    let tally = UsageByBackend::from_results(results);
+    if !tally.is_empty() {
+        println!();
+        println!("{}", "=== USAGE ===".cyan().bold());
+        for line in usage::summary_lines(&tally, config) {
+            println!("  {}", line);
+        }
     }
     println!();
 }
@@ -273,6 +659,28 @@ fn format_duration(ms: u64) -> String {
     }
 }
 
+/// Probe every enabled backend concurrently, sorted by name
+pub async fn check_health(config: &Config) -> Vec<(String, Health)> {
+    let mut names: Vec<&String> = config
+        .backends
+        .iter()
+        .filter(|(_, cfg)| cfg.enabled)
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+
+    let probes = names.into_iter().map(|name| async move {
+        let health = match create_backend(name, &config.backends[name]) {
+            Ok(backend) => health::probe(backend.as_ref()).await,
+            Err(e) => Health::unavailable(e.to_string())
+                .hint(format!("Fix [backends.{}] in lok.toml", name)),
+        };
+        (name.clone(), health)
+    });
+
+    join_all(probes).await
+}
+
 pub fn list_backends(config: &Config) -> Result<()> {
     println!("{}", "Available backends:".bold());
     println!();
@@ -291,10 +699,88 @@ pub fn list_backends(config: &Config) -> Result<()> {
 
         println!("  {} - {} ({})", name.bold(), status, available);
 
+        if let Some(ref kind) = backend_config.kind {
+            println!("    kind: {}", kind);
+        }
+
         if let Some(ref cmd) = backend_config.command {
             println!("    command: {} {}", cmd, backend_config.args.join(" "));
         }
+
+        let kind = backend_config.kind.as_deref().unwrap_or(name);
+        if matches!(kind, "codex" | "claude" | "gemini" | "exec") {
+            println!(
+                "    permissions: {}",
+                permissions::for_backend(name, config, None)
+            );
+        }
+
+        if !backend_config.fallback.is_empty() {
+            println!("    fallback: {}", backend_config.fallback.join(", "));
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_backend_uses_kind_and_instance_name() {
+        let config = BackendConfig {
+            kind: Some("ollama".to_string()),
+            model: Some("qwen2.5-coder:32b".to_string()),
+            ..Default::default()
+        };
+        let backend = create_backend("ollama-big", &config).unwrap();
+        assert_eq!(backend.name(), "ollama-big");
+    }
+
+    #[test]
+    fn test_build_backend_skips_unusable_fallbacks() {
+        let mut config = Config::default();
+        config.backends.insert(
+            "primary".to_string(),
+            BackendConfig {
+                kind: Some("ollama".to_string()),
+                fallback: vec!["missing".to_string(), "ollama".to_string()],
+                ..Default::default()
+            },
+        );
+        config
+            .backends
+            .insert("ollama".to_string(), BackendConfig::default());
+
+        let backend = build_backend("primary", &config, None).unwrap();
+        assert_eq!(backend.name(), "primary");
+        assert!(build_backend("missing", &config, None).is_err());
+    }
+
+    #[test]
+    fn test_create_backend_defaults_kind_to_name() {
+        let backend = create_backend("ollama", &BackendConfig::default()).unwrap();
+        assert_eq!(backend.name(), "ollama");
+    }
+
+    #[test]
+    fn test_create_backend_unknown_name_without_kind() {
+        let err = create_backend("custom", &BackendConfig::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("Unknown backend: custom"), "got: {}", err);
+        assert!(err.contains("kind"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_create_backend_unknown_kind() {
+        let config = BackendConfig {
+            kind: Some("nope".to_string()),
+            ..Default::default()
+        };
+        let err = create_backend("custom", &config).err().unwrap().to_string();
+        assert!(err.contains("Unknown backend kind 'nope'"), "got: {}", err);
+    }
+}
@@ -1,18 +1,37 @@
 //! Ollama backend - HTTP API for local LLMs
 
-use super::Backend;
+use super::health::{self, Health};
+use super::retry::HttpError;
+use super::stream::{ChunkSender, LineBuffer};
+use super::{Backend, Completion, QueryRequest, ToolCall};
 use crate::config::BackendConfig;
-use anyhow::Result;
+use crate::usage::Usage;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use reqwest::Client;
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::path::Path;
+use std::sync::LazyLock;
 use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Held while pulling, so parallel steps that all miss the model pull it once
+static PULL_LOCK: LazyLock<tokio::sync::Mutex<()>> = LazyLock::new(|| tokio::sync::Mutex::new(()));
 
 pub struct OllamaBackend {
+    name: String,
     client: Client,
     base_url: String,
     model: String,
+    /// Model options from the config, sent as Ollama's `options` object
+    options: Map<String, Value>,
+    keep_alive: Option<Value>,
+    auto_pull: bool,
+    /// Context length the model was trained with, from `/api/show`
+    context_length: OnceCell<Option<u64>>,
 }
 
 #[derive(Serialize)]
@@ -20,21 +39,129 @@ struct ChatRequest {
     model: String,
     messages: Vec<ChatMessage>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    /// Generation options (num_ctx, temperature, num_predict, stop, seed...)
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    options: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Value>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ChatMessage {
     role: String,
+    #[serde(default)]
     content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<FunctionCall>,
+    /// Tool a `tool` message answers; Ollama has no call ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
+    /// Base64 images, for multimodal models
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    function: Function,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Function {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
 }
 
 #[derive(Deserialize)]
 struct ChatResponse {
     message: Option<ChatMessage>,
+    /// Set on streamed lines when the model fails mid-response
+    #[serde(default)]
+    error: Option<String>,
+    /// Token counts, sent on the final (`done`) response
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+}
+
+/// Response from `/api/tags` (locally available models)
+#[derive(Deserialize)]
+struct TagsResponse {
+    #[serde(default)]
+    models: Vec<TagModel>,
+}
+
+#[derive(Deserialize)]
+struct TagModel {
+    name: String,
+}
+
+impl TagsResponse {
+    /// Whether `model` is pulled; a model without a tag means `:latest`
+    fn has_model(&self, model: &str) -> bool {
+        self.models.iter().any(|m| {
+            m.name == model || (!model.contains(':') && m.name == format!("{}:latest", model))
+        })
+    }
+}
+
+/// Response from `/api/version`
+#[derive(Deserialize)]
+struct VersionResponse {
+    version: String,
+}
+
+/// Response from `/api/show` (model details)
+#[derive(Deserialize)]
+struct ShowResponse {
+    #[serde(default)]
+    model_info: Map<String, Value>,
+}
+
+impl ShowResponse {
+    /// `model_info` keys are prefixed with the architecture, e.g. `llama.context_length`
+    fn context_length(&self) -> Option<u64> {
+        self.model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64())
+    }
+}
+
+/// One line of the `/api/pull` progress stream
+#[derive(Deserialize)]
+struct PullProgress {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+    /// Size of the layer being downloaded
+    #[serde(default)]
+    total: Option<u64>,
+    #[serde(default)]
+    completed: Option<u64>,
+}
+
+impl ChatResponse {
+    fn usage(&self) -> Option<Usage> {
+        if self.prompt_eval_count.is_none() && self.eval_count.is_none() {
+            return None;
+        }
+        Some(Usage::new(
+            self.prompt_eval_count.unwrap_or(0),
+            self.eval_count.unwrap_or(0),
+        ))
+    }
 }
 
 impl OllamaBackend {
-    pub fn new(config: &BackendConfig) -> Result<Self> {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
         let base_url = config
             .command
             .clone()
@@ -55,52 +182,326 @@ impl OllamaBackend {
             .timeout(Duration::from_secs(timeout_secs))
             .build()?;
 
+        // Without num_ctx Ollama truncates to its small default context
+        let mut options = config.options.clone();
+        if let Some(window) = config.context_window {
+            options.entry("num_ctx").or_insert(window.into());
+        }
+
         Ok(Self {
+            name: name.to_string(),
             client,
             base_url,
             model,
+            options,
+            keep_alive: config.keep_alive.clone(),
+            auto_pull: config.auto_pull,
+            context_length: OnceCell::new(),
         })
     }
 
-    async fn chat(&self, prompt: &str) -> Result<String> {
-        let request = ChatRequest {
-            model: self.model.clone(),
-            messages: vec![ChatMessage {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
-            stream: false,
-        };
+    /// `num_ctx` from the configured options, if set
+    fn num_ctx(&self) -> Option<u64> {
+        self.options.get("num_ctx").and_then(Value::as_u64)
+    }
 
-        let response = self
+    /// The model's trained context length, as reported by `/api/show`.
+    ///
+    /// This is an upper bound: Ollama only uses `num_ctx` tokens of it.
+    pub async fn context_length(&self) -> Option<u64> {
+        self.context_length
+            .get_or_try_init(|| self.show_context_length())
+            .await
+            .ok()
+            .copied()
+            .flatten()
+    }
+
+    async fn show_context_length(&self) -> Result<Option<u64>> {
+        let show: ShowResponse = self
             .client
-            .post(format!("{}/api/chat", self.base_url))
-            .json(&request)
+            .post(format!("{}/api/show", self.base_url))
+            .json(&serde_json::json!({ "model": self.model, "name": self.model }))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(show.context_length())
+    }
+
+    async fn has_model(&self) -> Result<bool> {
+        let tags: TagsResponse = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(tags.has_model(&self.model))
+    }
+
+    /// Pull the model through `/api/pull`, showing download progress on stderr
+    async fn pull(&self) -> Result<()> {
+        let _lock = PULL_LOCK.lock().await;
+        if self.has_model().await.unwrap_or(false) {
+            return Ok(()); // Pulled by another step while we waited
+        }
+
+        eprintln!(
+            "{} pulling {} for {}",
+            "info:".cyan(),
+            self.model,
+            self.name
+        );
+        let mut response = self
+            .client
+            .post(format!("{}/api/pull", self.base_url))
+            .json(&serde_json::json!({ "model": self.model, "name": self.model, "stream": true }))
             .send()
             .await?;
 
         if !response.status().is_success() {
             let error_text = response.text().await?;
-            anyhow::bail!("Ollama error: {}", error_text);
+            anyhow::bail!("Ollama failed to pull {}: {}", self.model, error_text);
+        }
+
+        let pb = ProgressBar::new(0);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{msg} [{bar:30.cyan/blue}] {bytes}/{total_bytes}")
+                .expect("hardcoded progress bar template should be valid"),
+        );
+
+        let mut lines = LineBuffer::default();
+        let mut status = String::new();
+        let mut handle_line = |line: &str| -> Result<()> {
+            if line.trim().is_empty() {
+                return Ok(());
+            }
+            let progress: PullProgress = serde_json::from_str(line)
+                .with_context(|| format!("Invalid Ollama pull line: {}", line))?;
+            if let Some(error) = progress.error {
+                anyhow::bail!("Ollama failed to pull {}: {}", self.model, error);
+            }
+            if let Some(total) = progress.total {
+                pb.set_length(total);
+                pb.set_position(progress.completed.unwrap_or(0));
+            }
+            pb.set_message(progress.status.clone());
+            status = progress.status;
+            Ok(())
+        };
+
+        let result = async {
+            while let Some(bytes) = response.chunk().await? {
+                for line in lines.push(&bytes) {
+                    handle_line(&line)?;
+                }
+            }
+            if let Some(line) = lines.finish() {
+                handle_line(&line)?;
+            }
+            Ok::<_, anyhow::Error>(())
         }
+        .await;
+        pb.finish_and_clear();
+        result?;
+
+        if status != "success" {
+            anyhow::bail!(
+                "Ollama pull of {} ended without success (last status: {})",
+                self.model,
+                status
+            );
+        }
+        eprintln!("{} pulled {}", "info:".cyan(), self.model);
+        Ok(())
+    }
+
+    /// POST a chat request, pulling the model first if it's missing and `auto_pull` is set
+    async fn send_chat(&self, request: &ChatRequest) -> Result<reqwest::Response> {
+        let mut pulled = false;
+        loop {
+            let response = self
+                .client
+                .post(format!("{}/api/chat", self.base_url))
+                .json(request)
+                .send()
+                .await?;
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let error = HttpError::from_response("Ollama", response).await;
+            // A plain 404 (no "model" in the body) means `command` isn't an Ollama server
+            if error.status == StatusCode::NOT_FOUND.as_u16() && error.body.contains("model") && !pulled {
+                if !self.auto_pull {
+                    anyhow::bail!(
+                        "Ollama model {} not found: run `ollama pull {}` or set auto_pull = true",
+                        self.model,
+                        self.model
+                    );
+                }
+                self.pull().await?;
+                pulled = true;
+                continue;
+            }
+            return Err(error.into());
+        }
+    }
+
+    fn chat_request(&self, request: &QueryRequest, stream: bool) -> ChatRequest {
+        let mut messages = Vec::new();
+        if let Some(ref system) = request.system {
+            messages.push(ChatMessage {
+                role: "system".to_string(),
+                content: system.clone(),
+                tool_calls: Vec::new(),
+                tool_name: None,
+                images: Vec::new(),
+            });
+        }
+        messages.extend(request.messages().into_iter().map(|m| ChatMessage {
+            role: m.role.as_str().to_string(),
+            // Text files go in the content; images have their own field
+            content: m.content_with_attachments(false),
+            images: m.images().map(|(_, data)| data.to_string()).collect(),
+            tool_calls: m
+                .tool_calls
+                .into_iter()
+                .map(|call| FunctionCall {
+                    function: Function {
+                        name: call.name,
+                        arguments: call.input,
+                    },
+                })
+                .collect(),
+            tool_name: m.tool_name,
+        }));
+
+        let mut options = self.options.clone();
+        if let Some(temperature) = request.temperature {
+            options.insert("temperature".to_string(), temperature.into());
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            options.insert("num_predict".to_string(), max_tokens.into());
+        }
+        if !request.stop.is_empty() {
+            options.insert("stop".to_string(), request.stop.clone().into());
+        }
+
+        ChatRequest {
+            model: self.model.clone(),
+            messages,
+            stream,
+            format: request.wants_json().then_some("json"),
+            options,
+            keep_alive: self.keep_alive.clone(),
+            tools: super::tools::function_tools(&request.tools),
+        }
+    }
+
+    async fn chat(&self, request: &QueryRequest) -> Result<Completion> {
+        let request = self.chat_request(request, false);
+
+        let response = self.send_chat(&request).await?;
 
         let chat_response: ChatResponse = response.json().await?;
+        let usage = chat_response.usage();
+        let (text, tool_calls) = match chat_response.message {
+            Some(msg) => (msg.content, msg.tool_calls),
+            None => Default::default(),
+        };
+
+        Ok(Completion {
+            text,
+            usage,
+            // Ollama doesn't number calls, so give them ids for the conversation
+            tool_calls: tool_calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, call)| ToolCall {
+                    id: format!("call_{}", i + 1),
+                    name: call.function.name,
+                    input: call.function.arguments,
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Chat with `stream: true`; Ollama answers with one JSON object per line
+    async fn chat_stream(
+        &self,
+        request: &QueryRequest,
+        chunks: &ChunkSender,
+    ) -> Result<Completion> {
+        let request = self.chat_request(request, true);
+
+        let mut response = self.send_chat(&request).await?;
+
+        let mut lines = LineBuffer::default();
+        let mut output = String::new();
+        let mut usage = None;
+        let mut handle_line = |line: &str| -> Result<()> {
+            if line.trim().is_empty() {
+                return Ok(());
+            }
+            let event: ChatResponse = serde_json::from_str(line)
+                .with_context(|| format!("Invalid Ollama stream line: {}", line))?;
+            if let Some(error) = event.error {
+                anyhow::bail!("Ollama error: {}", error);
+            }
+            if let Some(u) = event.usage() {
+                usage = Some(u);
+            }
+            if let Some(msg) = event.message {
+                if !msg.content.is_empty() {
+                    let _ = chunks.send(msg.content.clone());
+                    output.push_str(&msg.content);
+                }
+            }
+            Ok(())
+        };
 
-        match chat_response.message {
-            Some(msg) => Ok(msg.content),
-            None => Ok(String::new()),
+        while let Some(bytes) = response.chunk().await? {
+            for line in lines.push(&bytes) {
+                handle_line(&line)?;
+            }
         }
+        if let Some(line) = lines.finish() {
+            handle_line(&line)?;
+        }
+
+        Ok(Completion {
+            text: output,
+            usage,
+            ..Default::default()
+        })
     }
 }
 
 #[async_trait]
 impl Backend for OllamaBackend {
     fn name(&self) -> &str {
-        "ollama"
+        &self.name
     }
 
-    async fn query(&self, prompt: &str, _cwd: &Path) -> Result<String> {
-        self.chat(prompt).await
+    async fn complete(&self, request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+        self.chat(request).await
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        _cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        self.chat_stream(request, &chunks).await
     }
 
     fn is_available(&self) -> bool {
@@ -108,4 +509,438 @@ impl Backend for OllamaBackend {
         // Return true and let runtime connection fail if not running.
         true
     }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    async fn health(&self) -> Health {
+        let probe_timeout = Duration::from_secs(10);
+        let response = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .timeout(probe_timeout)
+            .send()
+            .await;
+
+        let response = match response {
+            Ok(r) if r.status().is_success() => r,
+            Ok(r) => {
+                return Health::unavailable(format!("server returned {}", r.status()))
+                    .hint("Check the Ollama server logs")
+            }
+            Err(e) => {
+                return health::connect_failed(&self.base_url, &e)
+                    .hint("Start the server with `ollama serve`, or set `command` to its URL")
+            }
+        };
+
+        let tags: TagsResponse = match response.json().await {
+            Ok(tags) => tags,
+            Err(e) => {
+                return Health::unavailable(format!("unexpected /api/tags response: {}", e))
+                    .hint("Check that `command` points at an Ollama server")
+            }
+        };
+
+        // Version is informational; older servers may not report it
+        let version = match self
+            .client
+            .get(format!("{}/api/version", self.base_url))
+            .timeout(probe_timeout)
+            .send()
+            .await
+        {
+            Ok(r) => r.json::<VersionResponse>().await.ok().map(|v| v.version),
+            Err(_) => None,
+        };
+
+        let health = if tags.has_model(&self.model) {
+            match (self.context_length().await, self.num_ctx()) {
+                (Some(max), Some(num_ctx)) if num_ctx > max => Health::warning(format!(
+                    "num_ctx {} exceeds {}'s context length of {} tokens",
+                    num_ctx, self.model, max
+                ))
+                .hint(format!("Set options.num_ctx to {} or less", max)),
+                (Some(max), Some(num_ctx)) => {
+                    Health::ready().detail(format!("num_ctx {} of {} tokens", num_ctx, max))
+                }
+                (Some(max), None) => Health::ready().detail(format!("{}-token context", max)),
+                (None, _) => Health::ready(),
+            }
+        } else {
+            Health::unavailable(format!("model {} is not pulled", self.model))
+                .hint(format!("ollama pull {}", self.model))
+        };
+        match version {
+            Some(version) => health.version(version),
+            None => health,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Attachment;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve one response whose body is written in separate chunks
+    async fn stub_stream_server(parts: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            for part in parts {
+                let chunk = format!("{:x}\r\n{}\r\n", part.len(), part);
+                socket.write_all(chunk.as_bytes()).await.unwrap();
+                socket.flush().await.unwrap();
+            }
+            socket.write_all(b"0\r\n\r\n").await.unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    /// Serve `responses` to successive requests, one connection each.
+    /// Returns the server URL and the request lines it received.
+    async fn stub_server(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut socket).await;
+                seen.lock()
+                    .unwrap()
+                    .push(request.lines().next().unwrap_or("").to_string());
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (format!("http://{}", addr), requests)
+    }
+
+    /// Read a request's headers and its `content-length` body
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).to_string();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length = text
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if n == 0 || data.len() >= end + 4 + length {
+                    return text;
+                }
+            } else if n == 0 {
+                return text;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_ndjson() {
+        let url = stub_stream_server(vec![
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"done\":false}\n{\"mess",
+            "age\":{\"role\":\"assistant\",\"content\":\"lo\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"prompt_eval_count\":26,\"eval_count\":2}\n",
+        ])
+        .await;
+
+        let config = BackendConfig {
+            command: Some(url),
+            timeout: Some(5),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let output = backend
+            .query_stream(&QueryRequest::new("hi"), Path::new("."), tx)
+            .await
+            .unwrap();
+        assert_eq!(output.text, "Hello");
+        assert_eq!(output.usage, Some(Usage::new(26, 2)));
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, vec!["Hel", "lo"]);
+    }
+
+    #[test]
+    fn test_tags_has_model() {
+        let tags: TagsResponse = serde_json::from_str(
+            r#"{"models":[{"name":"llama3.2:latest"},{"name":"qwen2.5-coder:32b"}]}"#,
+        )
+        .unwrap();
+        assert!(tags.has_model("llama3.2"));
+        assert!(tags.has_model("llama3.2:latest"));
+        assert!(tags.has_model("qwen2.5-coder:32b"));
+        assert!(!tags.has_model("qwen2.5-coder"));
+        assert!(!tags.has_model("mistral"));
+    }
+
+    #[tokio::test]
+    async fn test_health_server_down() {
+        // Grab a free port, then close it so the connection is refused
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let config = BackendConfig {
+            command: Some(url.clone()),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        let health = backend.health().await;
+        assert_eq!(health.status, health::HealthStatus::Unavailable);
+        assert!(health.detail.unwrap().contains(&url));
+        assert!(health.hint.unwrap().contains("ollama serve"));
+    }
+
+    #[test]
+    fn test_chat_request_maps_options() {
+        let backend = OllamaBackend::new("ollama", &BackendConfig::default()).unwrap();
+        let request = QueryRequest {
+            system: Some("Be terse.".to_string()),
+            max_tokens: Some(64),
+            temperature: Some(0.2),
+            stop: vec!["END".to_string()],
+            response_format: crate::backend::ResponseFormat::Json,
+            ..QueryRequest::new("hi")
+        };
+
+        let json = serde_json::to_value(backend.chat_request(&request, false)).unwrap();
+        assert_eq!(json["messages"][0]["role"], "system");
+        assert_eq!(json["messages"][0]["content"], "Be terse.");
+        assert_eq!(json["messages"][1]["content"], "hi");
+        assert_eq!(json["format"], "json");
+        assert_eq!(json["options"]["num_predict"], 64);
+        assert_eq!(json["options"]["stop"][0], "END");
+        assert!((json["options"]["temperature"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_chat_request_attachments() {
+        let backend = OllamaBackend::new("ollama", &BackendConfig::default()).unwrap();
+        let request = QueryRequest {
+            attachments: vec![
+                Attachment::Text {
+                    path: "notes.txt".to_string(),
+                    content: "todo".to_string(),
+                },
+                Attachment::Image {
+                    path: "shot.png".to_string(),
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw==".to_string(),
+                },
+            ],
+            ..QueryRequest::new("hi")
+        };
+
+        let json = serde_json::to_value(backend.chat_request(&request, false)).unwrap();
+        assert_eq!(
+            json["messages"][0]["content"],
+            "hi\n\n--- BEGIN FILE notes.txt ---\ntodo\n--- END FILE notes.txt ---"
+        );
+        assert_eq!(json["messages"][0]["images"][0], "iVBORw==");
+    }
+
+    #[test]
+    fn test_chat_request_plain_omits_options() {
+        let backend = OllamaBackend::new("ollama", &BackendConfig::default()).unwrap();
+        let json =
+            serde_json::to_value(backend.chat_request(&QueryRequest::new("hi"), false)).unwrap();
+        assert_eq!(json["messages"].as_array().unwrap().len(), 1);
+        assert!(json.get("format").is_none());
+        assert!(json.get("options").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_error_line() {
+        let url = stub_stream_server(vec!["{\"error\":\"model not found\"}\n"]).await;
+        let config = BackendConfig {
+            command: Some(url),
+            timeout: Some(5),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let err = backend
+            .query_stream(&QueryRequest::new("hi"), Path::new("."), tx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("model not found"));
+    }
+
+    #[test]
+    fn test_config_options_and_keep_alive() {
+        let mut options = Map::new();
+        options.insert("num_ctx".to_string(), 8192.into());
+        options.insert("temperature".to_string(), 0.9.into());
+        let config = BackendConfig {
+            options,
+            keep_alive: Some("10m".into()),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        let request = QueryRequest {
+            temperature: Some(0.1),
+            ..QueryRequest::new("hi")
+        };
+
+        let json = serde_json::to_value(backend.chat_request(&request, false)).unwrap();
+        assert_eq!(json["options"]["num_ctx"], 8192);
+        // The request's own temperature wins over the config
+        assert!((json["options"]["temperature"].as_f64().unwrap() - 0.1).abs() < 1e-6);
+        assert_eq!(json["keep_alive"], "10m");
+        assert_eq!(backend.num_ctx(), Some(8192));
+    }
+
+    #[test]
+    fn test_context_window_sets_num_ctx() {
+        let config = BackendConfig {
+            context_window: Some(32768),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        assert_eq!(backend.num_ctx(), Some(32768));
+    }
+
+    #[tokio::test]
+    async fn test_chat_tool_calls() {
+        let (url, _) = stub_server(vec![(
+            "200 OK",
+            r#"{"message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"lookup","arguments":{"q":"x"}}}]},"done":true}"#,
+        )])
+        .await;
+        let config = BackendConfig {
+            command: Some(url),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        let completion = backend
+            .complete(&QueryRequest::new("find x"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(completion.tool_calls[0].id, "call_1");
+        assert_eq!(completion.tool_calls[0].name, "lookup");
+        assert_eq!(completion.tool_calls[0].input["q"], "x");
+
+        let call = completion.tool_calls[0].clone();
+        let request = QueryRequest {
+            tools: vec![crate::backend::Tool {
+                name: "lookup".to_string(),
+                description: "Look something up".to_string(),
+                input_schema: serde_json::json!({"type": "object"}),
+            }],
+            history: vec![
+                crate::backend::Message::user("find x"),
+                crate::backend::Message::assistant_with_calls("", vec![call.clone()]),
+                crate::backend::Message::tool_result(&call, "found"),
+            ],
+            ..QueryRequest::new("")
+        };
+        let json = serde_json::to_value(backend.chat_request(&request, false)).unwrap();
+        assert_eq!(json["tools"][0]["function"]["parameters"]["type"], "object");
+        assert_eq!(json["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            json["messages"][1]["tool_calls"][0]["function"]["arguments"]["q"],
+            "x"
+        );
+        assert_eq!(json["messages"][2]["role"], "tool");
+        assert_eq!(json["messages"][2]["tool_name"], "lookup");
+    }
+
+    #[test]
+    fn test_show_context_length() {
+        let show: ShowResponse = serde_json::from_str(
+            r#"{"model_info":{"general.architecture":"llama","llama.context_length":131072}}"#,
+        )
+        .unwrap();
+        assert_eq!(show.context_length(), Some(131072));
+
+        let show: ShowResponse = serde_json::from_str(r#"{"parameters":""}"#).unwrap();
+        assert_eq!(show.context_length(), None);
+    }
+
+    const MODEL_NOT_FOUND: &str = r#"{"error":"model \"tiny\" not found, try pulling it first"}"#;
+
+    #[tokio::test]
+    async fn test_missing_model_without_auto_pull() {
+        let (url, _) = stub_server(vec![("404 Not Found", MODEL_NOT_FOUND)]).await;
+        let config = BackendConfig {
+            command: Some(url),
+            model: Some("tiny".to_string()),
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        assert!(err.to_string().contains("ollama pull tiny"));
+    }
+
+    #[tokio::test]
+    async fn test_auto_pull_then_retry() {
+        let (url, requests) = stub_server(vec![
+            ("404 Not Found", MODEL_NOT_FOUND),
+            ("200 OK", r#"{"models":[]}"#),
+            (
+                "200 OK",
+                "{\"status\":\"pulling manifest\"}\n{\"status\":\"pulling 6a0746a1ec1a\",\"total\":100,\"completed\":40}\n{\"status\":\"success\"}\n",
+            ),
+            (
+                "200 OK",
+                r#"{"message":{"role":"assistant","content":"hello"},"done":true}"#,
+            ),
+        ])
+        .await;
+        let config = BackendConfig {
+            command: Some(url),
+            model: Some("tiny".to_string()),
+            auto_pull: true,
+            ..Default::default()
+        };
+        let backend = OllamaBackend::new("ollama", &config).unwrap();
+        assert_eq!(backend.query("hi", Path::new(".")).await.unwrap(), "hello");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "POST /api/chat HTTP/1.1",
+                "GET /api/tags HTTP/1.1",
+                "POST /api/pull HTTP/1.1",
+                "POST /api/chat HTTP/1.1",
+            ]
+        );
+    }
 }
@@ -0,0 +1,634 @@
+//! OpenAI-compatible backend - HTTP API for vLLM, llama.cpp server, LiteLLM, etc.
+
+use super::health::{self, Health};
+use super::retry::HttpError;
+use super::{Backend, Completion, Message, QueryRequest, ToolCall};
+use crate::config::BackendConfig;
+use crate::usage::Usage;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+pub struct OpenAiBackend {
+    name: String,
+    client: Client,
+    base_url: String,
+    model: String,
+    api_key: Option<SecretString>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: Option<Content>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<FunctionCall>,
+    /// Set on `tool` messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+/// Message content: text, or text and images as content parts
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<serde_json::Value>),
+}
+
+impl Content {
+    /// A user turn's text, with image attachments as `image_url` data URLs
+    fn from_message(message: &Message) -> Self {
+        let text = message.content_with_attachments(false);
+        let images: Vec<serde_json::Value> = message
+            .images()
+            .map(|(media_type, data)| {
+                serde_json::json!({
+                    "type": "image_url",
+                    "image_url": { "url": format!("data:{};base64,{}", media_type, data) },
+                })
+            })
+            .collect();
+        if images.is_empty() {
+            return Content::Text(text);
+        }
+        let mut parts = vec![serde_json::json!({ "type": "text", "text": text })];
+        parts.extend(images);
+        Content::Parts(parts)
+    }
+
+    fn into_text(self) -> String {
+        match self {
+            Content::Text(text) => text,
+            Content::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    id: String,
+    #[serde(rename = "type", default = "function_type")]
+    kind: String,
+    function: Function,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Function {
+    name: String,
+    /// JSON-encoded arguments
+    arguments: String,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+impl From<&ToolCall> for FunctionCall {
+    fn from(call: &ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            kind: function_type(),
+            function: Function {
+                name: call.name.clone(),
+                arguments: call.input.to_string(),
+            },
+        }
+    }
+}
+
+impl From<FunctionCall> for ToolCall {
+    fn from(call: FunctionCall) -> Self {
+        // Some servers send arguments that aren't valid JSON; pass them on as a string
+        let input = serde_json::from_str(&call.function.arguments)
+            .unwrap_or(serde_json::Value::String(call.function.arguments));
+        Self {
+            id: call.id,
+            name: call.function.name,
+            input,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Deserialize)]
+struct ChatUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+/// Response from `/v1/models`
+#[derive(Deserialize)]
+struct ModelList {
+    #[serde(default)]
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: Option<ChatMessage>,
+}
+
+impl OpenAiBackend {
+    pub fn new(name: &str, config: &BackendConfig) -> Result<Self> {
+        let base_url = config
+            .command
+            .clone()
+            .unwrap_or_else(|| "http://localhost:8000".to_string());
+        // Accept both "http://host:port" and "http://host:port/v1"
+        let base_url = base_url
+            .trim_end_matches('/')
+            .trim_end_matches("/v1")
+            .to_string();
+
+        let model = config.model.clone().ok_or_else(|| {
+            anyhow::anyhow!("OpenAI-compatible backend requires 'model' to be set")
+        })?;
+
+        // Local servers often run without auth, so the key is optional
+        let api_key = match config.api_key_env {
+            Some(ref var) => {
+                Some(SecretString::from(env::var(var).with_context(|| {
+                    format!("Missing environment variable: {}", var)
+                })?))
+            }
+            None => None,
+        };
+
+        let timeout_secs = config.timeout.unwrap_or(300);
+        let timeout_secs = if timeout_secs == 0 {
+            365 * 24 * 60 * 60 // 1 year = effectively no timeout
+        } else {
+            timeout_secs
+        };
+        let client = Client::builder()
+            .timeout(Duration::from_secs(timeout_secs))
+            .build()?;
+
+        Ok(Self {
+            name: name.to_string(),
+            client,
+            base_url,
+            model,
+            api_key,
+        })
+    }
+
+    fn chat_request(&self, request: &QueryRequest) -> ChatRequest {
+        let mut messages = Vec::new();
+        if let Some(ref system) = request.system {
+            messages.push(ChatMessage {
+                role: "system".to_string(),
+                content: Some(Content::Text(system.clone())),
+                tool_calls: Vec::new(),
+                tool_call_id: None,
+            });
+        }
+        messages.extend(request.messages().into_iter().map(|m| ChatMessage {
+            role: m.role.as_str().to_string(),
+            // An assistant turn that only calls tools has no content
+            content: (!m.content.is_empty() || m.tool_calls.is_empty())
+                .then(|| Content::from_message(&m)),
+            tool_calls: m.tool_calls.iter().map(FunctionCall::from).collect(),
+            tool_call_id: m.tool_call_id,
+        }));
+
+        ChatRequest {
+            model: self.model.clone(),
+            messages,
+            stream: false,
+            temperature: request.temperature,
+            max_tokens: request.max_tokens,
+            stop: request.stop.clone(),
+            response_format: request
+                .wants_json()
+                .then(|| serde_json::json!({ "type": "json_object" })),
+            tools: super::tools::function_tools(&request.tools),
+        }
+    }
+
+    async fn chat(&self, request: &QueryRequest) -> Result<Completion> {
+        let request = self.chat_request(request);
+
+        let mut builder = self
+            .client
+            .post(format!("{}/v1/chat/completions", self.base_url))
+            .json(&request);
+        if let Some(ref key) = self.api_key {
+            builder = builder.bearer_auth(key.expose_secret());
+        }
+
+        let response = builder.send().await?;
+
+        if !response.status().is_success() {
+            return Err(HttpError::from_response("OpenAI-compatible", response).await.into());
+        }
+
+        let chat_response: ChatResponse = response
+            .json()
+            .await
+            .context("Failed to parse chat completion response")?;
+
+        let usage = chat_response
+            .usage
+            .map(|u| Usage::new(u.prompt_tokens, u.completion_tokens));
+        // An empty answer isn't a success; erroring lets retries and fallbacks step in
+        let choice = chat_response
+            .choices
+            .into_iter()
+            .next()
+            .context("OpenAI-compatible backend returned no choices in response")?;
+        let (text, tool_calls) = match choice.message {
+            Some(m) => (
+                m.content.map(Content::into_text).unwrap_or_default(),
+                m.tool_calls.into_iter().map(ToolCall::from).collect(),
+            ),
+            None => Default::default(),
+        };
+
+        Ok(Completion {
+            text,
+            usage,
+            tool_calls,
+            ..Default::default()
+        })
+    }
+}
+
+#[async_trait]
+impl Backend for OpenAiBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn complete(&self, request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+        self.chat(request).await
+    }
+
+    fn is_available(&self) -> bool {
+        // HTTP server, same as Ollama: let the request fail at runtime if it's down
+        true
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    async fn health(&self) -> Health {
+        let mut builder = self
+            .client
+            .get(format!("{}/v1/models", self.base_url))
+            .timeout(Duration::from_secs(10));
+        if let Some(ref key) = self.api_key {
+            builder = builder.bearer_auth(key.expose_secret());
+        }
+
+        let response = match builder.send().await {
+            Ok(r) => r,
+            Err(e) => {
+                return health::connect_failed(&self.base_url, &e)
+                    .hint("Start the server, or set `command` to its base URL")
+            }
+        };
+
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Health::unavailable(format!("server rejected credentials ({})", status))
+                .hint("Set `api_key_env` to the variable holding the server's API key");
+        }
+        if !status.is_success() {
+            return Health::unavailable(format!("/v1/models returned {}", status))
+                .hint("Check that `command` points at an OpenAI-compatible server");
+        }
+
+        // Proxies like LiteLLM may route names they don't list, so a missing model only warns
+        match response.json::<ModelList>().await {
+            Ok(list) if !list.data.iter().any(|m| m.id == self.model) => {
+                Health::warning(format!("model {} not listed by the server", self.model)).hint(
+                    format!(
+                        "Available: {}",
+                        list.data
+                            .iter()
+                            .map(|m| m.id.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )
+            }
+            _ => Health::ready(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Attachment;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve a single canned HTTP response and return the base URL plus the raw request
+    async fn stub_server(status: &str, body: &str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let response = format!(
+            "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the full body (per content-length) has arrived
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(header_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..header_end]
+                        .lines()
+                        .find_map(|l| {
+                            l.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().parse::<usize>().unwrap_or(0))
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= header_end + 4 + content_length {
+                        break;
+                    }
+                }
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        (format!("http://{}", addr), handle)
+    }
+
+    fn test_config(base_url: &str) -> BackendConfig {
+        BackendConfig {
+            command: Some(base_url.to_string()),
+            model: Some("test-model".to_string()),
+            timeout: Some(5),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_success() {
+        let (url, handle) = stub_server(
+            "200 OK",
+            r#"{"choices":[{"index":0,"message":{"role":"assistant","content":"hello there"}}],"usage":{"prompt_tokens":9,"completion_tokens":3,"total_tokens":12}}"#,
+        )
+        .await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let output = backend
+            .complete(&QueryRequest::new("hi"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(output.text, "hello there");
+        assert_eq!(output.usage, Some(Usage::new(9, 3)));
+
+        let request = handle.await.unwrap();
+        assert!(request.starts_with("POST /v1/chat/completions"));
+        assert!(request.contains("\"model\":\"test-model\""));
+        assert!(request.contains("\"content\":\"hi\""));
+        // Unset options are left to the server's defaults
+        assert!(!request.contains("temperature"));
+        assert!(!request.contains("response_format"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_request_options() {
+        let (url, handle) = stub_server(
+            "200 OK",
+            r#"{"choices":[{"message":{"role":"assistant","content":"{}"}}]}"#,
+        )
+        .await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let request = QueryRequest {
+            system: Some("Be terse.".to_string()),
+            max_tokens: Some(32),
+            stop: vec!["END".to_string()],
+            response_format: crate::backend::ResponseFormat::Json,
+            ..QueryRequest::new("hi")
+        };
+        backend.complete(&request, Path::new(".")).await.unwrap();
+
+        let raw = handle.await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_str(&raw[raw.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][0]["content"], "Be terse.");
+        assert_eq!(body["max_tokens"], 32);
+        assert_eq!(body["stop"][0], "END");
+        assert_eq!(body["response_format"]["type"], "json_object");
+    }
+
+    #[test]
+    fn test_chat_request_image_parts() {
+        let backend = OpenAiBackend::new("openai", &test_config("http://localhost:1")).unwrap();
+        let request = QueryRequest {
+            attachments: vec![Attachment::Image {
+                path: "shot.png".to_string(),
+                media_type: "image/png".to_string(),
+                data: "iVBORw==".to_string(),
+            }],
+            ..QueryRequest::new("what is this?")
+        };
+
+        let body = serde_json::to_value(backend.chat_request(&request)).unwrap();
+        let parts = &body["messages"][0]["content"];
+        assert_eq!(parts[0]["text"], "what is this?");
+        assert_eq!(
+            parts[1]["image_url"]["url"],
+            "data:image/png;base64,iVBORw=="
+        );
+
+        // Without images, content stays a string
+        let body = serde_json::to_value(backend.chat_request(&QueryRequest::new("hi"))).unwrap();
+        assert_eq!(body["messages"][0]["content"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_chat_tool_calls() {
+        let (url, handle) = stub_server(
+            "200 OK",
+            r#"{"choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_9","type":"function","function":{"name":"lookup","arguments":"{\"q\":\"x\"}"}}]}}]}"#,
+        )
+        .await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let earlier = ToolCall {
+            id: "call_1".to_string(),
+            name: "lookup".to_string(),
+            input: serde_json::json!({"q": "w"}),
+        };
+        let request = QueryRequest {
+            tools: vec![crate::backend::Tool {
+                name: "lookup".to_string(),
+                description: "Look something up".to_string(),
+                input_schema: serde_json::json!({"type": "object"}),
+            }],
+            history: vec![
+                crate::backend::Message::user("find x"),
+                crate::backend::Message::assistant_with_calls("", vec![earlier.clone()]),
+                crate::backend::Message::tool_result(&earlier, "nothing"),
+            ],
+            ..QueryRequest::new("")
+        };
+        let completion = backend.complete(&request, Path::new(".")).await.unwrap();
+        assert_eq!(completion.text, "");
+        assert_eq!(completion.tool_calls[0].id, "call_9");
+        assert_eq!(completion.tool_calls[0].input["q"], "x");
+
+        let raw = handle.await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_str(&raw[raw.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!(body["tools"][0]["function"]["name"], "lookup");
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[1].get("content").unwrap().is_null());
+        assert_eq!(messages[1]["tool_calls"][0]["function"]["arguments"], "{\"q\":\"w\"}");
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_1");
+    }
+
+    #[tokio::test]
+    async fn test_chat_error_status() {
+        let (url, _handle) = stub_server("429 Too Many Requests", r#"{"error":"slow down"}"#).await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("429"), "got: {}", msg);
+        assert!(msg.contains("slow down"), "got: {}", msg);
+    }
+
+    #[tokio::test]
+    async fn test_chat_no_choices() {
+        let (url, _handle) = stub_server("200 OK", r#"{"choices":[]}"#).await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        assert!(err.to_string().contains("no choices"), "got: {}", err);
+    }
+
+    #[tokio::test]
+    async fn test_chat_error_keeps_retry_after() {
+        // The stub writes the status line verbatim, so a header can ride along
+        let (url, _handle) = stub_server(
+            "429 Too Many Requests\r\nretry-after: 7",
+            r#"{"error":"slow down"}"#,
+        )
+        .await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let err = backend.query("hi", Path::new(".")).await.unwrap_err();
+        let http = err.downcast_ref::<HttpError>().unwrap();
+        assert_eq!(http.status, 429);
+        assert_eq!(
+            super::super::retry::retry_after(&err),
+            Some(std::time::Duration::from_secs(7))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_base_url_with_v1_suffix() {
+        let (url, handle) = stub_server(
+            "200 OK",
+            r#"{"choices":[{"message":{"role":"assistant","content":"ok"}}]}"#,
+        )
+        .await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&format!("{}/v1/", url))).unwrap();
+        backend.query("hi", Path::new(".")).await.unwrap();
+
+        let request = handle.await.unwrap();
+        assert!(request.starts_with("POST /v1/chat/completions"));
+    }
+
+    #[tokio::test]
+    async fn test_health_lists_model() {
+        let (url, handle) = stub_server(
+            "200 OK",
+            r#"{"object":"list","data":[{"id":"test-model","object":"model"}]}"#,
+        )
+        .await;
+
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        assert!(backend.health().await.is_ready());
+        assert!(handle.await.unwrap().starts_with("GET /v1/models"));
+    }
+
+    #[tokio::test]
+    async fn test_health_missing_model_and_auth() {
+        let (url, _handle) = stub_server("200 OK", r#"{"data":[{"id":"other-model"}]}"#).await;
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let health = backend.health().await;
+        assert_eq!(health.status, health::HealthStatus::Warning);
+        assert_eq!(health.hint.as_deref(), Some("Available: other-model"));
+
+        let (url, _handle) = stub_server("401 Unauthorized", r#"{"error":"bad key"}"#).await;
+        let backend = OpenAiBackend::new("openai", &test_config(&url)).unwrap();
+        let health = backend.health().await;
+        assert_eq!(health.status, health::HealthStatus::Unavailable);
+        assert!(health.hint.unwrap().contains("api_key_env"));
+    }
+
+    #[test]
+    fn test_requires_model() {
+        let mut config = test_config("http://localhost:1");
+        config.model = None;
+        assert!(OpenAiBackend::new("openai", &config).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Permission levels for agentic CLIs
+//!
+//! `permissions` on a backend, or on a workflow step, says what an agent may
+//! do in the same words for every CLI. Each CLI backend passes it as its own
+//! flag:
+//!
+//! | level             | codex                   | claude CLI                            | gemini                     |
+//! |-------------------|-------------------------|---------------------------------------|----------------------------|
+//! | `read-only`       | `-s read-only`          | `--permission-mode default`           | `--approval-mode default`  |
+//! | `workspace-write` | `-s workspace-write`    | `--permission-mode acceptEdits`       | `--approval-mode auto_edit`|
+//! | `full`            | `-s danger-full-access` | `--permission-mode bypassPermissions` | `--approval-mode yolo`     |
+//!
+//! Gemini CLIs before 0.6.0 have no `--approval-mode` and only run read-only.
+//!
+//! Backends that only return text (HTTP APIs, mock) can't act on anything, so
+//! they count as read-only. `exec` backends run a command lok can't constrain:
+//! they count as their declared `permissions`, or full without one.
+
+use crate::config::{BackendConfig, Config};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What an agent may do, from least to most
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permissions {
+    /// Read files and answer
+    #[default]
+    ReadOnly,
+    /// Also edit files in the working directory
+    WorkspaceWrite,
+    /// Anything: run commands, write anywhere, use the network
+    Full,
+}
+
+impl Permissions {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permissions::ReadOnly => "read-only",
+            Permissions::WorkspaceWrite => "workspace-write",
+            Permissions::Full => "full",
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Permissions::ReadOnly => 0,
+            Permissions::WorkspaceWrite => 1,
+            Permissions::Full => 2,
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A CLI's permission flag and its value for each level
+struct CliFlag {
+    /// Spellings of the flag; the first is the one lok passes
+    names: &'static [&'static str],
+    values: [&'static str; 3],
+}
+
+const CODEX: CliFlag = CliFlag {
+    names: &["-s", "--sandbox"],
+    values: ["read-only", "workspace-write", "danger-full-access"],
+};
+
+const CLAUDE: CliFlag = CliFlag {
+    names: &["--permission-mode"],
+    values: ["default", "acceptEdits", "bypassPermissions"],
+};
+
+const GEMINI: CliFlag = CliFlag {
+    names: &["--approval-mode"],
+    values: ["default", "auto_edit", "yolo"],
+};
+
+fn cli_flag(kind: &str) -> Option<&'static CliFlag> {
+    match kind {
+        "codex" => Some(&CODEX),
+        "claude" => Some(&CLAUDE),
+        "gemini" => Some(&GEMINI),
+        _ => None,
+    }
+}
+
+/// The level set by the CLI's own flag in `args`, e.g. `-s workspace-write`
+fn from_args(flag: &CliFlag, args: &[String]) -> Option<Permissions> {
+    let value = args.iter().enumerate().find_map(|(i, arg)| {
+        flag.names.iter().find_map(|name| {
+            if arg == name {
+                args.get(i + 1).map(String::as_str)
+            } else {
+                arg.strip_prefix(name)?.strip_prefix('=')
+            }
+        })
+    })?;
+    [
+        Permissions::ReadOnly,
+        Permissions::WorkspaceWrite,
+        Permissions::Full,
+    ]
+    .into_iter()
+    .find(|level| flag.values[level.index()] == value)
+    // A value lok doesn't know can't be vouched for
+    .or(Some(Permissions::Full))
+}
+
+/// Permissions of one agentic CLI backend
+#[derive(Clone)]
+pub struct CliPermissions {
+    flag: &'static CliFlag,
+    level: Permissions,
+    /// The configured `args` set the flag themselves, so lok adds none
+    in_args: bool,
+}
+
+impl CliPermissions {
+    /// From `permissions` in the backend's config, or the CLI's own flag in its
+    /// `args`; read-only when neither sets it
+    pub fn new(kind: &str, config: &BackendConfig, args: &[String]) -> Result<Self> {
+        let flag =
+            cli_flag(kind).ok_or_else(|| anyhow::anyhow!("{} has no permission flags", kind))?;
+        let in_args = from_args(flag, args);
+        if let (Some(_), Some(_)) = (config.permissions, in_args) {
+            anyhow::bail!(
+                "{} args already pass {}; set either permissions or the flag, not both",
+                kind,
+                flag.names[0]
+            );
+        }
+        Ok(Self {
+            flag,
+            level: in_args.or(config.permissions).unwrap_or_default(),
+            in_args: in_args.is_some(),
+        })
+    }
+
+    /// The level a request runs at, which may ask for its own
+    pub fn level(&self, requested: Option<Permissions>) -> Permissions {
+        match requested {
+            Some(level) if !self.in_args => level,
+            _ => self.level,
+        }
+    }
+
+    /// Flags to add to the command for a request, which may ask for its own level
+    pub fn args(&self, requested: Option<Permissions>) -> Result<Vec<String>> {
+        if self.in_args {
+            match requested {
+                Some(level) if level != self.level => anyhow::bail!(
+                    "permissions = \"{}\" was asked for, but the backend's args pin {}",
+                    level,
+                    self.level
+                ),
+                _ => return Ok(Vec::new()),
+            }
+        }
+        let level = requested.unwrap_or(self.level);
+        Ok(vec![
+            self.flag.names[0].to_string(),
+            self.flag.values[level.index()].to_string(),
+        ])
+    }
+}
+
+/// The level `name` runs at when a query asks for `requested`, counting its
+/// `fallback` chain, which answers with the same request
+pub fn for_backend(name: &str, config: &Config, requested: Option<Permissions>) -> Permissions {
+    let Some(backend) = config.backends.get(name) else {
+        return Permissions::ReadOnly;
+    };
+    backend
+        .fallback
+        .iter()
+        .filter_map(|fallback| {
+            let fallback_config = config.backends.get(fallback)?;
+            Some(level(fallback, fallback_config, requested))
+        })
+        .fold(level(name, backend, requested), Permissions::max)
+}
+
+fn level(name: &str, backend: &BackendConfig, requested: Option<Permissions>) -> Permissions {
+    let kind = backend.kind.as_deref().unwrap_or(name);
+    match kind {
+        // The Claude API only returns text; the CLI is used when `command` is set
+        "claude" if backend.command.is_none() => Permissions::ReadOnly,
+        "codex" | "claude" | "gemini" => {
+            let flag = cli_flag(kind).expect("agentic CLIs have a permission flag");
+            from_args(flag, &backend.args)
+                .or(requested)
+                .or(backend.permissions)
+                .unwrap_or_default()
+        }
+        "exec" => backend.permissions.unwrap_or(Permissions::Full),
+        _ => Permissions::ReadOnly,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cli_flags_per_level() {
+        let config = BackendConfig {
+            permissions: Some(Permissions::WorkspaceWrite),
+            ..Default::default()
+        };
+        let codex = CliPermissions::new("codex", &config, &args(&["exec", "--json"])).unwrap();
+        assert_eq!(codex.args(None).unwrap(), ["-s", "workspace-write"]);
+        // A step may ask for another level
+        assert_eq!(
+            codex.args(Some(Permissions::Full)).unwrap(),
+            ["-s", "danger-full-access"]
+        );
+
+        let claude = CliPermissions::new("claude", &BackendConfig::default(), &[]).unwrap();
+        assert_eq!(claude.args(None).unwrap(), ["--permission-mode", "default"]);
+    }
+
+    #[test]
+    fn test_flag_in_args_wins() {
+        let codex_args = args(&["exec", "--json", "--sandbox=workspace-write"]);
+        let codex = CliPermissions::new("codex", &BackendConfig::default(), &codex_args).unwrap();
+        assert!(codex.args(None).unwrap().is_empty());
+        assert!(codex.args(Some(Permissions::WorkspaceWrite)).is_ok());
+        assert!(codex.args(Some(Permissions::ReadOnly)).is_err());
+
+        // Both at once is ambiguous
+        let config = BackendConfig {
+            permissions: Some(Permissions::ReadOnly),
+            ..Default::default()
+        };
+        assert!(CliPermissions::new("codex", &config, &codex_args).is_err());
+    }
+
+    #[test]
+    fn test_for_backend() {
+        let mut config = Config::default();
+        config.backends.insert(
+            "agent".to_string(),
+            BackendConfig {
+                kind: Some("codex".to_string()),
+                args: args(&["exec", "-s", "danger-full-access"]),
+                ..Default::default()
+            },
+        );
+        config.backends.insert(
+            "script".to_string(),
+            BackendConfig {
+                kind: Some("exec".to_string()),
+                fallback: vec!["ollama".to_string()],
+                ..Default::default()
+            },
+        );
+        config.backends.get_mut("ollama").unwrap().fallback = vec!["codex".to_string()];
+
+        assert_eq!(for_backend("codex", &config, None), Permissions::ReadOnly);
+        assert_eq!(
+            for_backend("codex", &config, Some(Permissions::WorkspaceWrite)),
+            Permissions::WorkspaceWrite
+        );
+        assert_eq!(for_backend("agent", &config, None), Permissions::Full);
+        // Nothing says what an exec command may do
+        assert_eq!(for_backend("script", &config, None), Permissions::Full);
+        // Ollama can't act, but its fallback can
+        assert_eq!(
+            for_backend("ollama", &config, Some(Permissions::WorkspaceWrite)),
+            Permissions::WorkspaceWrite
+        );
+    }
+}
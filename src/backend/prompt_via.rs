@@ -0,0 +1,163 @@
+//! How agentic CLIs receive the prompt
+//!
+//! A prompt passed as one argument is capped by the OS (128 KiB per argument on
+//! Linux, `E2BIG` beyond it) and shows up in `ps`. `prompt_via` on a backend
+//! picks another way in:
+//!
+//! - `arg`: the last argument, as before
+//! - `stdin`: written to the CLI's stdin through a pipe
+//! - `file`: written to a temp file that is fed to the CLI as its stdin instead
+//!   of a pipe, and removed once the CLI exits. The CLI reads the same input as
+//!   with `stdin`; it is never given the file's path
+//!
+//! Unset, prompts go as an argument up to [`ARG_LIMIT`] bytes and on stdin
+//! beyond it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Output, Stdio};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+/// Largest prompt passed as an argument when `prompt_via` isn't set, well
+/// under Linux's 128 KiB per argument so the rest of the command fits too
+pub const ARG_LIMIT: usize = 32 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptVia {
+    Arg,
+    Stdin,
+    File,
+}
+
+impl PromptVia {
+    /// The configured way, or the one that fits the prompt
+    pub fn choose(configured: Option<PromptVia>, prompt: &str) -> PromptVia {
+        match configured {
+            Some(via) => via,
+            None if prompt.len() > ARG_LIMIT => PromptVia::Stdin,
+            None => PromptVia::Arg,
+        }
+    }
+}
+
+/// A prompt on its way to a CLI process
+pub struct PromptInput {
+    prompt: String,
+    via: PromptVia,
+    /// Deleted when dropped, so it must outlive the process reading it
+    file: Option<NamedTempFile>,
+}
+
+impl PromptInput {
+    pub fn new(configured: Option<PromptVia>, prompt: String) -> Result<Self> {
+        let via = PromptVia::choose(configured, &prompt);
+        let file = match via {
+            PromptVia::File => {
+                let mut file = tempfile::Builder::new()
+                    .prefix("lok-prompt-")
+                    .suffix(".txt")
+                    .tempfile()
+                    .context("Failed to create prompt file")?;
+                file.write_all(prompt.as_bytes())
+                    .and_then(|()| file.flush())
+                    .context("Failed to write prompt file")?;
+                Some(file)
+            }
+            _ => None,
+        };
+        Ok(Self { prompt, via, file })
+    }
+
+    /// The prompt, when it goes on the command line
+    pub fn arg(&self) -> Option<&str> {
+        (self.via == PromptVia::Arg).then_some(self.prompt.as_str())
+    }
+
+    /// What the CLI's stdin should be
+    pub fn stdin(&self) -> Result<Stdio> {
+        Ok(match (self.via, &self.file) {
+            (PromptVia::Stdin, _) => Stdio::piped(),
+            (PromptVia::File, Some(file)) => {
+                Stdio::from(file.reopen().context("Failed to open prompt file")?)
+            }
+            _ => Stdio::null(),
+        })
+    }
+
+    /// Write the prompt to a spawned CLI's stdin pipe, in the background so a
+    /// CLI that prints before it has read everything can't block on stdout
+    pub fn feed(&self, child: &mut Child) -> Option<JoinHandle<std::io::Result<()>>> {
+        let mut stdin = child.stdin.take()?;
+        let prompt = self.prompt.clone();
+        Some(tokio::spawn(async move {
+            match stdin.write_all(prompt.as_bytes()).await {
+                // The CLI exited without reading stdin; its exit status tells the story
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            }
+            // Dropping stdin closes the pipe so the CLI sees EOF
+        }))
+    }
+
+    /// Run `cmd` to completion with the prompt on its way in, like `Command::output`
+    pub async fn output(&self, cmd: &mut Command) -> Result<Output> {
+        let mut child = cmd.stdin(self.stdin()?).spawn()?;
+        let writer = self.feed(&mut child);
+        let output = child.wait_with_output().await?;
+        if let Some(writer) = writer {
+            writer.await?.context("Failed to write prompt to stdin")?;
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_by_size() {
+        assert_eq!(PromptVia::choose(None, "short"), PromptVia::Arg);
+        let long = "x".repeat(ARG_LIMIT + 1);
+        assert_eq!(PromptVia::choose(None, &long), PromptVia::Stdin);
+        // A configured way is used whatever the size
+        assert_eq!(
+            PromptVia::choose(Some(PromptVia::Arg), &long),
+            PromptVia::Arg
+        );
+        assert_eq!(
+            PromptVia::choose(Some(PromptVia::File), "short"),
+            PromptVia::File
+        );
+    }
+
+    #[tokio::test]
+    async fn test_large_prompt_reaches_cli() {
+        // Over Linux's per-argument limit
+        let prompt = "diff line\n".repeat(20_000);
+        for via in [PromptVia::Stdin, PromptVia::File] {
+            let input = PromptInput::new(Some(via), prompt.clone()).unwrap();
+            assert!(input.arg().is_none());
+            let output = input
+                .output(Command::new("wc").arg("-c").stdout(Stdio::piped()))
+                .await
+                .unwrap();
+            let count = String::from_utf8_lossy(&output.stdout);
+            assert_eq!(count.trim(), prompt.len().to_string(), "{:?}", via);
+        }
+    }
+
+    #[test]
+    fn test_prompt_file_removed_on_drop() {
+        let input = PromptInput::new(Some(PromptVia::File), "secret".to_string()).unwrap();
+        let path = input.file.as_ref().unwrap().path().to_path_buf();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "secret");
+        drop(input);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,381 @@
+//! Query request - the prompt plus generation options sent to a backend
+
+use super::attachment::{self, Attachment};
+use super::permissions::Permissions;
+use serde::{Deserialize, Serialize};
+
+/// Hint for the shape of the answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Who said a turn of a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    /// The result of a tool call the assistant asked for
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+/// A function the model may call, described by a JSON Schema for its input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
+/// A tool call the model asked for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Provider-assigned id, echoed back with the result
+    pub id: String,
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+/// One turn of a conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// Calls made in an assistant turn
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Id of the call a tool turn answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Name of the tool a tool turn answers (Ollama matches results by name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+    /// Files sent with a user turn
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+impl Message {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            tool_name: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            tool_name: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// An assistant turn that asked for tool calls
+    pub fn assistant_with_calls(content: impl Into<String>, calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls: calls,
+            ..Self::assistant(content)
+        }
+    }
+
+    /// The result of `call`
+    pub fn tool_result(call: &ToolCall, content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Tool,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: Some(call.id.clone()),
+            tool_name: Some(call.name.clone()),
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Content with the attachments written out after it, for backends that
+    /// can't send them natively. `images` also names image attachments;
+    /// leave it off for backends that send images on their own.
+    pub fn content_with_attachments(&self, images: bool) -> String {
+        let inlined: Vec<&Attachment> = self
+            .attachments
+            .iter()
+            .filter(|a| images || !a.is_image())
+            .collect();
+        attachment::inline(&self.content, &inlined)
+    }
+
+    /// Media type and base64 data of each image attachment, for backends
+    /// that send images natively
+    pub fn images(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attachments.iter().filter_map(|a| match a {
+            Attachment::Image {
+                media_type, data, ..
+            } => Some((media_type.as_str(), data.as_str())),
+            Attachment::Text { .. } => None,
+        })
+    }
+}
+
+/// Instruction added for backends without a native JSON mode
+const JSON_INSTRUCTION: &str =
+    "Respond with valid JSON only, without markdown fences or commentary.";
+
+/// Everything sent to a backend for one query.
+///
+/// Backends map each option to their native setting where one exists; options
+/// a backend can't express are ignored (e.g. temperature for CLI agents).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryRequest {
+    pub prompt: String,
+    pub system: Option<String>,
+    pub temperature: Option<f32>,
+    /// Maximum output tokens
+    pub max_tokens: Option<u32>,
+    /// Stop sequences
+    pub stop: Vec<String>,
+    pub response_format: ResponseFormat,
+    /// Earlier turns, oldest first; `prompt` is the new user turn
+    pub history: Vec<Message>,
+    /// Backend-side session to continue (e.g. a Claude CLI session id)
+    pub session: Option<String>,
+    /// Tools the model may call, for backends that `supports_tools`
+    pub tools: Vec<Tool>,
+    /// What an agentic CLI may do for this query, instead of its configured `permissions`
+    pub permissions: Option<Permissions>,
+    /// Files sent with `prompt`
+    pub attachments: Vec<Attachment>,
+}
+
+impl QueryRequest {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Same options with a different prompt
+    pub fn with_prompt(&self, prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            ..self.clone()
+        }
+    }
+
+    pub fn wants_json(&self) -> bool {
+        self.response_format == ResponseFormat::Json
+    }
+
+    /// System prompt including the JSON instruction, for APIs with a system
+    /// prompt but no JSON mode (Claude, Bedrock)
+    pub fn system_with_format_hint(&self) -> Option<String> {
+        match (&self.system, self.wants_json()) {
+            (Some(system), true) => Some(format!("{}\n\n{}", system, JSON_INSTRUCTION)),
+            (Some(system), false) => Some(system.clone()),
+            (None, true) => Some(JSON_INSTRUCTION.to_string()),
+            (None, false) => None,
+        }
+    }
+
+    /// Whether this request only hands tool results back, with no new user turn
+    fn continues_tool_calls(&self) -> bool {
+        self.prompt.is_empty() && self.history.last().is_some_and(|m| m.role == Role::Tool)
+    }
+
+    /// History followed by the new user turn, for message-array APIs
+    pub fn messages(&self) -> Vec<Message> {
+        let mut messages = self.history.clone();
+        if !self.continues_tool_calls() {
+            messages.push(self.user_turn());
+        }
+        messages
+    }
+
+    /// The new user turn, with its attachments
+    fn user_turn(&self) -> Message {
+        Message {
+            attachments: self.attachments.clone(),
+            ..Message::user(self.prompt.clone())
+        }
+    }
+
+    /// Prompt with its attachments written out after it
+    pub fn prompt_with_attachments(&self) -> String {
+        self.user_turn().content_with_attachments(true)
+    }
+
+    /// Prompt with earlier turns written out as a transcript, for backends
+    /// that can't take a message list
+    pub fn prompt_with_history(&self) -> String {
+        let prompt = self.prompt_with_attachments();
+        if self.history.is_empty() {
+            return prompt;
+        }
+
+        let mut text = String::from("Conversation so far:\n\n");
+        for message in &self.history {
+            text.push_str(&format!("[{}]\n", message.role.as_str()));
+            let content = message.content_with_attachments(true);
+            if !content.is_empty() {
+                text.push_str(&format!("{}\n", content));
+            }
+            for call in &message.tool_calls {
+                text.push_str(&format!("(called {} with {})\n", call.name, call.input));
+            }
+            text.push('\n');
+        }
+        if self.continues_tool_calls() {
+            text.truncate(text.trim_end().len());
+        } else {
+            text.push_str(&format!("[user]\n{}", prompt));
+        }
+        text
+    }
+
+    /// Single prompt text for CLIs that have no separate system prompt
+    pub fn flattened_prompt(&self) -> String {
+        match self.system_with_format_hint() {
+            Some(system) => format!("{}\n\n{}", system, self.prompt_with_history()),
+            None => self.prompt_with_history(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flattened_prompt_plain() {
+        assert_eq!(QueryRequest::new("hi").flattened_prompt(), "hi");
+    }
+
+    #[test]
+    fn test_flattened_prompt_with_system_and_json() {
+        let request = QueryRequest {
+            system: Some("You are a reviewer.".to_string()),
+            response_format: ResponseFormat::Json,
+            ..QueryRequest::new("list bugs")
+        };
+        let flat = request.flattened_prompt();
+        assert!(flat.starts_with("You are a reviewer.\n\n"));
+        assert!(flat.contains(JSON_INSTRUCTION));
+        assert!(flat.ends_with("list bugs"));
+    }
+
+    #[test]
+    fn test_flattened_prompt_with_history() {
+        let request = QueryRequest {
+            history: vec![Message::user("What is 2+2?"), Message::assistant("4")],
+            ..QueryRequest::new("And doubled?")
+        };
+        let flat = request.flattened_prompt();
+        assert!(flat.contains("[user]\nWhat is 2+2?"));
+        assert!(flat.contains("[assistant]\n4"));
+        assert!(flat.ends_with("[user]\nAnd doubled?"));
+
+        let messages = request.messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2], Message::user("And doubled?"));
+    }
+
+    #[test]
+    fn test_tool_results_continue_without_prompt() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "lookup".to_string(),
+            input: serde_json::json!({"q": "x"}),
+        };
+        let request = QueryRequest {
+            history: vec![
+                Message::user("find x"),
+                Message::assistant_with_calls("Looking.", vec![call.clone()]),
+                Message::tool_result(&call, "x is 4"),
+            ],
+            ..QueryRequest::new("")
+        };
+
+        let messages = request.messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2].role, Role::Tool);
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("call_1"));
+
+        let flat = request.prompt_with_history();
+        assert!(flat.contains("(called lookup with {\"q\":\"x\"})"));
+        assert!(flat.ends_with("[tool]\nx is 4"));
+    }
+
+    #[test]
+    fn test_attachments_follow_prompt() {
+        let request = QueryRequest {
+            attachments: vec![
+                Attachment::Text {
+                    path: "src/lib.rs".to_string(),
+                    content: "pub fn f() {}\n".to_string(),
+                },
+                Attachment::Image {
+                    path: "shot.png".to_string(),
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw==".to_string(),
+                },
+            ],
+            ..QueryRequest::new("review")
+        };
+        assert_eq!(
+            request.flattened_prompt(),
+            "review\n\n--- BEGIN FILE src/lib.rs ---\npub fn f() {}\n--- END FILE src/lib.rs ---\n\n--- IMAGE shot.png ---"
+        );
+
+        // Message APIs get them on the new user turn, to send natively
+        let messages = request.messages();
+        assert_eq!(messages[0].attachments.len(), 2);
+        assert!(!messages[0].content_with_attachments(false).contains("IMAGE"));
+        assert_eq!(
+            messages[0].images().collect::<Vec<_>>(),
+            [("image/png", "iVBORw==")]
+        );
+    }
+
+    #[test]
+    fn test_with_prompt_keeps_options() {
+        let base = QueryRequest {
+            system: Some("sys".to_string()),
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        let request = base.with_prompt("p");
+        assert_eq!(request.prompt, "p");
+        assert_eq!(request.system.as_deref(), Some("sys"));
+        assert_eq!(request.max_tokens, Some(100));
+    }
+
+    #[test]
+    fn test_response_format_deserialize() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            format: ResponseFormat,
+        }
+        let w: Wrapper = toml::from_str("format = \"json\"").unwrap();
+        assert_eq!(w.format, ResponseFormat::Json);
+    }
+}
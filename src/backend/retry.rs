@@ -0,0 +1,196 @@
+//! Structured HTTP errors and the backoff hints they carry
+//!
+//! HTTP backends fail with `HttpError`, which keeps the status code and how
+//! long the provider asked us to wait: `retry-after-ms`, `Retry-After`
+//! (seconds or an HTTP date), or on a 429 from the Claude API the reset time
+//! of whichever `anthropic-ratelimit-*` limit ran out. Retry loops use
+//! `retry_after` to sleep that long instead of guessing.
+
+use super::circuit::CircuitOpen;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Limits the Claude API reports as `anthropic-ratelimit-<limit>-remaining` / `-reset`
+const ANTHROPIC_LIMITS: &[&str] = &["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// A non-success response from an HTTP backend
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} error {status}: {body}")]
+pub struct HttpError {
+    /// e.g. "Claude API", "Ollama"
+    pub provider: String,
+    pub status: u16,
+    pub body: String,
+    /// How long the provider asked us to wait before trying again
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpError {
+    /// Read the status, backoff headers and body of a failed response
+    pub async fn from_response(provider: &str, response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = backoff_hint(
+            status,
+            |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string)
+            },
+            Utc::now(),
+        );
+        let body = response.text().await.unwrap_or_default();
+        Self {
+            provider: provider.to_string(),
+            status,
+            body,
+            retry_after,
+        }
+    }
+}
+
+/// How long the provider asked us to wait, from the response headers
+/// (looked up case-insensitively by `header`)
+pub fn backoff_hint(
+    status: u16,
+    header: impl Fn(&str) -> Option<String>,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    // Waits too long for a Duration (`inf`, `1e400`) are ignored
+    let secs = |value: f64| Duration::try_from_secs_f64(value.max(0.0)).ok();
+
+    if let Some(wait) = header("retry-after-ms")
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .and_then(|ms| secs(ms / 1000.0))
+    {
+        return Some(wait);
+    }
+
+    if let Some(value) = header("retry-after") {
+        let value = value.trim();
+        if let Ok(value) = value.parse::<f64>() {
+            return secs(value);
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(until(date.with_timezone(&Utc), now));
+        }
+    }
+
+    // Without Retry-After, wait for the exhausted limit to reset
+    if status == 429 {
+        return ANTHROPIC_LIMITS
+            .iter()
+            .filter(|limit| {
+                header(&format!("anthropic-ratelimit-{}-remaining", limit)).as_deref() == Some("0")
+            })
+            .filter_map(|limit| header(&format!("anthropic-ratelimit-{}-reset", limit)))
+            .filter_map(|reset| DateTime::parse_from_rfc3339(reset.trim()).ok())
+            .map(|reset| until(reset.with_timezone(&Utc), now))
+            .max();
+    }
+
+    None
+}
+
+fn until(time: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    (time - now).to_std().unwrap_or_default()
+}
+
+/// How long to wait before retrying after `error`, if something said so:
+/// the provider's backoff hint, or the cooldown of an open circuit
+pub fn retry_after(error: &anyhow::Error) -> Option<Duration> {
+    error.chain().find_map(|cause| {
+        if let Some(http) = cause.downcast_ref::<HttpError>() {
+            http.retry_after
+        } else {
+            cause
+                .downcast_ref::<CircuitOpen>()
+                .map(|open| open.retry_in)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn hint(status: u16, headers: &[(&str, &str)], now: DateTime<Utc>) -> Option<Duration> {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        backoff_hint(status, |name| headers.get(name).cloned(), now)
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-01-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_retry_after_seconds_and_ms() {
+        assert_eq!(
+            hint(429, &[("retry-after", "7")], now()),
+            Some(Duration::from_secs(7))
+        );
+        // The millisecond header is more precise, so it wins
+        assert_eq!(
+            hint(429, &[("retry-after", "7"), ("retry-after-ms", "6500")], now()),
+            Some(Duration::from_millis(6500))
+        );
+    }
+
+    #[test]
+    fn test_retry_after_out_of_range() {
+        assert_eq!(hint(429, &[("retry-after", "inf")], now()), None);
+        assert_eq!(hint(429, &[("retry-after", "1e400")], now()), None);
+        // An unusable millisecond header falls back to the seconds one
+        assert_eq!(
+            hint(429, &[("retry-after", "7"), ("retry-after-ms", "1e400")], now()),
+            Some(Duration::from_secs(7))
+        );
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        assert_eq!(
+            hint(503, &[("retry-after", "Thu, 01 Jan 2026 12:00:30 GMT")], now()),
+            Some(Duration::from_secs(30))
+        );
+        // A date in the past means retry now
+        assert_eq!(
+            hint(503, &[("retry-after", "Thu, 01 Jan 2026 11:00:00 GMT")], now()),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_anthropic_reset_of_exhausted_limit() {
+        let headers = [
+            ("anthropic-ratelimit-requests-remaining", "10"),
+            ("anthropic-ratelimit-requests-reset", "2026-01-01T12:01:00Z"),
+            ("anthropic-ratelimit-tokens-remaining", "0"),
+            ("anthropic-ratelimit-tokens-reset", "2026-01-01T12:00:12Z"),
+        ];
+        assert_eq!(hint(429, &headers, now()), Some(Duration::from_secs(12)));
+        // Reset times only say when to retry on a rate limit
+        assert_eq!(hint(500, &headers, now()), None);
+        assert_eq!(hint(429, &[], now()), None);
+    }
+
+    #[test]
+    fn test_retry_after_sees_through_context() {
+        let error = anyhow::Error::new(HttpError {
+            provider: "Claude API".to_string(),
+            status: 429,
+            body: "rate limited".to_string(),
+            retry_after: Some(Duration::from_secs(3)),
+        })
+        .context("all fallbacks failed");
+        assert_eq!(retry_after(&error), Some(Duration::from_secs(3)));
+        assert_eq!(retry_after(&anyhow::anyhow!("HTTP 503")), None);
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers for streaming backend output
+
+use tokio::sync::mpsc;
+
+/// Receives incremental output chunks from `Backend::query_stream`
+pub type ChunkSender = mpsc::UnboundedSender<String>;
+
+/// Reassembles newline-delimited records (NDJSON, SSE, JSONL) from arbitrary byte chunks.
+///
+/// Bytes are buffered until a newline so multi-byte UTF-8 characters split across
+/// network chunks are decoded intact.
+#[derive(Default)]
+pub struct LineBuffer {
+    buf: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Append bytes and return every line completed by them (without the line ending)
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line[..line.len() - 1]);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+        lines
+    }
+
+    /// Return any trailing data that wasn't terminated by a newline
+    pub fn finish(&mut self) -> Option<String> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let rest = String::from_utf8_lossy(&self.buf).trim_end().to_string();
+        self.buf.clear();
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_buffer_split_chunks() {
+        let mut buf = LineBuffer::default();
+        assert!(buf.push(b"{\"a\":").is_empty());
+        assert_eq!(buf.push(b"1}\n{\"b\""), vec!["{\"a\":1}"]);
+        assert_eq!(buf.push(b":2}\r\n"), vec!["{\"b\":2}"]);
+        assert_eq!(buf.finish(), None);
+    }
+
+    #[test]
+    fn test_line_buffer_multibyte_boundary() {
+        let mut buf = LineBuffer::default();
+        let bytes = "héllo\n".as_bytes();
+        // Split inside the two-byte 'é'
+        assert!(buf.push(&bytes[..2]).is_empty());
+        assert_eq!(buf.push(&bytes[2..]), vec!["héllo"]);
+    }
+
+    #[test]
+    fn test_line_buffer_finish_trailing() {
+        let mut buf = LineBuffer::default();
+        assert!(buf.push(b"partial").is_empty());
+        assert_eq!(buf.finish(), Some("partial".to_string()));
+    }
+}
@@ -0,0 +1,198 @@
+//! Tool calling on every backend
+//!
+//! Backends with native tool use (Claude API, Ollama, OpenAI-compatible
+//! servers, Bedrock) get the tools in the request. CLI agents and `exec`
+//! backends get them described in the system prompt instead, answer with
+//! `<tool_call>` blocks, and those blocks are parsed back into `ToolCall`s.
+
+use super::{Backend, Completion, QueryRequest, Tool, ToolCall};
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+const CALL_OPEN: &str = "<tool_call>";
+const CALL_CLOSE: &str = "</tool_call>";
+
+/// Run one turn of a tool-calling conversation on any backend
+pub async fn complete_with_tools(
+    backend: &dyn Backend,
+    request: &QueryRequest,
+    cwd: &Path,
+) -> Result<Completion> {
+    if request.tools.is_empty() || backend.supports_tools() {
+        return backend.complete(request, cwd).await;
+    }
+
+    let prompted = QueryRequest {
+        system: Some(system_with_tools(request)),
+        tools: Vec::new(),
+        ..request.clone()
+    };
+    let mut completion = backend.complete(&prompted, cwd).await?;
+    let (text, calls) = parse_tool_calls(&completion.text);
+    completion.text = text;
+    completion.tool_calls = calls;
+    Ok(completion)
+}
+
+/// `tools` in the OpenAI function-calling format, which Ollama also uses
+pub(super) fn function_tools(tools: &[Tool]) -> Vec<serde_json::Value> {
+    tools
+        .iter()
+        .map(|tool| {
+            serde_json::json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.input_schema,
+                }
+            })
+        })
+        .collect()
+}
+
+/// The request's system prompt plus the tool descriptions and call format
+fn system_with_tools(request: &QueryRequest) -> String {
+    let mut system = match request.system {
+        Some(ref system) => format!("{}\n\n", system),
+        None => String::new(),
+    };
+
+    system.push_str("You can call these tools:\n\n");
+    for tool in &request.tools {
+        system.push_str(&format!(
+            "- {}: {}\n  Input (JSON Schema): {}\n",
+            tool.name, tool.description, tool.input_schema
+        ));
+    }
+    system.push_str(&format!(
+        "\nTo call a tool, reply with one block per call, each holding a single JSON object:\n\n\
+         {}\n{{\"name\": \"<tool name>\", \"input\": {{...}}}}\n{}\n\n\
+         The results come back in the next message. Once you can answer, reply without any tool_call blocks.",
+        CALL_OPEN, CALL_CLOSE
+    ));
+    system
+}
+
+#[derive(Deserialize)]
+struct PromptedCall {
+    name: String,
+    #[serde(default)]
+    input: serde_json::Value,
+}
+
+/// Split `<tool_call>` blocks out of an answer. Blocks that aren't valid
+/// calls are left in the text.
+fn parse_tool_calls(text: &str) -> (String, Vec<ToolCall>) {
+    let mut remaining = String::new();
+    let mut calls = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(CALL_OPEN) {
+        let body_start = start + CALL_OPEN.len();
+        let (body, after) = match rest[body_start..].find(CALL_CLOSE) {
+            Some(end) => (
+                &rest[body_start..body_start + end],
+                &rest[body_start + end + CALL_CLOSE.len()..],
+            ),
+            None => (&rest[body_start..], ""),
+        };
+
+        let json = body
+            .trim()
+            .trim_start_matches("```json")
+            .trim_matches('`')
+            .trim();
+        match serde_json::from_str::<PromptedCall>(json) {
+            Ok(call) => {
+                remaining.push_str(&rest[..start]);
+                calls.push(ToolCall {
+                    id: format!("call_{}", calls.len() + 1),
+                    name: call.name,
+                    input: call.input,
+                });
+            }
+            Err(_) => remaining.push_str(&rest[..rest.len() - after.len()]),
+        }
+        rest = after;
+    }
+    remaining.push_str(rest);
+
+    (remaining.trim().to_string(), calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_parse_tool_calls() {
+        let text = "Let me check.\n<tool_call>\n{\"name\": \"query_backend\", \"input\": {\"backend\": \"codex\", \"prompt\": \"hi\"}}\n</tool_call>\n<tool_call>```json\n{\"name\": \"query_backend\", \"input\": {}}\n```</tool_call>";
+        let (rest, calls) = parse_tool_calls(text);
+        assert_eq!(rest, "Let me check.");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].input["backend"], "codex");
+        assert_eq!(calls[1].id, "call_2");
+    }
+
+    #[test]
+    fn test_invalid_block_stays_in_text() {
+        let (rest, calls) = parse_tool_calls("Use <tool_call>like this</tool_call> to call.");
+        assert!(calls.is_empty());
+        assert_eq!(rest, "Use <tool_call>like this</tool_call> to call.");
+    }
+
+    /// Text-only backend that remembers the system prompt it was given
+    struct Prompted {
+        system: Mutex<Option<String>>,
+    }
+
+    #[async_trait]
+    impl Backend for Prompted {
+        fn name(&self) -> &str {
+            "prompted"
+        }
+
+        async fn complete(&self, request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+            *self.system.lock().unwrap() = request.system.clone();
+            Ok("<tool_call>{\"name\": \"lookup\", \"input\": {\"q\": \"x\"}}</tool_call>"
+                .to_string()
+                .into())
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompted_tool_calls() {
+        let backend = Prompted {
+            system: Mutex::new(None),
+        };
+        let request = QueryRequest {
+            system: Some("Be brief.".to_string()),
+            tools: vec![Tool {
+                name: "lookup".to_string(),
+                description: "Look something up".to_string(),
+                input_schema: serde_json::json!({"type": "object"}),
+            }],
+            ..QueryRequest::new("find x")
+        };
+
+        let completion = complete_with_tools(&backend, &request, Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "");
+        assert_eq!(completion.tool_calls[0].name, "lookup");
+        assert_eq!(completion.tool_calls[0].input["q"], "x");
+
+        let system = backend.system.lock().unwrap().clone().unwrap();
+        assert!(system.starts_with("Be brief."));
+        assert!(system.contains("- lookup: Look something up"));
+    }
+}
@@ -0,0 +1,162 @@
+//! What an agentic backend did while answering
+//!
+//! Codex reports every step of a run as `exec --json` events: the commands it
+//! ran, files it changed, reasoning summaries, MCP tool calls, web searches and
+//! token usage per turn. `Trace` keeps them in order so a run can be audited.
+//! Codex reads files through commands (`cat`, `sed -n`, `rg`...), so the files
+//! it looked at show up as `Command` events.
+
+use crate::usage::{self, Usage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Events of one or more agent runs, in the order they happened
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Trace {
+    pub events: Vec<TraceEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// A reasoning summary
+    Reasoning {
+        text: String,
+    },
+    /// A message to the user; the last one is the answer
+    Message {
+        text: String,
+    },
+    /// A shell command and what it printed
+    Command {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i64>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        output: String,
+    },
+    /// A file the agent added, deleted or updated
+    FileChange {
+        path: String,
+        kind: String,
+    },
+    /// A call to an MCP server's tool
+    ToolCall {
+        server: String,
+        tool: String,
+    },
+    WebSearch {
+        query: String,
+    },
+    Error {
+        message: String,
+    },
+    /// End of a turn, with the tokens it used
+    TurnCompleted {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
+    },
+}
+
+impl Trace {
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// Add `trace` to the end of `into`, e.g. for a step queried more than once
+pub fn append(into: &mut Option<Trace>, trace: Option<Trace>) {
+    if let Some(trace) = trace {
+        into.get_or_insert_with(Trace::default)
+            .events
+            .extend(trace.events);
+    }
+}
+
+/// One line per event; multi-line text is indented under its event.
+/// Command output is left out, it's in the recording (see `--record`).
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let line = match event {
+                TraceEvent::Reasoning { text } => format!("reasoning: {}", text.trim()),
+                TraceEvent::Message { text } => format!("message: {}", text.trim()),
+                TraceEvent::Command {
+                    command, exit_code, ..
+                } => match exit_code {
+                    Some(code) => format!("$ {} (exit {})", command, code),
+                    None => format!("$ {}", command),
+                },
+                TraceEvent::FileChange { path, kind } => format!("{} {}", kind, path),
+                TraceEvent::ToolCall { server, tool } => format!("tool: {}.{}", server, tool),
+                TraceEvent::WebSearch { query } => format!("search: {}", query),
+                TraceEvent::Error { message } => format!("error: {}", message),
+                TraceEvent::TurnCompleted { usage } => match usage {
+                    Some(u) => format!("turn completed ({} tokens)", usage::format_tokens(u)),
+                    None => "turn completed".to_string(),
+                },
+            };
+            write!(f, "{}", line.replace('\n', "\n  "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_one_line_per_event() {
+        let trace = Trace {
+            events: vec![
+                TraceEvent::Reasoning {
+                    text: "**Reading**\nlooking at main".to_string(),
+                },
+                TraceEvent::Command {
+                    command: "bash -lc 'cat src/main.rs'".to_string(),
+                    exit_code: Some(0),
+                    output: "fn main() {}".to_string(),
+                },
+                TraceEvent::FileChange {
+                    path: "src/lib.rs".to_string(),
+                    kind: "update".to_string(),
+                },
+                TraceEvent::TurnCompleted {
+                    usage: Some(Usage::new(1200, 7)),
+                },
+            ],
+        };
+        assert_eq!(
+            trace.to_string(),
+            "reasoning: **Reading**\n  looking at main\n\
+             $ bash -lc 'cat src/main.rs' (exit 0)\n\
+             update src/lib.rs\n\
+             turn completed (1,200 in / 7 out tokens)"
+        );
+    }
+
+    #[test]
+    fn test_append_and_serde() {
+        let mut into = None;
+        append(&mut into, None);
+        assert!(into.is_none());
+
+        let trace = Trace {
+            events: vec![TraceEvent::WebSearch {
+                query: "tokio timeout".to_string(),
+            }],
+        };
+        append(&mut into, Some(trace.clone()));
+        append(&mut into, Some(trace.clone()));
+        assert_eq!(into.as_ref().unwrap().events.len(), 2);
+
+        let json = serde_json::to_string(&trace).unwrap();
+        assert_eq!(json, r#"[{"type":"web_search","query":"tokio timeout"}]"#);
+        assert_eq!(serde_json::from_str::<Trace>(&json).unwrap(), trace);
+    }
+}
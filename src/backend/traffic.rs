@@ -0,0 +1,459 @@
+//! Record and replay backend traffic (`--record <dir>` / `--replay <dir>`)
+//!
+//! Recording appends every exchange to `<dir>/<backend>.jsonl`: the backend
+//! name, working directory, rendered prompt (system prompt and conversation
+//! included) and the answer, error or timeout. Replaying serves those
+//! exchanges back without creating the real backends. Repeated prompts are
+//! answered in the order they were recorded, so a retry that failed once and
+//! then succeeded replays the same way; once a prompt's recordings run out the
+//! last one repeats. Exchanges recorded in another directory are used when
+//! nothing matches the current one, so recordings can be replayed elsewhere.
+
+use super::stream::ChunkSender;
+use super::{Backend, Completion, Fallback, QueryRequest, ToolCall, Trace};
+use crate::usage::Usage;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Set from `--record`/`--replay` on the command line
+#[derive(Clone)]
+pub enum Traffic {
+    Record(Arc<Recorder>),
+    Replay(Arc<Replayer>),
+}
+
+impl Traffic {
+    pub fn record(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create record directory: {}", dir.display()))?;
+        Ok(Self::Record(Arc::new(Recorder {
+            dir: dir.to_path_buf(),
+            lock: Mutex::new(()),
+        })))
+    }
+
+    pub fn replay(dir: &Path) -> Result<Self> {
+        Ok(Self::Replay(Arc::new(Replayer::load(dir)?)))
+    }
+}
+
+impl fmt::Debug for Traffic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Record(r) => write!(f, "Record({})", r.dir.display()),
+            Self::Replay(r) => write!(f, "Replay({})", r.dir.display()),
+        }
+    }
+}
+
+/// One recorded request and what came back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Exchange {
+    backend: String,
+    cwd: String,
+    prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The request was cancelled before answering, usually by a timeout
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    timeout: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fallback: Option<Fallback>,
+    /// Names of the tools the request offered natively (see `Backend::supports_tools`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace: Option<Trace>,
+}
+
+impl Exchange {
+    fn new(backend: &str, request: &QueryRequest, cwd: &Path) -> Self {
+        Self {
+            backend: backend.to_string(),
+            cwd: cwd.to_string_lossy().into_owned(),
+            prompt: request.flattened_prompt(),
+            response: None,
+            error: None,
+            timeout: false,
+            usage: None,
+            fallback: None,
+            tools: request.tools.iter().map(|t| t.name.clone()).collect(),
+            tool_calls: Vec::new(),
+            trace: None,
+        }
+    }
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    /// Parallel steps append to the same files
+    lock: Mutex<()>,
+}
+
+impl Recorder {
+    fn append(&self, exchange: &Exchange) {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let path = self.dir.join(format!("{}.jsonl", exchange.backend));
+        let written = serde_json::to_string(exchange)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?;
+                writeln!(file, "{}", line)?;
+                Ok(())
+            });
+        if let Err(e) = written {
+            eprintln!("warning: failed to record to {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub struct Replayer {
+    dir: PathBuf,
+    exchanges: Vec<Exchange>,
+    /// How many times each (backend, cwd, prompt) has been served
+    served: Mutex<HashMap<(String, String, String), usize>>,
+}
+
+impl Replayer {
+    fn load(dir: &Path) -> Result<Self> {
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read replay directory: {}", dir.display()))?;
+
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect();
+        paths.sort();
+
+        let mut exchanges = Vec::new();
+        for path in paths {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            for (n, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let exchange: Exchange = serde_json::from_str(line).with_context(|| {
+                    format!("Invalid recording at {}:{}", path.display(), n + 1)
+                })?;
+                exchanges.push(exchange);
+            }
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            exchanges,
+            served: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether `backend` was recorded answering tool calls natively, so replay
+    /// hands it the same requests
+    fn has_native_tools(&self, backend: &str) -> bool {
+        self.exchanges
+            .iter()
+            .any(|e| e.backend == backend && !e.tools.is_empty())
+    }
+
+    /// The next recorded exchange for this request, preferring the same cwd
+    fn next(&self, backend: &str, cwd: &str, prompt: &str) -> Option<&Exchange> {
+        let same_prompt = |e: &&Exchange| e.backend == backend && e.prompt == prompt;
+        let mut matches: Vec<&Exchange> = self
+            .exchanges
+            .iter()
+            .filter(same_prompt)
+            .filter(|e| e.cwd == cwd)
+            .collect();
+        if matches.is_empty() {
+            matches = self.exchanges.iter().filter(same_prompt).collect();
+        }
+        let last = matches.len().checked_sub(1)?;
+
+        let key = (backend.to_string(), cwd.to_string(), prompt.to_string());
+        let mut served = self.served.lock().unwrap_or_else(|e| e.into_inner());
+        let count = served.entry(key).or_default();
+        let exchange = matches[(*count).min(last)];
+        *count += 1;
+        Some(exchange)
+    }
+}
+
+/// Records every exchange with the backend it wraps
+pub struct RecordingBackend {
+    inner: Arc<dyn Backend>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Arc<dyn Backend>, recorder: Arc<Recorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+/// Writes the exchange when the request finishes, or as a timeout if it is
+/// dropped first
+struct Pending<'a> {
+    recorder: &'a Recorder,
+    exchange: Option<Exchange>,
+}
+
+impl Pending<'_> {
+    fn finish(mut self, result: Result<Completion>) -> Result<Completion> {
+        if let Some(mut exchange) = self.exchange.take() {
+            match &result {
+                Ok(completion) => {
+                    exchange.response = Some(completion.text.clone());
+                    exchange.usage = completion.usage;
+                    exchange.fallback = completion.fallback.clone();
+                    exchange.tool_calls = completion.tool_calls.clone();
+                    exchange.trace = completion.trace.clone();
+                }
+                Err(e) => exchange.error = Some(format!("{:#}", e)),
+            }
+            self.recorder.append(&exchange);
+        }
+        result
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(mut exchange) = self.exchange.take() {
+            exchange.timeout = true;
+            self.recorder.append(&exchange);
+        }
+    }
+}
+
+impl RecordingBackend {
+    fn start(&self, request: &QueryRequest, cwd: &Path) -> Pending<'_> {
+        Pending {
+            recorder: &self.recorder,
+            exchange: Some(Exchange::new(self.inner.name(), request, cwd)),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for RecordingBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let pending = self.start(request, cwd);
+        pending.finish(self.inner.complete(request, cwd).await)
+    }
+
+    async fn query_stream(
+        &self,
+        request: &QueryRequest,
+        cwd: &Path,
+        chunks: ChunkSender,
+    ) -> Result<Completion> {
+        let pending = self.start(request, cwd);
+        pending.finish(self.inner.query_stream(request, cwd, chunks).await)
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    async fn health(&self) -> super::Health {
+        self.inner.health().await
+    }
+}
+
+/// Stands in for a backend, answering from a recording
+pub struct ReplayBackend {
+    name: String,
+    replayer: Arc<Replayer>,
+}
+
+impl ReplayBackend {
+    pub fn new(name: &str, replayer: Arc<Replayer>) -> Self {
+        Self {
+            name: name.to_string(),
+            replayer,
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for ReplayBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn complete(&self, request: &QueryRequest, cwd: &Path) -> Result<Completion> {
+        let prompt = request.flattened_prompt();
+        let cwd = cwd.to_string_lossy();
+        let Some(exchange) = self.replayer.next(&self.name, &cwd, &prompt) else {
+            anyhow::bail!(
+                "{}: no recorded response for this prompt in {}",
+                self.name,
+                self.replayer.dir.display()
+            );
+        };
+
+        if exchange.timeout {
+            // Replay the hang so the caller's timeout fires again
+            std::future::pending::<()>().await;
+        }
+        if let Some(ref error) = exchange.error {
+            anyhow::bail!("{}", error);
+        }
+
+        Ok(Completion {
+            text: exchange.response.clone().unwrap_or_default(),
+            usage: exchange.usage,
+            fallback: exchange.fallback.clone(),
+            tool_calls: exchange.tool_calls.clone(),
+            trace: exchange.trace.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.replayer.has_native_tools(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails on its first call, then answers with the call count
+    struct Flaky {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Backend for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn complete(&self, _request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if call == 1 {
+                anyhow::bail!("HTTP 503: overloaded");
+            }
+            Ok(Completion {
+                text: format!("answer {}", call),
+                usage: Some(Usage::new(5, 2)),
+                ..Default::default()
+            })
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = Path::new("/work/project");
+
+        let Traffic::Record(recorder) = Traffic::record(dir.path()).unwrap() else {
+            unreachable!()
+        };
+        let recording = RecordingBackend::new(
+            Arc::new(Flaky {
+                calls: AtomicUsize::new(0),
+            }),
+            recorder,
+        );
+        assert!(recording.query("hi", cwd).await.is_err());
+        assert_eq!(recording.query("hi", cwd).await.unwrap(), "answer 2");
+        assert_eq!(recording.query("hi", cwd).await.unwrap(), "answer 3");
+        assert!(dir.path().join("flaky.jsonl").exists());
+
+        let Traffic::Replay(replayer) = Traffic::replay(dir.path()).unwrap() else {
+            unreachable!()
+        };
+        let replay = ReplayBackend::new("flaky", replayer);
+        let err = replay.query("hi", cwd).await.unwrap_err();
+        assert!(err.to_string().contains("503"));
+        let completion = replay
+            .complete(&QueryRequest::new("hi"), cwd)
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "answer 2");
+        assert_eq!(completion.usage, Some(Usage::new(5, 2)));
+        assert_eq!(replay.query("hi", cwd).await.unwrap(), "answer 3");
+        // Recordings ran out: the last one repeats
+        assert_eq!(replay.query("hi", cwd).await.unwrap(), "answer 3");
+
+        // Another directory still finds the recording
+        let elsewhere = Path::new("/somewhere/else");
+        assert!(replay.query("hi", elsewhere).await.is_err());
+
+        let err = replay.query("never asked", cwd).await.unwrap_err();
+        assert!(err.to_string().contains("no recorded response"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_replays_as_timeout() {
+        struct Hang;
+
+        #[async_trait]
+        impl Backend for Hang {
+            fn name(&self) -> &str {
+                "hang"
+            }
+
+            async fn complete(&self, _request: &QueryRequest, _cwd: &Path) -> Result<Completion> {
+                std::future::pending().await
+            }
+
+            fn is_available(&self) -> bool {
+                true
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = Path::new(".");
+        let Traffic::Record(recorder) = Traffic::record(dir.path()).unwrap() else {
+            unreachable!()
+        };
+        let recording = RecordingBackend::new(Arc::new(Hang), recorder);
+        let short = std::time::Duration::from_millis(10);
+        assert!(tokio::time::timeout(short, recording.query("hi", cwd))
+            .await
+            .is_err());
+
+        let Traffic::Replay(replayer) = Traffic::replay(dir.path()).unwrap() else {
+            unreachable!()
+        };
+        let replay = ReplayBackend::new("hang", replayer);
+        assert!(tokio::time::timeout(short, replay.query("hi", cwd))
+            .await
+            .is_err());
+    }
+}
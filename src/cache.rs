@@ -14,7 +14,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::backend::QueryResult;
+use crate::backend::{Fallback, QueryResult, Trace};
+use crate::usage::Usage;
 
 /// Cache operation types for warning context
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -100,6 +101,12 @@ struct CachedResult {
     output: String,
     success: bool,
     elapsed_ms: u64,
+    #[serde(default)]
+    usage: Option<Usage>,
+    #[serde(default)]
+    fallback: Option<Fallback>,
+    #[serde(default)]
+    trace: Option<Trace>,
 }
 
 impl From<&QueryResult> for CachedResult {
@@ -109,6 +116,9 @@ impl From<&QueryResult> for CachedResult {
             output: r.output.clone(),
             success: r.success,
             elapsed_ms: r.elapsed_ms,
+            usage: r.usage,
+            fallback: r.fallback.clone(),
+            trace: r.trace.clone(),
         }
     }
 }
@@ -120,6 +130,11 @@ impl From<CachedResult> for QueryResult {
             output: r.output,
             success: r.success,
             elapsed_ms: r.elapsed_ms,
+            usage: r.usage,
+            // Sessions aren't cached: a cached answer may outlive its session
+            session: None,
+            fallback: r.fallback,
+            trace: r.trace,
         }
     }
 }
@@ -350,6 +365,10 @@ mod tests {
             output: "output".to_string(),
             success: true,
             elapsed_ms: 100,
+            usage: None,
+            session: None,
+            fallback: None,
+            trace: None,
         }];
 
         // Should not cache when disabled
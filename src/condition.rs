@@ -0,0 +1,1111 @@
+//! Expressions for `when` conditions and prompt templates
+//!
+//! A small expression language over the results of earlier steps:
+//!
+//! ```text
+//! steps.check.success && len(steps.scan.output.findings) > 0
+//! contains(steps.review.output, "LGTM") || steps.review.output.score >= 8
+//! !matches(steps.plan.output, "(?i)no changes")
+//! "error" in steps.build.output and steps.build.output | lines | length > 3
+//! ```
+//!
+//! - `&&`, `||`, `!` (or `and`, `or`, `not`) and parentheses
+//! - `==`, `!=`, `<`, `<=`, `>`, `>=`. Strings compare without surrounding
+//!   whitespace; a string compared with a number is read as one.
+//! - `contains(a, b)` (also `a contains b` and `b in a`), `equals(a, b)`,
+//!   `len(x)` and `matches(x, "regex")`
+//! - filters, applied left to right: `x | default("none")`, `| truncate(200)`,
+//!   `| json`, `| lines`, `| first`, `| last`, `| join(", ")`, `| length`,
+//!   `| trim`, and `| raw`, which changes nothing except in shell commands
+//!   (see [`crate::template`])
+//! - `steps.NAME.output`, `steps.NAME.success`, and paths into a step's JSON
+//!   output: `steps.NAME.output.findings[0].severity`, or `steps.NAME.findings`
+//!   for short. `NAME.output` without `steps.` works too in conditions.
+//!
+//! Variables are resolved by a [`Scope`], so templates add their own (`env`,
+//! `arg`, loop variables). Anything else (unknown names or filters, missing
+//! operands, bad regexes) is a parse error, so workflows with broken
+//! conditions are rejected when loaded.
+
+use crate::workflow::{self, StepResult};
+use regex::Regex;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConditionError {
+    #[error("{message} at column {column}")]
+    Parse { message: String, column: usize },
+
+    #[error("unknown step '{0}'")]
+    UnknownStep(String),
+
+    #[error("unknown variable '{0}'")]
+    UnknownVariable(String),
+
+    /// The variable gets a value later, like `item` before a for_each
+    /// iteration
+    #[error("'{0}' has no value yet")]
+    Deferred(String),
+
+    #[error("{0}")]
+    Eval(String),
+}
+
+/// One step of a variable path: `.name`, or `[0]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// Resolves the variables an expression reads
+pub trait Scope {
+    /// The value at `path`, e.g. `steps`, `scan`, `output`, `findings`, or
+    /// None when the path leads nowhere
+    fn lookup(&self, path: &[Segment]) -> Result<Option<Value>, ConditionError>;
+}
+
+/// A parsed expression
+#[derive(Debug)]
+pub struct Expression {
+    expr: Expr,
+}
+
+/// A parsed `when` condition: an expression whose variables all name steps
+#[derive(Debug)]
+pub struct Condition {
+    expression: Expression,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Var { path: Vec<Segment>, column: usize },
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(CmpOp, Box<Expr>, Box<Expr>),
+    Contains(Box<Expr>, Box<Expr>),
+    Len(Box<Expr>),
+    Matches(Box<Expr>, Regex),
+    Filter(Box<Expr>, Filter, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Filter {
+    Default,
+    Truncate,
+    Json,
+    Lines,
+    First,
+    Last,
+    Join,
+    Length,
+    Trim,
+    /// Leaves the value as is; in shell commands it skips quoting
+    Raw,
+}
+
+impl Filter {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "default" => Filter::Default,
+            "truncate" => Filter::Truncate,
+            "json" => Filter::Json,
+            "lines" => Filter::Lines,
+            "first" => Filter::First,
+            "last" => Filter::Last,
+            "join" => Filter::Join,
+            "length" => Filter::Length,
+            "trim" => Filter::Trim,
+            "raw" => Filter::Raw,
+            _ => return None,
+        })
+    }
+
+    /// Fewest and most arguments
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Filter::Default | Filter::Truncate => (1, 1),
+            Filter::Join => (0, 1),
+            _ => (0, 0),
+        }
+    }
+
+    fn apply(self, value: Value, args: &[Value]) -> Result<Value, ConditionError> {
+        Ok(match self {
+            Filter::Default if !is_present(&value) => args[0].clone(),
+            Filter::Default => value,
+            Filter::Truncate => {
+                let limit =
+                    args[0].as_f64().filter(|n| *n >= 0.0).ok_or_else(|| {
+                        ConditionError::Eval("truncate() takes a count".to_string())
+                    })? as usize;
+                let text = display(&value);
+                if text.chars().count() <= limit {
+                    Value::String(text)
+                } else {
+                    let kept: String = text.chars().take(limit).collect();
+                    Value::String(format!("{}...", kept))
+                }
+            }
+            Filter::Json => Value::String(value.to_string()),
+            Filter::Lines => Value::Array(lines(&value).into_iter().map(Value::String).collect()),
+            Filter::First | Filter::Last => {
+                let mut items = match value {
+                    Value::Array(items) => items,
+                    Value::Null => Vec::new(),
+                    other => lines(&other).into_iter().map(Value::String).collect(),
+                };
+                let item = if self == Filter::First {
+                    (!items.is_empty()).then(|| items.swap_remove(0))
+                } else {
+                    items.pop()
+                };
+                item.unwrap_or_default()
+            }
+            Filter::Join => {
+                let separator = args.first().map_or(", ".to_string(), display);
+                match value {
+                    Value::Array(items) => Value::String(
+                        items
+                            .iter()
+                            .map(display)
+                            .collect::<Vec<_>>()
+                            .join(&separator),
+                    ),
+                    other => other,
+                }
+            }
+            Filter::Length => Value::from(len(&value)?),
+            Filter::Trim => Value::String(display(&value).trim().to_string()),
+            Filter::Raw => value,
+        })
+    }
+}
+
+/// Whether a value counts as present for `default`: anything but null and
+/// blank text
+fn is_present(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::String(s) => !s.trim().is_empty(),
+        _ => true,
+    }
+}
+
+/// Non-blank lines of a value's text
+fn lines(value: &Value) -> Vec<String> {
+    display(value)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.trim_end().to_string())
+        .collect()
+}
+
+/// A value as text: strings as they are, null as nothing, the rest as JSON
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `steps.scan.output.findings[0]` for a path
+pub fn path_to_string(path: &[Segment]) -> String {
+    let mut out = String::new();
+    for segment in path {
+        match segment {
+            Segment::Field(name) if out.is_empty() => out.push_str(name),
+            Segment::Field(name) => {
+                out.push('.');
+                out.push_str(name);
+            }
+            Segment::Index(i) => out.push_str(&format!("[{}]", i)),
+        }
+    }
+    out
+}
+
+/// Follow `path` into a JSON value. Numeric fields index arrays, so
+/// `arg.1`-style paths work on lists too.
+pub fn navigate(mut value: Value, path: &[Segment]) -> Option<Value> {
+    for segment in path {
+        value = match (segment, value) {
+            (Segment::Field(key), Value::Object(mut map)) => map.remove(key)?,
+            (Segment::Field(key), Value::Array(mut items)) => {
+                let i: usize = key.parse().ok()?;
+                (i < items.len()).then(|| items.swap_remove(i))?
+            }
+            (Segment::Index(i), Value::Array(mut items)) if *i < items.len() => {
+                items.swap_remove(*i)
+            }
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// A step's output, success, trace, or a path into its JSON output
+pub fn step_value(result: &StepResult, path: &[Segment]) -> Option<Value> {
+    let json_path = match path {
+        [Segment::Field(field)] if field == "success" => return Some(Value::Bool(result.success)),
+        [Segment::Field(field)] if field == "output" => {
+            return Some(Value::String(result.output.clone()))
+        }
+        // What an agentic backend did; steps without one fall through to JSON fields
+        [Segment::Field(field)] if field == "trace" && result.trace.is_some() => {
+            return result.trace.as_ref().map(|t| Value::String(t.to_string()))
+        }
+        [Segment::Field(field), rest @ ..] if field == "output" => rest,
+        // steps.NAME.field is short for steps.NAME.output.field
+        _ => path,
+    };
+    navigate(workflow::step_json(result)?, json_path)
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.chars().count() + 1,
+        };
+        let expr = parser.or()?;
+        if let Some((token, column)) = parser.tokens.get(parser.pos) {
+            return Err(ConditionError::Parse {
+                message: format!("unexpected {}", token.describe()),
+                column: *column,
+            });
+        }
+        Ok(Self { expr })
+    }
+
+    pub fn evaluate(&self, scope: &dyn Scope) -> Result<Value, ConditionError> {
+        self.expr.evaluate(scope)
+    }
+
+    pub fn is_true(&self, scope: &dyn Scope) -> Result<bool, ConditionError> {
+        Ok(truthy(&self.evaluate(scope)?))
+    }
+
+    /// Like [`Expression::evaluate`], but None when the expression is just a
+    /// variable that isn't there
+    pub fn evaluate_var(&self, scope: &dyn Scope) -> Result<Option<Value>, ConditionError> {
+        match &self.expr {
+            Expr::Var { path, .. } => scope.lookup(path),
+            expr => expr.evaluate(scope).map(Some),
+        }
+    }
+
+    /// Whether the last filter applied is `| raw`
+    pub fn is_raw(&self) -> bool {
+        matches!(self.expr, Expr::Filter(_, Filter::Raw, _))
+    }
+
+    /// Every variable path read, with its column
+    fn vars(&self) -> Vec<(&[Segment], usize)> {
+        let mut vars = Vec::new();
+        self.expr.collect_vars(&mut vars);
+        vars
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let expression = Expression::parse(source)?;
+        for (path, column) in expression.vars() {
+            let error = |message: String| ConditionError::Parse { message, column };
+            let (step, fields) = match path {
+                [Segment::Field(root), Segment::Field(step), fields @ ..] if root == "steps" => {
+                    (step, fields)
+                }
+                [Segment::Field(root)] if root == "steps" => {
+                    return Err(error("expected a step name after 'steps.'".to_string()))
+                }
+                [Segment::Field(step), fields @ ..] if !fields.is_empty() => (step, fields),
+                [Segment::Field(name)] => {
+                    return Err(error(format!(
+                        "unknown name '{}' (use steps.NAME.output or steps.NAME.success)",
+                        name
+                    )))
+                }
+                _ => return Err(error("expected a step name".to_string())),
+            };
+            match fields {
+                [] => {
+                    return Err(error(format!(
+                        "expected a field after step '{}', e.g. steps.{}.output",
+                        step, step
+                    )))
+                }
+                [Segment::Field(field), _, ..] if field == "success" => {
+                    return Err(error("steps.NAME.success has no fields".to_string()))
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { expression })
+    }
+
+    /// Names of the steps the condition reads
+    pub fn steps(&self) -> Vec<&str> {
+        let mut steps = Vec::new();
+        for (path, _) in self.expression.vars() {
+            if let Some(step) = condition_step(path) {
+                if !steps.contains(&step) {
+                    steps.push(step);
+                }
+            }
+        }
+        steps
+    }
+
+    pub fn evaluate(&self, results: &HashMap<String, StepResult>) -> Result<bool, ConditionError> {
+        self.expression.is_true(&StepResults(results))
+    }
+}
+
+/// The step a condition's variable reads: `steps.NAME...` or `NAME...`
+fn condition_step(path: &[Segment]) -> Option<&str> {
+    match path {
+        [Segment::Field(root), Segment::Field(step), ..] if root == "steps" => Some(step),
+        [Segment::Field(step), ..] => Some(step),
+        _ => None,
+    }
+}
+
+/// Results of earlier steps, as conditions see them
+struct StepResults<'a>(&'a HashMap<String, StepResult>);
+
+impl Scope for StepResults<'_> {
+    fn lookup(&self, path: &[Segment]) -> Result<Option<Value>, ConditionError> {
+        let fields = match path {
+            [Segment::Field(root), _, fields @ ..] if root == "steps" => fields,
+            [_, fields @ ..] => fields,
+            [] => return Ok(None),
+        };
+        // A step that hasn't run (yet, or skipped) has nothing
+        Ok(condition_step(path)
+            .and_then(|step| self.0.get(step))
+            .and_then(|result| step_value(result, fields)))
+    }
+}
+
+impl Expr {
+    fn collect_vars<'a>(&'a self, vars: &mut Vec<(&'a [Segment], usize)>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Var { path, column } => vars.push((path, *column)),
+            Expr::Not(a) | Expr::Len(a) | Expr::Matches(a, _) => a.collect_vars(vars),
+            Expr::And(a, b) | Expr::Or(a, b) | Expr::Compare(_, a, b) | Expr::Contains(a, b) => {
+                a.collect_vars(vars);
+                b.collect_vars(vars);
+            }
+            Expr::Filter(a, _, args) => {
+                a.collect_vars(vars);
+                for arg in args {
+                    arg.collect_vars(vars);
+                }
+            }
+        }
+    }
+
+    fn evaluate(&self, scope: &dyn Scope) -> Result<Value, ConditionError> {
+        Ok(match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Var { path, .. } => scope.lookup(path)?.unwrap_or_default(),
+            Expr::Not(a) => Value::Bool(!truthy(&a.evaluate(scope)?)),
+            Expr::And(a, b) => {
+                Value::Bool(truthy(&a.evaluate(scope)?) && truthy(&b.evaluate(scope)?))
+            }
+            Expr::Or(a, b) => {
+                Value::Bool(truthy(&a.evaluate(scope)?) || truthy(&b.evaluate(scope)?))
+            }
+            Expr::Compare(op, a, b) => {
+                Value::Bool(compare(*op, &a.evaluate(scope)?, &b.evaluate(scope)?))
+            }
+            Expr::Contains(a, b) => Value::Bool(contains(&a.evaluate(scope)?, &b.evaluate(scope)?)),
+            Expr::Len(a) => Value::from(len(&a.evaluate(scope)?)?),
+            Expr::Matches(a, regex) => match a.evaluate(scope)? {
+                Value::Null => Value::Bool(false),
+                other => Value::Bool(regex.is_match(&display(&other))),
+            },
+            Expr::Filter(a, filter, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                filter.apply(a.evaluate(scope)?, &args)?
+            }
+        })
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.trim().is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn compare(op: CmpOp, a: &Value, b: &Value) -> bool {
+    let ordering = match (a, b) {
+        (Value::Number(_), _) | (_, Value::Number(_)) => match (as_number(a), as_number(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => None,
+        },
+        (Value::String(x), Value::String(y)) => Some(x.trim().cmp(y.trim())),
+        // `steps.x.output == true` for an output of "true"
+        (Value::String(s), Value::Bool(flag)) | (Value::Bool(flag), Value::String(s)) => {
+            (s.trim() == flag.to_string()).then_some(Ordering::Equal)
+        }
+        _ if a == b => Some(Ordering::Equal),
+        _ => None,
+    };
+    match op {
+        CmpOp::Eq => ordering == Some(Ordering::Equal),
+        CmpOp::Ne => ordering != Some(Ordering::Equal),
+        CmpOp::Lt => ordering == Some(Ordering::Less),
+        CmpOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        CmpOp::Gt => ordering == Some(Ordering::Greater),
+        CmpOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
+
+/// Substring, array element or object key
+fn contains(haystack: &Value, needle: &Value) -> bool {
+    let needle_text = display(needle);
+    match haystack {
+        Value::String(s) => s.contains(&needle_text),
+        Value::Array(items) => items.iter().any(|item| compare(CmpOp::Eq, item, needle)),
+        Value::Object(map) => map.contains_key(&needle_text),
+        _ => false,
+    }
+}
+
+fn len(value: &Value) -> Result<usize, ConditionError> {
+    match value {
+        Value::Null => Ok(0),
+        Value::String(s) => Ok(s.trim().chars().count()),
+        Value::Array(items) => Ok(items.len()),
+        Value::Object(map) => Ok(map.len()),
+        other => Err(ConditionError::Eval(format!("len() of {}", other))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Dot,
+    And,
+    Or,
+    Bang,
+    Pipe,
+    Cmp(CmpOp),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => format!("'{}'", name),
+            Token::Str(s) => format!("string \"{}\"", s),
+            Token::Num(n) => format!("number {}", n),
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+            Token::LBracket => "'['".to_string(),
+            Token::RBracket => "']'".to_string(),
+            Token::Comma => "','".to_string(),
+            Token::Dot => "'.'".to_string(),
+            Token::And => "'&&'".to_string(),
+            Token::Or => "'||'".to_string(),
+            Token::Bang => "'!'".to_string(),
+            Token::Pipe => "'|'".to_string(),
+            Token::Cmp(op) => format!("'{}'", op.as_str()),
+        }
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self, Token::Ident(name) if name == word)
+    }
+}
+
+impl CmpOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+}
+
+/// Tokens with their 1-based column
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ConditionError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let error = |message: String| ConditionError::Parse { message, column };
+        let next = chars.get(i + 1).copied();
+        let (token, width) = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => (Token::LParen, 1),
+            ')' => (Token::RParen, 1),
+            '[' => (Token::LBracket, 1),
+            ']' => (Token::RBracket, 1),
+            ',' => (Token::Comma, 1),
+            '.' => (Token::Dot, 1),
+            '&' if next == Some('&') => (Token::And, 2),
+            '|' if next == Some('|') => (Token::Or, 2),
+            '|' => (Token::Pipe, 1),
+            '=' if next == Some('=') => (Token::Cmp(CmpOp::Eq), 2),
+            '!' if next == Some('=') => (Token::Cmp(CmpOp::Ne), 2),
+            '<' if next == Some('=') => (Token::Cmp(CmpOp::Le), 2),
+            '>' if next == Some('=') => (Token::Cmp(CmpOp::Ge), 2),
+            '!' => (Token::Bang, 1),
+            '<' => (Token::Cmp(CmpOp::Lt), 1),
+            '>' => (Token::Cmp(CmpOp::Gt), 1),
+            '"' | '\'' => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err(error("unterminated string".to_string())),
+                        Some(&q) if q == c => break,
+                        Some('\\') => {
+                            let escaped = chars
+                                .get(j + 1)
+                                .ok_or_else(|| error("unterminated string".to_string()))?;
+                            value.push(match escaped {
+                                'n' => '\n',
+                                't' => '\t',
+                                other => *other,
+                            });
+                            j += 2;
+                        }
+                        Some(&other) => {
+                            value.push(other);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Str(value), j + 1 - i)
+            }
+            c if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let mut j = i + 1;
+                while j < chars.len()
+                    && (chars[j].is_ascii_digit()
+                        || (chars[j] == '.'
+                            && chars.get(j + 1).is_some_and(|n| n.is_ascii_digit())))
+                {
+                    j += 1;
+                }
+                let text: String = chars[i..j].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| error(format!("bad number '{}'", text)))?;
+                (Token::Num(number), j - i)
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut j = i + 1;
+                // Step names may contain '-'
+                while j < chars.len()
+                    && (chars[j].is_alphanumeric() || chars[j] == '_' || chars[j] == '-')
+                {
+                    j += 1;
+                }
+                (Token::Ident(chars[i..j].iter().collect()), j - i)
+            }
+            other => return Err(error(format!("unexpected '{}'", other))),
+        };
+        tokens.push((token, column));
+        i += width;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Column just past the end, for errors at the end of input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        self.peek().is_some_and(|token| token.is_word(word))
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ConditionError {
+        ConditionError::Parse {
+            message: message.into(),
+            column: self.column(),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.peek_word(word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ConditionError> {
+        if self.eat(&token) {
+            return Ok(());
+        }
+        let found = match self.peek() {
+            Some(found) => found.describe(),
+            None => "end of expression".to_string(),
+        };
+        Err(self.error(format!("expected {}, found {}", token.describe(), found)))
+    }
+
+    fn or(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) || self.eat_word("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) || self.eat_word("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ConditionError> {
+        // `not(x)` reads the same as `not x`
+        if self.eat(&Token::Bang) || self.eat_word("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ConditionError> {
+        let left = self.filtered()?;
+        match self.peek() {
+            Some(Token::Cmp(op)) => {
+                let op = *op;
+                self.pos += 1;
+                Ok(Expr::Compare(
+                    op,
+                    Box::new(left),
+                    Box::new(self.filtered()?),
+                ))
+            }
+            Some(token) if token.is_word("contains") => {
+                self.pos += 1;
+                Ok(Expr::Contains(Box::new(left), Box::new(self.filtered()?)))
+            }
+            Some(token) if token.is_word("in") => {
+                self.pos += 1;
+                Ok(Expr::Contains(Box::new(self.filtered()?), Box::new(left)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    /// A primary followed by `| filter` or `| filter(args)`
+    fn filtered(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.primary()?;
+        while self.eat(&Token::Pipe) {
+            let column = self.column();
+            let name = match self.next() {
+                Some(Token::Ident(name)) => name,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected a filter name after '|'"));
+                }
+            };
+            let filter = Filter::from_name(&name).ok_or_else(|| ConditionError::Parse {
+                message: format!(
+                    "unknown filter '{}' (use default, truncate, json, lines, first, last, join, length, trim or raw)",
+                    name
+                ),
+                column,
+            })?;
+            let args = if self.peek() == Some(&Token::LParen) {
+                self.args()?
+            } else {
+                Vec::new()
+            };
+            let (min, max) = filter.arity();
+            if args.len() < min || args.len() > max {
+                return Err(ConditionError::Parse {
+                    message: format!(
+                        "filter '{}' takes {} argument(s), got {}",
+                        name,
+                        max,
+                        args.len()
+                    ),
+                    column,
+                });
+            }
+            expr = Expr::Filter(Box::new(expr), filter, args);
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ConditionError> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Num(n)) if n.fract() == 0.0 => Ok(Expr::Literal(Value::from(n as i64))),
+            Some(Token::Num(n)) => Ok(Expr::Literal(Value::from(n))),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.peek() == Some(&Token::LParen) => self.call(&name, column),
+                _ => self.path(name, column),
+            },
+            Some(token) => Err(ConditionError::Parse {
+                message: format!("unexpected {}", token.describe()),
+                column,
+            }),
+            None => Err(self.error("expression ends early")),
+        }
+    }
+
+    /// `.field`, `.0` or `[index]`... after a variable name
+    fn path(&mut self, name: String, column: usize) -> Result<Expr, ConditionError> {
+        let mut path = vec![Segment::Field(name)];
+        loop {
+            if self.eat(&Token::Dot) {
+                match self.next() {
+                    Some(Token::Ident(field)) => path.push(Segment::Field(field)),
+                    // `arg.1`
+                    Some(Token::Num(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                        path.push(Segment::Field(n.to_string()))
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a field name after '.'"));
+                    }
+                }
+            } else if self.eat(&Token::LBracket) {
+                match self.next() {
+                    Some(Token::Num(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                        path.push(Segment::Index(n as usize))
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected an index like [0]"));
+                    }
+                }
+                self.expect(Token::RBracket)?;
+            } else {
+                break;
+            }
+        }
+        Ok(Expr::Var { path, column })
+    }
+
+    /// `(a, b, ...)`
+    fn args(&mut self) -> Result<Vec<Expr>, ConditionError> {
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+        if !self.eat(&Token::RParen) {
+            loop {
+                args.push(self.or()?);
+                if self.eat(&Token::RParen) {
+                    break;
+                }
+                self.expect(Token::Comma)?;
+            }
+        }
+        Ok(args)
+    }
+
+    fn call(&mut self, name: &str, column: usize) -> Result<Expr, ConditionError> {
+        let mut args = self.args()?;
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(ConditionError::Parse {
+                    message: format!("{}() takes {} argument(s), got {}", name, n, args.len()),
+                    column,
+                })
+            }
+        };
+        match name {
+            "contains" | "equals" => {
+                arity(2)?;
+                let (a, b) = two(args);
+                Ok(if name == "contains" {
+                    Expr::Contains(a, b)
+                } else {
+                    Expr::Compare(CmpOp::Eq, a, b)
+                })
+            }
+            "len" => {
+                arity(1)?;
+                Ok(Expr::Len(Box::new(args.pop().expect("one argument"))))
+            }
+            "matches" => {
+                arity(2)?;
+                let Some(Expr::Literal(Value::String(pattern))) = args.pop() else {
+                    return Err(ConditionError::Parse {
+                        message: "matches() takes a string literal pattern".to_string(),
+                        column,
+                    });
+                };
+                let regex = Regex::new(&pattern).map_err(|e| ConditionError::Parse {
+                    message: format!("bad regex in matches(): {}", e),
+                    column,
+                })?;
+                Ok(Expr::Matches(
+                    Box::new(args.pop().expect("two arguments")),
+                    regex,
+                ))
+            }
+            _ => Err(ConditionError::Parse {
+                message: format!(
+                    "unknown function '{}' (use contains, equals, len, matches or not)",
+                    name
+                ),
+                column,
+            }),
+        }
+    }
+}
+
+fn two(mut args: Vec<Expr>) -> (Box<Expr>, Box<Expr>) {
+    let b = args.pop().expect("two arguments");
+    let a = args.pop().expect("two arguments");
+    (Box::new(a), Box::new(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::UsageByBackend;
+
+    fn result(name: &str, output: &str, success: bool) -> StepResult {
+        StepResult {
+            name: name.to_string(),
+            output: output.to_string(),
+            parsed_output: None,
+            success,
+            elapsed_ms: 0,
+            backend: None,
+            usage: UsageByBackend::default(),
+            fallback: None,
+            trace: None,
+        }
+    }
+
+    fn results() -> HashMap<String, StepResult> {
+        [
+            result("check", "PASS\n", true),
+            result("count", " 12 ", true),
+            result(
+                "scan",
+                r#"```json
+{"findings": [{"severity": "high"}, {"severity": "low"}], "score": 7, "ok": false}
+```"#,
+                true,
+            ),
+            result("broken", "Error: timeout\n\nat line 3\n", false),
+        ]
+        .into_iter()
+        .map(|r| (r.name.clone(), r))
+        .collect()
+    }
+
+    fn eval(source: &str) -> bool {
+        Condition::parse(source)
+            .unwrap_or_else(|e| panic!("{}: {}", source, e))
+            .evaluate(&results())
+            .unwrap()
+    }
+
+    fn value(source: &str) -> Value {
+        Expression::parse(source)
+            .unwrap_or_else(|e| panic!("{}: {}", source, e))
+            .evaluate(&StepResults(&results()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        assert!(eval("steps.check.success && !steps.broken.success"));
+        assert!(eval("steps.broken.success || steps.check.success"));
+        assert!(!eval(
+            "steps.broken.success || (steps.check.success && false)"
+        ));
+        assert!(eval("not(steps.broken.success)"));
+        assert!(eval("steps.check.success and not steps.broken.success"));
+        // A step that hasn't run is neither successful nor failed
+        assert!(!eval("steps.later.success"));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert!(eval(r#"steps.check.output == "PASS""#));
+        assert!(eval(r#"equals(check.output, 'PASS')"#));
+        assert!(eval("steps.count.output > 10 && steps.count.output <= 12"));
+        assert!(eval("steps.scan.score >= 7"));
+        assert!(eval("steps.scan.output.ok == false"));
+        assert!(eval(r#"steps.check.output != "FAIL""#));
+        assert!(!eval(r#"steps.check.output < 3"#));
+    }
+
+    #[test]
+    fn test_functions_and_paths() {
+        assert!(eval("len(steps.scan.output.findings) == 2"));
+        assert!(eval(r#"steps.scan.output.findings[0].severity == "high""#));
+        assert!(eval(r#"steps.scan.findings[5].severity == null"#));
+        assert!(eval(r#"contains(steps.broken.output, "timeout")"#));
+        assert!(eval(r#"steps.broken.output contains 'timeout'"#));
+        assert!(eval(r#""timeout" in steps.broken.output"#));
+        assert!(eval(r#"matches(steps.broken.output, "(?i)^error: \\w+")"#));
+        assert!(eval(r#"contains(steps.scan.output, "findings")"#));
+        assert!(eval(
+            r#"contains(steps.scan.output.findings[1], "severity")"#
+        ));
+        assert!(!eval(r#"contains(missing.output, "x")"#));
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            value("steps.broken.output | lines"),
+            serde_json::json!(["Error: timeout", "at line 3"])
+        );
+        assert_eq!(value("steps.broken.output | lines | length"), 2);
+        assert_eq!(value("steps.broken.output | last"), "at line 3");
+        assert_eq!(value("steps.broken.output | truncate(5)"), "Error...");
+        assert_eq!(value("steps.check.output | trim | json"), r#""PASS""#);
+        assert_eq!(value(r#"steps.scan.nothing | default("none")"#), "none");
+        assert_eq!(value(r#"steps.scan.score | default("none")"#), 7);
+        assert_eq!(value("steps.check.output | trim | raw"), "PASS");
+        assert!(Expression::parse("x | trim | raw").unwrap().is_raw());
+        assert!(!Expression::parse("x | raw | trim").unwrap().is_raw());
+        assert_eq!(
+            value("steps.scan.findings | first | json"),
+            r#"{"severity":"high"}"#
+        );
+        assert_eq!(
+            value(r#"steps.broken.output | lines | join(" / ")"#),
+            "Error: timeout / at line 3"
+        );
+        // Filters bind tighter than comparisons
+        assert!(eval("steps.broken.output | lines | length > 1"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for bad in [
+            "",
+            "some random text",
+            "steps.check",
+            "steps.check.success &&",
+            "steps.check.success & steps.count.success",
+            r#"contains(steps.check.output)"#,
+            r#"matches(steps.check.output, "(")"#,
+            r#"startswith(steps.check.output, "x")"#,
+            r#"steps.check.output == "PASS"#,
+            "(steps.check.success",
+            "steps.check.success.value",
+            "steps.check.output | upper",
+            "steps.check.output | truncate",
+        ] {
+            assert!(Condition::parse(bad).is_err(), "{:?} should not parse", bad);
+        }
+
+        let error = Condition::parse("steps.check.success && sucess").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown name 'sucess' (use steps.NAME.output or steps.NAME.success) at column 24"
+        );
+    }
+
+    #[test]
+    fn test_steps_referenced() {
+        let condition =
+            Condition::parse(r#"steps.a.success && (contains(b.output, "x") || steps.a.ok)"#)
+                .unwrap();
+        assert_eq!(condition.steps(), ["a", "b"]);
+    }
+}
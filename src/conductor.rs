@@ -1,98 +1,54 @@
-use crate::backend;
+use crate::backend::{self, Backend, Conversation, Message, QueryRequest, Tool};
 use crate::config::Config;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use colored::Colorize;
-use secrecy::{ExposeSecret, SecretString};
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 pub struct Conductor {
-    api_key: SecretString,
-    model: String,
-    client: reqwest::Client,
+    /// Backend that plans, delegates and answers
+    backend: Arc<dyn Backend>,
     config: Config,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct Message {
-    role: String,
-    content: MessageContent,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(untagged)]
-enum MessageContent {
-    Text(String),
-    Blocks(Vec<ContentBlock>),
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(tag = "type")]
-enum ContentBlock {
-    #[serde(rename = "text")]
-    Text { text: String },
-    #[serde(rename = "tool_use")]
-    ToolUse {
-        id: String,
-        name: String,
-        input: serde_json::Value,
-    },
-    #[serde(rename = "tool_result")]
-    ToolResult {
-        tool_use_id: String,
-        content: String,
-    },
-}
-
-#[derive(Serialize)]
-struct Tool {
-    name: String,
-    description: String,
-    input_schema: serde_json::Value,
-}
-
-#[derive(Deserialize, Debug)]
-struct ClaudeResponse {
-    content: Vec<ResponseBlock>,
-}
-
-#[derive(Deserialize, Debug)]
-#[serde(tag = "type")]
-enum ResponseBlock {
-    #[serde(rename = "text")]
-    Text { text: String },
-    #[serde(rename = "tool_use")]
-    ToolUse {
-        id: String,
-        name: String,
-        input: serde_json::Value,
-    },
+    /// Shared by every round, so a failing backend is skipped
+    breaker: backend::CircuitBreaker,
 }
 
 impl Conductor {
-    pub fn new(config: &Config) -> Result<Self> {
-        let claude = backend::create_claude_backend(config)?;
-        let (api_key, model, client) = claude
-            .api_details()
-            .ok_or_else(|| anyhow::anyhow!(
-                "Conductor requires Claude API mode. Set ANTHROPIC_API_KEY or configure claude backend without 'command' field."
-            ))?;
+    /// `backend` overrides `conductor.backend` from the config
+    pub fn new(config: &Config, backend: Option<&str>) -> Result<Self> {
+        let name = backend.unwrap_or(&config.conductor.backend);
+        if !config.backends.get(name).is_some_and(|b| b.enabled) {
+            anyhow::bail!("Conductor backend '{}' is not configured or disabled", name);
+        }
+
+        let breaker = backend::CircuitBreaker::new(&config.circuit_breaker);
+        let backend = backend::build_backend(name, config, Some(&breaker))?;
+        if !backend.is_available() {
+            anyhow::bail!(
+                "Conductor backend '{}' is not available. Run `lok doctor` for details.",
+                name
+            );
+        }
 
         Ok(Self {
-            api_key: api_key.clone(),
-            model: model.to_string(),
-            client: client.clone(),
+            backend,
             config: config.clone(),
+            breaker,
         })
     }
 
     fn get_available_backends(&self) -> Vec<String> {
-        self.config
+        // Sorted so the prompt is the same on every run (and replays match)
+        let mut backends: Vec<String> = self
+            .config
             .backends
             .iter()
-            .filter(|(name, cfg)| cfg.enabled && *name != "claude")
+            .filter(|(name, cfg)| cfg.enabled && *name != self.backend.name())
             .map(|(name, _)| name.clone())
-            .collect()
+            .collect();
+        backends.sort();
+        backends
     }
 
     fn build_system_prompt(&self) -> String {
@@ -139,7 +95,7 @@ Always explain your reasoning briefly before making tool calls."#,
 
         vec![Tool {
             name: "query_backend".to_string(),
-            description: "Query an LLM backend with a specific prompt. The backend will analyze the codebase in the current directory.".to_string(),
+            description: "Query an LLM backend with a specific prompt. The backend will analyze the codebase in the current directory. Each backend remembers your earlier queries to it, so follow-ups only need the new question.".to_string(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -163,6 +119,7 @@ Always explain your reasoning briefly before making tool calls."#,
         name: &str,
         input: &serde_json::Value,
         cwd: &Path,
+        conversations: &mut HashMap<String, Conversation>,
     ) -> Result<String> {
         match name {
             "query_backend" => {
@@ -175,23 +132,22 @@ Always explain your reasoning briefly before making tool calls."#,
 
                 println!("  {} Querying {} ...", "→".cyan(), backend_name.yellow());
 
-                let backend_config = self
-                    .config
-                    .backends
-                    .get(backend_name)
-                    .ok_or_else(|| anyhow::anyhow!("Backend not found: {}", backend_name))?;
-
-                let backend = backend::create_backend(backend_name, backend_config)?;
-                let result = backend.query(prompt, cwd).await?;
+                let backend =
+                    backend::build_backend(backend_name, &self.config, Some(&self.breaker))?;
+                let completion = conversations
+                    .entry(backend_name.to_string())
+                    .or_default()
+                    .send(backend.as_ref(), prompt, cwd)
+                    .await?;
 
                 println!(
                     "  {} {} responded ({} chars)",
                     "←".green(),
-                    backend_name.yellow(),
-                    result.len()
+                    completion.answered_by(backend_name).yellow(),
+                    completion.text.len()
                 );
 
-                Ok(result)
+                Ok(completion.text)
             }
             _ => anyhow::bail!("Unknown tool: {}", name),
         }
@@ -200,17 +156,23 @@ Always explain your reasoning briefly before making tool calls."#,
     pub async fn conduct(&self, task: &str, cwd: &Path) -> Result<String> {
         let cwd = crate::utils::canonicalize_async(cwd).await;
 
-        println!("{}", "Conductor starting...".cyan().bold());
+        println!(
+            "{} {}",
+            "Conductor starting...".cyan().bold(),
+            format!("({})", self.backend.name()).dimmed()
+        );
         println!("Task: {}", task);
         println!();
 
-        let system = self.build_system_prompt();
-        let tools = self.build_tools();
+        // One conversation per delegated backend, kept across rounds
+        let mut conversations: HashMap<String, Conversation> = HashMap::new();
 
-        let mut messages = vec![Message {
-            role: "user".to_string(),
-            content: MessageContent::Text(task.to_string()),
-        }];
+        let mut request = QueryRequest {
+            system: Some(self.build_system_prompt()),
+            max_tokens: Some(self.config.conductor.max_tokens as u32),
+            tools: self.build_tools(),
+            ..QueryRequest::new(task)
+        };
 
         let max_rounds = self.config.conductor.max_rounds;
         for round in 0..max_rounds {
@@ -220,95 +182,37 @@ Always explain your reasoning briefly before making tool calls."#,
                 "Thinking...".dimmed()
             );
 
-            let request = serde_json::json!({
-                "model": &self.model,
-                "max_tokens": self.config.conductor.max_tokens,
-                "system": system,
-                "tools": tools,
-                "messages": messages
-            });
-
-            let response = self
-                .client
-                .post("https://api.anthropic.com/v1/messages")
-                .header("x-api-key", self.api_key.expose_secret())
-                .header("anthropic-version", "2023-06-01")
-                .header("content-type", "application/json")
-                .json(&request)
-                .send()
-                .await
-                .context("Failed to send request to Claude API")?;
-
-            if !response.status().is_success() {
-                let status = response.status();
-                let body = response.text().await.unwrap_or_default();
-                anyhow::bail!("Claude API error {}: {}", status, body);
+            let completion =
+                backend::tools::complete_with_tools(self.backend.as_ref(), &request, &cwd).await?;
+            if !completion.text.is_empty() {
+                println!("{}", completion.text.dimmed());
             }
 
-            let response: ClaudeResponse = response
-                .json()
-                .await
-                .context("Failed to parse Claude response")?;
-
-            // Check for tool use
-            let mut has_tool_use = false;
-            let mut tool_results = Vec::new();
-            let mut assistant_content = Vec::new();
-
-            for block in &response.content {
-                match block {
-                    ResponseBlock::Text { text } => {
-                        println!("{}", text.dimmed());
-                        assistant_content.push(ContentBlock::Text { text: text.clone() });
-                    }
-                    ResponseBlock::ToolUse { id, name, input } => {
-                        has_tool_use = true;
-                        assistant_content.push(ContentBlock::ToolUse {
-                            id: id.clone(),
-                            name: name.clone(),
-                            input: input.clone(),
-                        });
-
-                        let result = self.execute_tool(name, input, &cwd).await;
-                        let result_text = match result {
-                            Ok(text) => text,
-                            Err(e) => format!("Error: {}", e),
-                        };
-
-                        tool_results.push(ContentBlock::ToolResult {
-                            tool_use_id: id.clone(),
-                            content: result_text,
-                        });
-                    }
-                }
+            // No tool calls means we're done
+            if completion.tool_calls.is_empty() {
+                println!();
+                return Ok(completion.text);
             }
 
-            // Add assistant message
-            messages.push(Message {
-                role: "assistant".to_string(),
-                content: MessageContent::Blocks(assistant_content),
-            });
-
-            // If we had tool use, add tool results and continue
-            if has_tool_use {
-                messages.push(Message {
-                    role: "user".to_string(),
-                    content: MessageContent::Blocks(tool_results),
-                });
-            } else {
-                // No tool use means we're done
-                let final_text = response
-                    .content
-                    .iter()
-                    .filter_map(|b| match b {
-                        ResponseBlock::Text { text } => Some(text.clone()),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n");
-
-                println!();
-                return Ok(final_text);
+            // The task becomes the first turn; later rounds only add tool results
+            if !request.prompt.is_empty() {
+                let task = std::mem::take(&mut request.prompt);
+                request.history.push(Message::user(task));
+            }
+            request.history.push(Message::assistant_with_calls(
+                completion.text,
+                completion.tool_calls.clone(),
+            ));
+
+            for call in &completion.tool_calls {
+                let result = self
+                    .execute_tool(&call.name, &call.input, &cwd, &mut conversations)
+                    .await;
+                let result_text = match result {
+                    Ok(text) => text,
+                    Err(e) => format!("Error: {}", e),
+                };
+                request.history.push(Message::tool_result(call, result_text));
             }
         }
 
@@ -1,3 +1,4 @@
+use crate::backend::{Permissions, PromptVia};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -13,9 +14,17 @@ pub struct Config {
     #[serde(default)]
     pub cache: crate::cache::CacheConfig,
     #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
     pub backends: HashMap<String, BackendConfig>,
     #[serde(default)]
     pub tasks: HashMap<String, TaskConfig>,
+    /// Token prices (USD per million tokens), keyed by backend name or model
+    #[serde(default)]
+    pub prices: HashMap<String, crate::usage::Price>,
+    /// Set by `--record`/`--replay`; never read from or written to lok.toml
+    #[serde(skip)]
+    pub traffic: Option<crate::backend::Traffic>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -50,12 +59,20 @@ impl Default for Defaults {
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ConductorConfig {
+    /// Backend that plans and delegates; any backend works, those without
+    /// native tool calling are given the tools in their prompt
+    #[serde(default = "default_conductor_backend")]
+    pub backend: String,
     #[serde(default = "default_max_rounds")]
     pub max_rounds: usize,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
 }
 
+fn default_conductor_backend() -> String {
+    "claude".to_string()
+}
+
 fn default_max_rounds() -> usize {
     5
 }
@@ -67,31 +84,137 @@ fn default_max_tokens() -> usize {
 impl Default for ConductorConfig {
     fn default() -> Self {
         Self {
+            backend: default_conductor_backend(),
             max_rounds: default_max_rounds(),
             max_tokens: default_max_tokens(),
         }
     }
 }
 
+/// Stop calling a backend for a while after it keeps failing the same way
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures of one kind that open the circuit (0 disables)
+    #[serde(default = "default_breaker_threshold")]
+    pub threshold: u32,
+    /// Seconds to wait before letting a request through to test the backend again
+    #[serde(default = "default_breaker_cooldown")]
+    pub cooldown: u64,
+}
+
+fn default_breaker_threshold() -> u32 {
+    3
+}
+
+fn default_breaker_cooldown() -> u64 {
+    60
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            threshold: default_breaker_threshold(),
+            cooldown: default_breaker_cooldown(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BackendConfig {
     #[serde(default = "default_enabled")]
     pub enabled: bool,
+    /// Backend implementation (codex, gemini, claude, ollama, openai, exec, mock, bedrock).
+    /// Defaults to the backend's name, so `[backends.ollama-big]` needs `kind = "ollama"`.
+    #[serde(default)]
+    pub kind: Option<String>,
     pub command: Option<String>,
     #[serde(default)]
     pub args: Vec<String>,
+    /// Output lines to drop before the answer (banners of CLIs without JSON output)
     #[serde(default)]
     pub skip_lines: usize,
     pub api_key_env: Option<String>,
     pub model: Option<String>,
     /// Per-backend timeout in seconds (overrides defaults.timeout)
     pub timeout: Option<u64>,
+    /// Exec backend: regex to extract the answer from stdout (first capture group, or whole match)
+    #[serde(default)]
+    pub output_regex: Option<String>,
+    /// Exec backend: JSON pointer (e.g. "/result/text") to extract the answer from JSON/JSONL stdout
+    #[serde(default)]
+    pub output_json_pointer: Option<String>,
+    /// Minimum CLI version `lok doctor` accepts (overrides the built-in minimum)
+    #[serde(default)]
+    pub min_version: Option<String>,
+    /// Backends to re-issue the query to, in order, when this one fails with a
+    /// rate limit, capacity, auth, network or not-installed error
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    /// Most requests to this backend in flight at once, across the whole process
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// Most requests started per minute; requests are spaced evenly
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Mock backend: TOML or JSONL file of canned answers
+    #[serde(default)]
+    pub fixtures: Option<String>,
+    /// Ollama backend: model options sent with every request (num_ctx, seed, top_p...).
+    /// A step's own temperature, max_tokens and stop take precedence.
+    #[serde(default)]
+    pub options: serde_json::Map<String, serde_json::Value>,
+    /// Ollama backend: how long the model stays loaded after a request ("10m", or -1 for forever)
+    #[serde(default)]
+    pub keep_alive: Option<serde_json::Value>,
+    /// Ollama backend: pull the model through the server when it isn't present yet
+    #[serde(default)]
+    pub auto_pull: bool,
+    /// Tokens the model can take in; prompts are checked against it before sending.
+    /// Ollama backends also use it as `num_ctx` unless `options.num_ctx` is set.
+    #[serde(default)]
+    pub context_window: Option<u64>,
+    /// Agentic CLIs (codex, claude CLI, gemini): what the agent may do, passed
+    /// as the CLI's own flag. "read-only" (default), "workspace-write" or "full".
+    #[serde(default)]
+    pub permissions: Option<Permissions>,
+    /// Agentic and exec CLIs: how the prompt gets to the CLI, "arg", "stdin" or "file".
+    /// Unset, long prompts go on stdin and short ones as an argument.
+    #[serde(default)]
+    pub prompt_via: Option<PromptVia>,
 }
 
 fn default_enabled() -> bool {
     true
 }
 
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            kind: None,
+            command: None,
+            args: Vec::new(),
+            skip_lines: 0,
+            api_key_env: None,
+            model: None,
+            timeout: None,
+            output_regex: None,
+            output_json_pointer: None,
+            min_version: None,
+            fallback: Vec::new(),
+            max_concurrent: None,
+            requests_per_minute: None,
+            fixtures: None,
+            options: serde_json::Map::new(),
+            keep_alive: None,
+            auto_pull: false,
+            context_window: None,
+            permissions: None,
+            prompt_via: None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TaskConfig {
     pub description: Option<String>,
@@ -114,57 +237,40 @@ impl Default for Config {
         backends.insert(
             "codex".to_string(),
             BackendConfig {
-                enabled: true,
                 command: Some("codex".to_string()),
-                args: vec![
-                    "exec".to_string(),
-                    "--json".to_string(),
-                    "-s".to_string(),
-                    "read-only".to_string(),
-                ],
-                skip_lines: 0,
-                api_key_env: None,
-                model: None,
-                timeout: None,
+                args: vec!["exec".to_string(), "--json".to_string()],
+                ..Default::default()
             },
         );
 
         backends.insert(
             "gemini".to_string(),
             BackendConfig {
-                enabled: true,
                 command: Some("npx".to_string()),
                 args: vec!["@google/gemini-cli".to_string()],
                 skip_lines: 1,
-                api_key_env: None,
-                model: None,
                 timeout: Some(600), // Gemini goes agentic, needs more time
+                ..Default::default()
             },
         );
 
         backends.insert(
             "claude".to_string(),
             BackendConfig {
-                enabled: true,
                 command: Some("claude".to_string()), // CLI mode by default (Claude Code)
                 args: vec![],
-                skip_lines: 0,
-                api_key_env: None,
                 model: None, // Uses Claude Code's default model
-                timeout: None,
+                ..Default::default()
             },
         );
 
         backends.insert(
             "ollama".to_string(),
             BackendConfig {
-                enabled: true,
                 command: Some("http://localhost:11434".to_string()), // Base URL
                 args: vec![],
-                skip_lines: 0,
-                api_key_env: None,
                 model: Some("llama3.2".to_string()), // Default model
-                timeout: None,
+                ..Default::default()
             },
         );
 
@@ -218,8 +324,11 @@ impl Default for Config {
             defaults: Defaults::default(),
             conductor: ConductorConfig::default(),
             cache: crate::cache::CacheConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
             backends,
             tasks,
+            prices: HashMap::new(),
+            traffic: None,
         }
     }
 }
@@ -381,6 +490,64 @@ args = ["--flag", "value"]
         assert_eq!(custom.args, vec!["--flag", "value"]);
     }
 
+    #[test]
+    fn test_parse_backend_kind() {
+        let toml_str = r#"
+[backends.ollama-small]
+kind = "ollama"
+model = "qwen2.5-coder:1.5b"
+
+[backends.ollama-big]
+kind = "ollama"
+model = "qwen2.5-coder:32b"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let small = config.backends.get("ollama-small").unwrap();
+        let big = config.backends.get("ollama-big").unwrap();
+
+        assert_eq!(small.kind, Some("ollama".to_string()));
+        assert_eq!(big.kind, Some("ollama".to_string()));
+        assert_eq!(big.model, Some("qwen2.5-coder:32b".to_string()));
+    }
+
+    #[test]
+    fn test_parse_exec_backend() {
+        let toml_str = r#"
+[backends.internal-llm]
+kind = "exec"
+command = "internal-llm"
+args = ["ask", "--format", "json", "{prompt}"]
+output_json_pointer = "/answer"
+min_version = "2.1"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let exec = config.backends.get("internal-llm").unwrap();
+
+        assert_eq!(exec.kind, Some("exec".to_string()));
+        assert_eq!(exec.args.last(), Some(&"{prompt}".to_string()));
+        assert_eq!(exec.output_json_pointer, Some("/answer".to_string()));
+        assert!(exec.output_regex.is_none());
+        assert_eq!(exec.min_version, Some("2.1".to_string()));
+    }
+
+    #[test]
+    fn test_parse_prices() {
+        let toml_str = r#"
+[prices.claude]
+input = 3.0
+output = 15.0
+
+[prices."qwen2.5-coder:32b"]
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+
+        let claude = config.prices.get("claude").unwrap();
+        assert_eq!(claude.input, 3.0);
+        assert_eq!(claude.output, 15.0);
+        // Missing fields mean free (local models)
+        assert_eq!(config.prices.get("qwen2.5-coder:32b").unwrap().output, 0.0);
+    }
+
     #[test]
     fn test_parse_custom_task() {
         let toml_str = r#"
@@ -413,6 +580,48 @@ prompt = "Check code style"
         assert!(minimal.enabled); // default_enabled
         assert!(minimal.args.is_empty()); // default empty vec
         assert_eq!(minimal.skip_lines, 0); // default 0
+        assert!(minimal.kind.is_none()); // kind falls back to the name
+        assert!(minimal.fallback.is_empty()); // no fallback chain
+    }
+
+    #[test]
+    fn test_parse_circuit_breaker() {
+        let config: Config = toml::from_str("[circuit_breaker]\nthreshold = 5\n").unwrap();
+        assert_eq!(config.circuit_breaker.threshold, 5);
+        assert_eq!(config.circuit_breaker.cooldown, 60);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.circuit_breaker.threshold, 3);
+    }
+
+    #[test]
+    fn test_parse_fallback_chain() {
+        let toml_str = r#"
+[backends.claude]
+fallback = ["gemini", "ollama"]
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let claude = config.backends.get("claude").unwrap();
+        assert_eq!(claude.fallback, vec!["gemini", "ollama"]);
+    }
+
+    #[test]
+    fn test_parse_ollama_options() {
+        let toml_str = r#"
+[backends.ollama]
+keep_alive = "30m"
+auto_pull = true
+
+[backends.ollama.options]
+num_ctx = 32768
+seed = 42
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let ollama = config.backends.get("ollama").unwrap();
+        assert_eq!(ollama.options["num_ctx"], 32768);
+        assert_eq!(ollama.options["seed"], 42);
+        assert_eq!(ollama.keep_alive, Some(serde_json::json!("30m")));
+        assert!(ollama.auto_pull);
     }
 
     #[test]
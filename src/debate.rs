@@ -1,9 +1,10 @@
-use crate::backend::{self, Backend};
+use crate::backend::{self, Backend, Conversation};
 use crate::config::Config;
 use crate::utils::truncate;
 use anyhow::Result;
 use chrono::Utc;
 use colored::Colorize;
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -19,6 +20,8 @@ pub struct Debate<'a> {
     topic: String,
     cwd: std::path::PathBuf,
     config: &'a Config,
+    /// `Some(prefix)` prints responses live as they stream in
+    stream: Option<bool>,
 }
 
 struct Position {
@@ -44,9 +47,16 @@ impl<'a> Debate<'a> {
             topic: topic.to_string(),
             cwd: cwd.to_path_buf(),
             config,
+            stream: None,
         }
     }
 
+    /// Print responses live as they arrive, optionally tagging each line with the backend name
+    pub fn with_streaming(mut self, prefix: bool) -> Self {
+        self.stream = Some(prefix);
+        self
+    }
+
     pub async fn run(&self) -> Result<DebateOutput> {
         let mut rounds: Vec<RoundContent> = Vec::new();
         let participants: Vec<String> =
@@ -54,10 +64,15 @@ impl<'a> Debate<'a> {
 
         self.emit_header();
 
+        // Each backend keeps its own conversation, so later rounds only send the new turn
+        let mut conversations: HashMap<String, Conversation> = HashMap::new();
+
         // Round 1: Initial positions
         self.emit_round_header(1, "Initial Positions");
-        let mut positions = self.get_initial_positions().await?;
-        self.print_positions(&positions);
+        let mut positions = self.get_initial_positions(&mut conversations).await?;
+        if self.stream.is_none() {
+            self.print_positions(&positions);
+        }
 
         rounds.push(RoundContent {
             round: 1,
@@ -85,7 +100,7 @@ impl<'a> Debate<'a> {
             println!();
             self.emit_round_header(round, "Responses");
 
-            let new_positions = self.get_responses(&positions).await?;
+            let new_positions = self.get_responses(&positions, &mut conversations).await?;
 
             rounds.push(RoundContent {
                 round,
@@ -110,7 +125,9 @@ impl<'a> Debate<'a> {
             }
 
             positions = new_positions;
-            self.print_positions(&positions);
+            if self.stream.is_none() {
+                self.print_positions(&positions);
+            }
         }
 
         // Final summary
@@ -178,26 +195,57 @@ impl<'a> Debate<'a> {
         md
     }
 
-    async fn get_initial_positions(&self) -> Result<Vec<Position>> {
+    async fn get_initial_positions(
+        &self,
+        conversations: &mut HashMap<String, Conversation>,
+    ) -> Result<Vec<Position>> {
         let prompt = format!(
             "Question: {}\n\nProvide your position on this. Be specific and concise. \
             If analyzing code, reference specific files/lines.",
             self.topic
         );
 
-        let results = backend::run_query(&self.backends, &prompt, &self.cwd, self.config).await?;
-
-        Ok(results
-            .into_iter()
-            .filter(|r| r.success)
-            .map(|r| Position {
+        let results = match self.stream {
+            Some(prefix) => {
+                backend::run_query_streaming(
+                    &self.backends,
+                    &prompt,
+                    &self.cwd,
+                    self.config,
+                    prefix,
+                )
+                .await?
+            }
+            None => backend::run_query(&self.backends, &prompt, &self.cwd, self.config).await?,
+        };
+
+        let mut positions = Vec::new();
+        for r in results.into_iter().filter(|r| r.success) {
+            let completion = backend::Completion {
+                text: r.output.clone(),
+                usage: r.usage,
+                session: r.session,
+                fallback: r.fallback,
+                ..Default::default()
+            };
+            conversations
+                .entry(r.backend.clone())
+                .or_default()
+                .record(&prompt, &completion);
+            positions.push(Position {
                 backend: r.backend,
                 stance: r.output,
-            })
-            .collect())
+            });
+        }
+
+        Ok(positions)
     }
 
-    async fn get_responses(&self, positions: &[Position]) -> Result<Vec<Position>> {
+    async fn get_responses(
+        &self,
+        positions: &[Position],
+        conversations: &mut HashMap<String, Conversation>,
+    ) -> Result<Vec<Position>> {
         let mut new_positions = Vec::new();
 
         for backend in &self.backends {
@@ -216,22 +264,33 @@ impl<'a> Debate<'a> {
                 .collect::<Vec<_>>()
                 .join("\n\n");
 
+            // The question and this backend's earlier answers are already in the conversation
             let prompt = format!(
-                "Original question: {}\n\n\
-                Other positions:\n{}\n\n\
+                "Other positions:\n{}\n\n\
                 Respond to these positions. Do you agree, disagree, or partially agree? \
                 Point out any errors in their analysis or things they missed. \
                 Update your position if they made valid points. Be specific and concise.",
-                self.topic, other_positions
+                other_positions
             );
 
-            println!("  {} thinking...", backend.name().dimmed());
+            let conversation = conversations.entry(backend.name().to_string()).or_default();
+            let request = conversation.request(prompt.as_str());
+            let response = match self.stream {
+                Some(prefix) => {
+                    backend::query_live(backend.as_ref(), &request, &self.cwd, prefix).await
+                }
+                None => {
+                    println!("  {} thinking...", backend.name().dimmed());
+                    backend.complete(&request, &self.cwd).await
+                }
+            };
 
-            match backend.query(&prompt, &self.cwd).await {
+            match response {
                 Ok(response) => {
+                    conversation.record(prompt, &response);
                     new_positions.push(Position {
                         backend: backend.name().to_string(),
-                        stance: response,
+                        stance: response.text,
                     });
                 }
                 Err(e) => {
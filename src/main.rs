@@ -1,5 +1,6 @@
 mod backend;
 mod cache;
+mod condition;
 mod conductor;
 mod config;
 mod consensus;
@@ -11,6 +12,8 @@ mod output;
 mod spawn;
 mod tasks;
 mod team;
+mod template;
+mod usage;
 mod utils;
 mod workflow;
 mod workflows;
@@ -35,6 +38,14 @@ struct Cli {
     /// Verbose output (show prompts, timing, debug info)
     #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// Record every backend request and response to this directory
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer backend requests from a directory written by --record, without contacting any backend
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -52,9 +63,21 @@ enum Commands {
         #[arg(short, long, default_value = ".")]
         dir: PathBuf,
 
+        /// Attach a file, image or glob like 'src/**/*.rs', relative to --dir (repeatable)
+        #[arg(short = 'f', long = "file", value_name = "PATH")]
+        files: Vec<String>,
+
         /// Skip cache and force fresh query
         #[arg(long)]
         no_cache: bool,
+
+        /// Print output live as it arrives
+        #[arg(long)]
+        stream: bool,
+
+        /// Prefix each streamed line with the backend name
+        #[arg(long, requires = "stream")]
+        prefix: bool,
     },
 
     /// Run a bug hunt on a codebase
@@ -176,7 +199,7 @@ enum Commands {
     /// List available backends
     Backends,
 
-    /// Run with Claude as conductor (multi-round orchestration)
+    /// Run a conductor backend that delegates to the others (multi-round orchestration)
     Conduct {
         /// The task to accomplish
         task: String,
@@ -184,6 +207,10 @@ enum Commands {
         /// Working directory for the analysis
         #[arg(short, long, default_value = ".")]
         dir: PathBuf,
+
+        /// Backend that conducts (default: conductor.backend, or claude)
+        #[arg(short, long)]
+        backend: Option<String>,
     },
 
     /// Run a multi-round debate between backends
@@ -202,6 +229,14 @@ enum Commands {
         /// Write markdown transcript to file
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Print each response live as it arrives
+        #[arg(long)]
+        stream: bool,
+
+        /// Prefix each streamed line with the backend name
+        #[arg(long, requires = "stream")]
+        prefix: bool,
     },
 
     /// Suggest which backend to use for a task
@@ -380,28 +415,53 @@ enum WorkflowCommands {
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let config = config::load_config(cli.config.as_deref())?;
+    let mut config = config::load_config(cli.config.as_deref())?;
+    if let Some(ref dir) = cli.record {
+        config.traffic = Some(backend::Traffic::record(dir)?);
+    } else if let Some(ref dir) = cli.replay {
+        config.traffic = Some(backend::Traffic::replay(dir)?);
+    }
 
     match cli.command {
         Commands::Ask {
             prompt,
             backend,
             dir,
+            files,
             no_cache,
+            stream,
+            prefix,
         } => {
             let backends = backend::get_backends(&config, backend.as_deref())?;
+            let request = backend::QueryRequest {
+                attachments: backend::attachment::load_all(&files, &dir)?,
+                ..backend::QueryRequest::new(prompt.as_str())
+            };
             if cli.verbose {
-                backend::print_verbose_header(&prompt, &backends, &dir);
+                backend::print_verbose_header(&prompt, &backends, &dir, &config);
+                if !request.attachments.is_empty() {
+                    let paths: Vec<&str> = request.attachments.iter().map(|a| a.path()).collect();
+                    println!("{} {}", "Attachments:".dimmed(), paths.join(", "));
+                    println!();
+                }
             }
 
             let backend_names: Vec<String> =
                 backends.iter().map(|b| b.name().to_string()).collect();
             let cwd = crate::utils::canonicalize_async(&dir).await;
             let cwd_str = cwd.to_string_lossy().to_string();
+            // Cached answers would never reach the recorder or replayer
+            let no_cache = no_cache || config.traffic.is_some();
 
             // Check cache first (unless --no-cache)
             let mut cache = cache::Cache::new(&config.cache);
-            let cache_key = cache.cache_key(&prompt, &backend_names, &cwd_str);
+            // Attached files are part of the question
+            let cache_prompt = if request.attachments.is_empty() {
+                prompt.clone()
+            } else {
+                format!("{}\n{}", prompt, serde_json::to_string(&request.attachments)?)
+            };
+            let cache_key = cache.cache_key(&cache_prompt, &backend_names, &cwd_str);
 
             if !no_cache {
                 if let Some(cached_results) = cache.get(&cache_key).await {
@@ -412,17 +472,29 @@ async fn main() -> Result<()> {
                 }
             }
 
-            let results = backend::run_query(&backends, &prompt, &dir, &config).await?;
+            let results = if stream {
+                backend::run_request_streaming(&backends, request, &dir, &config, prefix).await?
+            } else {
+                backend::run_request(&backends, request, &dir, &config).await?
+            };
 
             // Cache the results
             if !no_cache {
                 cache.set(&cache_key, &results).await;
             }
 
-            output::print_results(&results);
+            if stream {
+                // Answers were already printed live; only errors are left to show
+                let failed: Vec<_> = results.iter().filter(|r| !r.success).cloned().collect();
+                if !failed.is_empty() {
+                    output::print_results(&failed);
+                }
+            } else {
+                output::print_results(&results);
+            }
 
             if cli.verbose {
-                backend::print_verbose_timing(&results);
+                backend::print_verbose_timing(&results, &config);
             }
 
             cache.print_warnings();
@@ -487,8 +559,8 @@ async fn main() -> Result<()> {
         Commands::Backends => {
             backend::list_backends(&config)?;
         }
-        Commands::Conduct { task, dir } => {
-            let conductor = conductor::Conductor::new(&config)?;
+        Commands::Conduct { task, dir, backend } => {
+            let conductor = conductor::Conductor::new(&config, backend.as_deref())?;
             let result = conductor.conduct(&task, &dir).await?;
             println!();
             println!("{}", "=== Final Result ===".green().bold());
@@ -500,9 +572,17 @@ async fn main() -> Result<()> {
             dir,
             backend,
             output,
+            stream,
+            prefix,
         } => {
-            let backends = backend::get_backends(&config, backend.as_deref())?;
-            let debate = debate::Debate::new(backends, &topic, &dir, &config);
+            // One breaker for every round of the debate
+            let breaker = backend::CircuitBreaker::new(&config.circuit_breaker);
+            let backends =
+                backend::get_backends_with_breaker(&config, backend.as_deref(), &breaker)?;
+            let mut debate = debate::Debate::new(backends, &topic, &dir, &config);
+            if stream {
+                debate = debate.with_streaming(prefix);
+            }
             let result = debate.run().await?;
             println!();
             println!("{}", result.summary);
@@ -549,14 +629,14 @@ async fn main() -> Result<()> {
                     let backends = backend::get_backends(&config, Some(backend_name))?;
 
                     if cli.verbose {
-                        backend::print_verbose_header(&prompt, &backends, &dir);
+                        backend::print_verbose_header(&prompt, &backends, &dir, &config);
                     }
 
                     let results = backend::run_query(&backends, &prompt, &dir, &config).await?;
                     output::print_results(&results);
 
                     if cli.verbose {
-                        backend::print_verbose_timing(&results);
+                        backend::print_verbose_timing(&results, &config);
                     }
                 }
                 None => {
@@ -567,14 +647,14 @@ async fn main() -> Result<()> {
                     let backends = backend::get_backends(&config, None)?;
 
                     if cli.verbose {
-                        backend::print_verbose_header(&prompt, &backends, &dir);
+                        backend::print_verbose_header(&prompt, &backends, &dir, &config);
                     }
 
                     let results = backend::run_query(&backends, &prompt, &dir, &config).await?;
                     output::print_results(&results);
 
                     if cli.verbose {
-                        backend::print_verbose_timing(&results);
+                        backend::print_verbose_timing(&results, &config);
                     }
                 }
             }
@@ -597,26 +677,14 @@ async fn main() -> Result<()> {
             println!("{}", "Checking backends...".yellow());
             println!();
 
-            let checks = vec![
-                ("codex", "codex", "npm install -g @openai/codex"),
-                ("gemini", "npx", "Install Node.js (npx comes with npm)"),
-                (
-                    "claude",
-                    "claude",
-                    "Install Claude Code: https://claude.ai/claude-code",
-                ),
-            ];
+            let checks = backend::check_health(&config).await;
+            let width = checks.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
 
             let mut available = 0;
-            for (name, binary, install_hint) in &checks {
-                let found = which::which(binary).is_ok();
-
-                if found {
-                    println!("  {} {} - ready", "✓".green(), name);
+            for (name, health) in &checks {
+                output::print_health(name, health, width);
+                if health.is_ready() {
                     available += 1;
-                } else {
-                    println!("  {} {} - not found", "✗".red(), name);
-                    println!("    {}", install_hint.dimmed());
                 }
             }
 
@@ -688,13 +756,13 @@ async fn main() -> Result<()> {
                 output,
                 args,
             } => {
-                run_workflow(&name, &dir, output.as_deref(), args, &config).await?;
+                run_workflow(&name, &dir, output.as_deref(), args, &config, cli.verbose).await?;
             }
             WorkflowCommands::List => {
                 list_workflows().await?;
             }
             WorkflowCommands::Validate { path } => {
-                validate_workflow(&path).await?;
+                validate_workflow(&path, &config).await?;
             }
         },
         Commands::Run {
@@ -704,7 +772,7 @@ async fn main() -> Result<()> {
             args,
         } => {
             // Shorthand for 'workflow run'
-            run_workflow(&name, &dir, output.as_deref(), args, &config).await?;
+            run_workflow(&name, &dir, output.as_deref(), args, &config, cli.verbose).await?;
         }
         Commands::Context {
             dir,
@@ -983,18 +1051,19 @@ async fn run_workflow(
     output: Option<&Path>,
     args: Vec<String>,
     config: &config::Config,
+    verbose: bool,
 ) -> Result<()> {
     let source = workflow::find_workflow(name).await?;
     let wf = workflow::load_workflow_from_source(source).await?;
 
     let cwd = crate::utils::canonicalize_async(dir).await;
-    let runner = workflow::WorkflowRunner::new(config.clone(), cwd, args);
+    let runner = workflow::WorkflowRunner::new(config.clone(), cwd, args).verbose(verbose);
 
     let results = runner.run(&wf).await?;
 
     if let Some(output_path) = output {
         // Write full results to file
-        let output_str = workflow::format_results(&results);
+        let output_str = workflow::format_results(&results, config);
         tokio::fs::write(output_path, &output_str)
             .await
             .with_context(|| format!("Failed to write output to {}", output_path.display()))?;
@@ -1004,7 +1073,7 @@ async fn run_workflow(
             output_path.display()
         );
     } else {
-        workflow::print_results(&results);
+        workflow::print_results(&results, config);
     }
 
     Ok(())
@@ -1300,8 +1369,9 @@ async fn list_workflows() -> Result<()> {
     Ok(())
 }
 
-async fn validate_workflow(path: &Path) -> Result<()> {
+async fn validate_workflow(path: &Path, config: &config::Config) -> Result<()> {
     let wf = workflow::load_workflow(path).await?;
+    wf.check_permissions(config)?;
 
     println!("{} {}", "✓".green(), "Workflow is valid".bold());
     println!();
@@ -1422,14 +1492,14 @@ Be concise and specific. Reference line numbers when possible.
     let backends = backend::get_backends(config, backend_filter)?;
 
     if verbose {
-        backend::print_verbose_header(&prompt, &backends, dir);
+        backend::print_verbose_header(&prompt, &backends, dir, config);
     }
 
     let results = backend::run_query(&backends, &prompt, dir, config).await?;
     output::print_results(&results);
 
     if verbose {
-        backend::print_verbose_timing(&results);
+        backend::print_verbose_timing(&results, config);
     }
 
     Ok(())
@@ -1580,14 +1650,14 @@ Organize your review by severity (critical, important, minor, nitpick)."#
     let backends = backend::get_backends(config, backend_filter)?;
 
     if verbose {
-        backend::print_verbose_header(&prompt, &backends, &cwd);
+        backend::print_verbose_header(&prompt, &backends, &cwd, config);
     }
 
     let results = backend::run_query(&backends, &prompt, &cwd, config).await?;
     output::print_results(&results);
 
     if verbose {
-        backend::print_verbose_timing(&results);
+        backend::print_verbose_timing(&results, config);
     }
 
     Ok(())
@@ -1780,14 +1850,14 @@ Be concise but thorough. A developer new to this codebase should understand the
     let backends = backend::get_backends(config, backend_filter)?;
 
     if verbose {
-        backend::print_verbose_header(&prompt, &backends, dir);
+        backend::print_verbose_header(&prompt, &backends, dir, config);
     }
 
     let results = backend::run_query(&backends, &prompt, dir, config).await?;
     output::print_results(&results);
 
     if verbose {
-        backend::print_verbose_timing(&results);
+        backend::print_verbose_timing(&results, config);
     }
 
     Ok(())
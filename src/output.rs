@@ -1,5 +1,12 @@
-use crate::backend::QueryResult;
+use crate::backend::health::HealthStatus;
+use crate::backend::{Health, QueryResult};
+use crate::config::Config;
+use crate::usage::{self, UsageByBackend};
 use colored::Colorize;
+use std::io::Write;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
 
 pub fn print_results(results: &[QueryResult]) {
     for result in results {
@@ -11,6 +18,9 @@ pub fn print_results(results: &[QueryResult]) {
         } else {
             println!("{}", header.red().bold());
         }
+        if let Some(ref fallback) = result.fallback {
+            println!("{}", fallback.to_string().dimmed());
+        }
 
         println!();
         println!("{}", result.output);
@@ -19,6 +29,50 @@ pub fn print_results(results: &[QueryResult]) {
     println!();
 }
 
+/// Print token usage and estimated cost, if any backend reported usage
+pub fn print_usage_summary(usage: &UsageByBackend, config: &Config) {
+    if usage.is_empty() {
+        return;
+    }
+    println!("{}", "Usage:".bold());
+    for line in usage::summary_lines(usage, config) {
+        println!("  {}", line.dimmed());
+    }
+    println!();
+}
+
+/// One `lok doctor` line per backend, plus the fix hint when there is one
+pub fn print_health(name: &str, health: &Health, width: usize) {
+    let icon = match health.status {
+        HealthStatus::Ready => "✓".green(),
+        HealthStatus::Warning => "!".yellow(),
+        HealthStatus::Unavailable => "✗".red(),
+    };
+
+    let mut line = format!("  {} {:width$}", icon, name, width = width);
+    match (health.status, &health.detail) {
+        (HealthStatus::Ready, Some(detail)) => {
+            line.push_str(&format!("  {}  {}", "ready".green(), detail.dimmed()))
+        }
+        (_, Some(detail)) => line.push_str(&format!("  {}", detail)),
+        (_, None) => line.push_str(&format!("  {}", "ready".green())),
+    }
+    if let Some(ref version) = health.version {
+        line.push_str(&format!("  {}", format!("v{}", version).dimmed()));
+    }
+    if let Some(latency) = health.latency {
+        line.push_str(&format!(
+            "  {}",
+            format!("{}ms", latency.as_millis()).dimmed()
+        ));
+    }
+    println!("{}", line);
+
+    if let Some(ref hint) = health.hint {
+        println!("    {} {}", "→".cyan(), hint.dimmed());
+    }
+}
+
 pub fn print_task_header(task_name: &str, description: Option<&str>) {
     println!();
     println!("{}", format!("Task: {}", task_name).cyan().bold());
@@ -32,3 +86,199 @@ pub fn print_prompt_header(prompt_name: &str) {
     println!();
     println!("{}", format!("[{}]", prompt_name).yellow().bold());
 }
+
+/// Formats streamed backend output for the terminal.
+///
+/// Without a prefix, chunks pass through untouched under a `=== BACKEND ===` header.
+/// With a prefix, output is line-buffered and each line is tagged `[backend]`, so
+/// several backends streaming in parallel stay readable. A printer without a
+/// prefix switches to one as soon as another stream is live, since their
+/// fragments would otherwise interleave mid-line.
+pub struct StreamPrinter {
+    backend: String,
+    prefix: bool,
+    started: bool,
+    pending: String,
+    live: Arc<Mutex<LiveStreams>>,
+}
+
+/// Streams being printed right now, across the whole process
+#[derive(Default)]
+struct LiveStreams {
+    count: usize,
+    /// Passthrough output left a line unfinished
+    line_open: bool,
+}
+
+static LIVE_STREAMS: LazyLock<Arc<Mutex<LiveStreams>>> = LazyLock::new(Arc::default);
+
+impl StreamPrinter {
+    pub fn new(backend: &str, prefix: bool) -> Self {
+        Self::sharing(backend, prefix, Arc::clone(&LIVE_STREAMS))
+    }
+
+    fn sharing(backend: &str, prefix: bool, live: Arc<Mutex<LiveStreams>>) -> Self {
+        live.lock().unwrap_or_else(|e| e.into_inner()).count += 1;
+        Self {
+            backend: backend.to_string(),
+            prefix,
+            started: false,
+            pending: String::new(),
+            live,
+        }
+    }
+
+    /// Take a chunk and return the text to write now
+    pub fn feed(&mut self, chunk: &str) -> String {
+        let live = Arc::clone(&self.live);
+        let mut live = live.lock().unwrap_or_else(|e| e.into_inner());
+        if live.count > 1 {
+            self.prefix = true;
+        }
+        let mut out = String::new();
+
+        if !self.prefix {
+            if !self.started && !chunk.is_empty() {
+                let header = format!("=== {} ===", self.backend.to_uppercase());
+                out.push_str(&format!("\n{}\n\n", header.green().bold()));
+                self.started = true;
+            }
+            out.push_str(chunk);
+            if !chunk.is_empty() {
+                live.line_open = !chunk.ends_with('\n');
+            }
+            return out;
+        }
+
+        self.pending.push_str(chunk);
+        while let Some(pos) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=pos).collect();
+            out.push_str(&self.prefixed(&mut live, line.trim_end_matches(['\n', '\r'])));
+        }
+        out
+    }
+
+    /// Flush anything left once the stream ends
+    pub fn finish(&mut self) -> String {
+        let live = Arc::clone(&self.live);
+        let mut live = live.lock().unwrap_or_else(|e| e.into_inner());
+        if self.prefix {
+            let pending = std::mem::take(&mut self.pending);
+            if pending.is_empty() {
+                String::new()
+            } else {
+                self.prefixed(&mut live, &pending)
+            }
+        } else if std::mem::take(&mut live.line_open) {
+            "\n".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// A tagged line, on a line of its own even if passthrough output left one open
+    fn prefixed(&self, live: &mut LiveStreams, line: &str) -> String {
+        let newline = if std::mem::take(&mut live.line_open) {
+            "\n"
+        } else {
+            ""
+        };
+        format!(
+            "{}{} {}\n",
+            newline,
+            format!("[{}]", self.backend).cyan(),
+            line
+        )
+    }
+}
+
+impl Drop for StreamPrinter {
+    fn drop(&mut self) {
+        self.live.lock().unwrap_or_else(|e| e.into_inner()).count -= 1;
+    }
+}
+
+/// Print chunks from `rx` live until the sender is dropped
+pub fn spawn_stream_printer(
+    backend: &str,
+    prefix: bool,
+    mut rx: UnboundedReceiver<String>,
+) -> JoinHandle<()> {
+    let mut printer = StreamPrinter::new(backend, prefix);
+    tokio::spawn(async move {
+        while let Some(chunk) = rx.recv().await {
+            write_stdout(&printer.feed(&chunk));
+        }
+        write_stdout(&printer.finish());
+    })
+}
+
+fn write_stdout(text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_printer_prefix_buffers_lines() {
+        let mut printer = StreamPrinter::sharing("codex", true, Arc::default());
+        assert_eq!(printer.feed("hel"), "");
+        let out = printer.feed("lo\nwor");
+        assert!(out.contains("[codex]"), "got: {}", out);
+        assert!(out.ends_with(" hello\n"), "got: {}", out);
+        let rest = printer.finish();
+        assert!(rest.contains("[codex]"));
+        assert!(rest.ends_with(" wor\n"));
+    }
+
+    #[test]
+    fn test_stream_printer_passthrough() {
+        let mut printer = StreamPrinter::sharing("ollama", false, Arc::default());
+        let first = printer.feed("Hello");
+        assert!(first.contains("=== OLLAMA ==="));
+        assert!(first.ends_with("Hello"));
+        assert_eq!(printer.feed(" world"), " world");
+        // Unterminated output gets a closing newline
+        assert_eq!(printer.finish(), "\n");
+    }
+
+    #[test]
+    fn test_concurrent_streams_are_prefixed() {
+        let live = Arc::default();
+        let mut claude = StreamPrinter::sharing("claude", false, Arc::clone(&live));
+        let first = claude.feed("Hel");
+        assert!(first.contains("=== CLAUDE ==="), "alone, it passes through");
+        assert!(first.ends_with("Hel"));
+
+        let mut gemini = StreamPrinter::sharing("gemini", false, Arc::clone(&live));
+        assert_eq!(gemini.feed("Hi"), "");
+        let out = gemini.feed(" there\n");
+        assert!(!out.contains("==="), "got: {}", out);
+        assert!(
+            out.starts_with('\n'),
+            "claude's line is closed first: {:?}",
+            out
+        );
+        assert!(out.contains("[gemini]"), "got: {}", out);
+        assert!(out.ends_with(" Hi there\n"), "got: {}", out);
+
+        let out = claude.feed("lo\n");
+        assert!(
+            out.starts_with(&"[claude]".cyan().to_string()),
+            "got: {:?}",
+            out
+        );
+        assert!(out.ends_with(" lo\n"), "got: {}", out);
+        drop(gemini);
+        assert_eq!(claude.finish(), "");
+        drop(claude);
+        assert_eq!(live.lock().unwrap().count, 0);
+    }
+}
@@ -1,10 +1,9 @@
-use crate::backend::{self, Backend};
+use crate::backend::{self, Backend, QueryRequest};
 use crate::config::Config;
 use crate::delegation::Delegator;
 use anyhow::{Context, Result};
 use colored::Colorize;
 use futures::future::join_all;
-use secrecy::ExposeSecret;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -30,6 +29,8 @@ pub struct Spawn {
     config: Config,
     cwd: std::path::PathBuf,
     delegator: Delegator,
+    /// Shared by planning and every agent
+    breaker: backend::CircuitBreaker,
 }
 
 impl Spawn {
@@ -38,6 +39,7 @@ impl Spawn {
             config: config.clone(),
             cwd: crate::utils::canonicalize_async(cwd).await,
             delegator: Delegator::new(),
+            breaker: backend::CircuitBreaker::new(&config.circuit_breaker),
         })
     }
 
@@ -71,11 +73,8 @@ impl Spawn {
     }
 
     async fn plan_with_conductor(&self, task: &str) -> Result<Vec<AgentTask>> {
-        let claude = backend::create_claude_backend(&self.config)?;
-
-        let (api_key, model, client) = claude
-            .api_details()
-            .ok_or_else(|| anyhow::anyhow!("Claude API required for planning"))?;
+        // Built like any other query, so --record and --replay cover it
+        let claude = backend::build_backend("claude", &self.config, Some(&self.breaker))?;
 
         let system = r#"You are a task planner. Break down the given task into 2-4 parallel subtasks that can be worked on independently.
 
@@ -94,66 +93,26 @@ Rules:
 - Names should be short (one word)
 - Descriptions should be actionable"#;
 
-        let request = serde_json::json!({
-            "model": model,
-            "max_tokens": 1024,
-            "system": system,
-            "messages": [{
-                "role": "user",
-                "content": format!("Break down this task into parallel subtasks:\n\n{}", task)
-            }]
-        });
+        let request = QueryRequest {
+            system: Some(system.to_string()),
+            max_tokens: Some(1024),
+            ..QueryRequest::new(format!(
+                "Break down this task into parallel subtasks:\n\n{}",
+                task
+            ))
+        };
 
         println!("{}", "Asking conductor to plan...".dimmed());
 
-        let response = client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", api_key.expose_secret())
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
+        let completion = claude
+            .complete(&request, &self.cwd)
             .await
             .context("Failed to send planning request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("Claude API error {}: {}", status, body);
-        }
-
-        let response: serde_json::Value = response.json().await?;
-
-        // Extract text from response, handling unexpected shapes
-        let text = match response
-            .get("content")
-            .and_then(|c| c.as_array())
-            .and_then(|arr| arr.first())
-            .and_then(|block| block.get("text"))
-            .and_then(|t| t.as_str())
-        {
-            Some(text) => text,
-            None => {
-                // Response shape unexpected - show what we got for debugging
-                let response_preview = serde_json::to_string_pretty(&response)
-                    .unwrap_or_else(|_| format!("{:?}", response));
-                anyhow::bail!(
-                    "Unexpected response shape from Claude API. Expected content[0].text.\n\
-                    Response preview:\n{}",
-                    if response_preview.len() > 500 {
-                        format!("{}...", crate::utils::truncate_utf8(&response_preview, 500))
-                    } else {
-                        response_preview
-                    }
-                );
-            }
-        };
-
-        self.parse_agent_tasks(text)
+        self.parse_agent_tasks(&completion.text)
     }
 
     async fn plan_with_backend(&self, task: &str) -> Result<Vec<AgentTask>> {
-        let backends = backend::get_backends(&self.config, None)?;
+        let backends = backend::get_backends_with_breaker(&self.config, None, &self.breaker)?;
         let backend = backends
             .first()
             .ok_or_else(|| anyhow::anyhow!("No backends available"))?;
@@ -226,7 +185,7 @@ AGENT: frontend | Build the UI"#,
         println!("{}", "=".repeat(50).dimmed());
         println!();
 
-        let backends = backend::get_backends(&self.config, None)?;
+        let backends = backend::get_backends_with_breaker(&self.config, None, &self.breaker)?;
         let backend_map: std::collections::HashMap<String, Arc<dyn Backend>> = backends
             .into_iter()
             .map(|b| (b.name().to_string(), b))
@@ -2,6 +2,7 @@ use crate::backend::{self, QueryResult};
 use crate::config::Config;
 use crate::context::CodebaseContext;
 use crate::output;
+use crate::usage::UsageByBackend;
 use anyhow::{Context, Result};
 use colored::Colorize;
 use std::collections::HashSet;
@@ -122,6 +123,8 @@ pub async fn run(
         all_results.extend(results);
     }
 
+    output::print_usage_summary(&UsageByBackend::from_results(&all_results), config);
+
     // Create issues if requested
     if create_issues {
         let backend = IssueBackend::from_str(issue_backend, dir)?;
@@ -359,10 +359,12 @@ pub async fn run(
                             r.output.lines().next().unwrap_or("no output")
                         ));
 
-                        // Only retry on unknown errors or network errors
+                        // Only retry on unknown errors, network errors or timeouts
                         if matches!(
                             kind,
-                            BackendErrorKind::Unknown | BackendErrorKind::NetworkError
+                            BackendErrorKind::Unknown
+                                | BackendErrorKind::NetworkError
+                                | BackendErrorKind::Timeout
                         ) {
                             should_retry = true;
                         }
@@ -10,6 +10,7 @@ use crate::backend;
 use crate::config::Config;
 use crate::context::CodebaseContext;
 use crate::output;
+use crate::usage::UsageByBackend;
 use anyhow::Result;
 use std::path::Path;
 
@@ -33,6 +34,8 @@ pub async fn run_task(config: &Config, task_name: &str, dir: &Path) -> Result<()
 
     let backends = backend::get_backends(config, backend_filter.as_deref())?;
 
+    let mut total_usage = UsageByBackend::default();
+
     // Run each prompt
     for prompt_config in &task.prompts {
         output::print_prompt_header(&prompt_config.name);
@@ -47,8 +50,11 @@ pub async fn run_task(config: &Config, task_name: &str, dir: &Path) -> Result<()
 
         let results = backend::run_query(&backends, &prompt_with_context, dir, config).await?;
         output::print_results(&results);
+        total_usage.merge(&UsageByBackend::from_results(&results));
     }
 
+    output::print_usage_summary(&total_usage, config);
+
     Ok(())
 }
 
@@ -0,0 +1,982 @@
+//! Templates for step prompts and shell commands
+//!
+//! ```text
+//! Review {{ steps.diff.output | truncate(20000) }}
+//! {% if "error" in steps.build.output %}
+//! The build failed: {{ steps.build.output | lines | last }}
+//! {% elif steps.scan.findings | length > 0 %}
+//! {% for finding in steps.scan.findings %}
+//! {{ loop.index }}. {{ finding.file }}: {{ finding.message | default("no details") }}
+//! {% endfor %}
+//! {% else %}
+//! Nothing to report.
+//! {% endif %}
+//! {% raw %}{{ left as is }}{% endraw %}
+//! ```
+//!
+//! `{{ ... }}` and the `{% if %}` / `{% for %}` conditions are expressions
+//! (see [`crate::condition`]); variables come from a [`Scope`]. A variable
+//! that isn't there renders as `[name not found]`. Loops run over JSON arrays,
+//! or text that parses as one, and set `loop.index` (from 1), `loop.first`
+//! and `loop.last`. A block tag alone on its line leaves no blank line.
+//!
+//! Templates for shell commands ([`Template::for_shell`]) quote every value
+//! for where it lands: `echo {{ x }}`, `echo 'said: {{ x }}'` and
+//! `echo "said: {{ x }}"` all pass `x` as text, whatever quotes or `$(...)`
+//! it holds. `{{ x | raw }}` inserts it unquoted, as shell syntax.
+
+use crate::condition::{self, ConditionError, Expression, Scope, Segment};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::LazyLock;
+use thiserror::Error;
+
+/// Placeholders for `{{` and `{%` in values rendered by
+/// [`Template::render_partial`], so rendering the result again leaves them be
+const ESCAPED_OPEN_VALUE: &str = "\x00LOK_OPEN_VALUE\x00";
+const ESCAPED_OPEN_TAG: &str = "\x00LOK_OPEN_TAG\x00";
+
+static ENDRAW_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{%\s*endraw\s*%\}").unwrap());
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("line {line}: {error}")]
+    Render { line: usize, error: ConditionError },
+}
+
+/// A parsed template
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+    /// Quote values as shell words
+    shell: bool,
+}
+
+#[derive(Debug)]
+struct Node {
+    kind: NodeKind,
+    /// The node's template text, written back when it can't be rendered yet
+    source: String,
+    line: usize,
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Text(String),
+    Raw(String),
+    Value {
+        expression: Expression,
+        /// Between the braces, for `[name not found]`
+        text: String,
+    },
+    If {
+        branches: Vec<(Expression, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        var: String,
+        items: Expression,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A piece of template source
+#[derive(Debug, Clone, Copy)]
+enum Piece<'a> {
+    Text(&'a str),
+    Value(&'a str),
+    /// A block tag's words, e.g. `if x == 1`
+    Tag(&'a str),
+    Raw(&'a str),
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let pieces = lex(source)?;
+        let mut parser = Parser {
+            source,
+            pieces,
+            pos: 0,
+        };
+        let (nodes, end) = parser.nodes(&[])?;
+        if let Some((tag, start)) = end {
+            return Err(TemplateError::Parse {
+                line: line_at(source, start),
+                message: format!("unexpected {{% {} %}}", tag),
+            });
+        }
+        Ok(Self {
+            nodes,
+            shell: false,
+        })
+    }
+
+    /// Render values quoted for the shell, unless they end in `| raw`
+    pub fn for_shell(mut self) -> Self {
+        self.shell = true;
+        self
+    }
+
+    /// Render with every variable in `scope`
+    pub fn render(&self, scope: &dyn Scope) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        let renderer = Renderer {
+            escape: false,
+            shell: self.shell,
+        };
+        renderer.nodes(&self.nodes, scope, &mut out)?;
+        Ok(unescape(&out))
+    }
+
+    /// Render what `scope` can: nodes reading a variable it defers (like
+    /// `item` before a loop) are written back as they were, for a later
+    /// [`Template::render`] of the result. Rendered values are escaped so
+    /// that render leaves them alone.
+    pub fn render_partial(&self, scope: &dyn Scope) -> Result<String, TemplateError> {
+        let renderer = Renderer {
+            escape: true,
+            shell: self.shell,
+        };
+        let mut out = String::new();
+        for node in &self.nodes {
+            // Rendered into `out` itself, so shell values see the quotes
+            // before them
+            let start = out.len();
+            match renderer.node(node, scope, &mut out) {
+                Ok(()) => {}
+                Err(TemplateError::Render {
+                    error: ConditionError::Deferred(_),
+                    ..
+                }) => {
+                    out.truncate(start);
+                    out.push_str(&node.source);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+fn unescape(s: &str) -> String {
+    s.replace(ESCAPED_OPEN_VALUE, "{{")
+        .replace(ESCAPED_OPEN_TAG, "{%")
+}
+
+fn escape(s: &str) -> String {
+    s.replace("{{", ESCAPED_OPEN_VALUE)
+        .replace("{%", ESCAPED_OPEN_TAG)
+}
+
+/// Split `source` into pieces with their start and end offsets. A block tag
+/// alone on its line takes the line's indentation and newline with it.
+fn lex(source: &str) -> Result<Vec<(Piece<'_>, usize, usize)>, TemplateError> {
+    let error = |offset: usize, message: String| TemplateError::Parse {
+        line: line_at(source, offset),
+        message,
+    };
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+    while pos < source.len() {
+        let Some(start) = next_open(source, pos) else {
+            pieces.push((Piece::Text(&source[pos..]), pos, source.len()));
+            break;
+        };
+        let is_tag = source[start..].starts_with("{%");
+        let close = if is_tag { "%}" } else { "}}" };
+        let inner_start = start + 2;
+        let inner_end = find_close(&source[inner_start..], close)
+            .map(|i| inner_start + i)
+            .ok_or_else(|| {
+                error(
+                    start,
+                    format!("'{}' is never closed", &source[start..start + 2]),
+                )
+            })?;
+        let inner = source[inner_start..inner_end].trim();
+        let mut end = inner_end + 2;
+
+        let mut text_end = start;
+        if is_tag {
+            // Indentation before a tag alone on its line
+            let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+            if line_start >= pos
+                && source[line_start..start]
+                    .chars()
+                    .all(|c| c == ' ' || c == '\t')
+            {
+                text_end = line_start;
+            }
+        }
+        if text_end > pos {
+            pieces.push((Piece::Text(&source[pos..text_end]), pos, text_end));
+        }
+
+        if !is_tag {
+            pieces.push((Piece::Value(inner), start, end));
+        } else if inner == "raw" {
+            let body_start = skip_newline(source, end);
+            let endraw = ENDRAW_RE
+                .find(&source[body_start..])
+                .ok_or_else(|| error(start, "{% raw %} without {% endraw %}".to_string()))?;
+            end = skip_newline(source, body_start + endraw.end());
+            let body = &source[body_start..body_start + endraw.start()];
+            pieces.push((Piece::Raw(body), start, end));
+        } else {
+            end = skip_newline(source, end);
+            pieces.push((Piece::Tag(inner), start, end));
+        }
+        pos = end;
+    }
+    Ok(pieces)
+}
+
+fn next_open(source: &str, from: usize) -> Option<usize> {
+    let value = source[from..].find("{{");
+    let tag = source[from..].find("{%");
+    let first = match (value, tag) {
+        (Some(a), Some(b)) => a.min(b),
+        (a, b) => a.or(b)?,
+    };
+    Some(from + first)
+}
+
+/// Offset of `close` in `text`, skipping quoted strings
+fn find_close(text: &str, close: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if text[i..].starts_with(close) => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Past the newline at `pos`, if there is one
+fn skip_newline(source: &str, pos: usize) -> usize {
+    if source[pos..].starts_with("\r\n") {
+        pos + 2
+    } else if source[pos..].starts_with('\n') {
+        pos + 1
+    } else {
+        pos
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pieces: Vec<(Piece<'a>, usize, usize)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, offset: usize, message: String) -> TemplateError {
+        TemplateError::Parse {
+            line: line_at(self.source, offset),
+            message,
+        }
+    }
+
+    fn expression(&self, text: &str, offset: usize) -> Result<Expression, TemplateError> {
+        Expression::parse(text).map_err(|e| self.error(offset, format!("{} in '{}'", e, text)))
+    }
+
+    /// Nodes up to a tag whose first word is in `until`, returned with the
+    /// tag and its start; or to the end of the template
+    #[allow(clippy::type_complexity)]
+    fn nodes(
+        &mut self,
+        until: &[&str],
+    ) -> Result<(Vec<Node>, Option<(&'a str, usize)>), TemplateError> {
+        let mut nodes = Vec::new();
+        while let Some(&(piece, start, end)) = self.pieces.get(self.pos) {
+            self.pos += 1;
+            let source = self.source[start..end].to_string();
+            let line = line_at(self.source, start);
+            let kind = match piece {
+                Piece::Text(text) => NodeKind::Text(text.to_string()),
+                Piece::Raw(text) => NodeKind::Raw(text.to_string()),
+                Piece::Value(text) => NodeKind::Value {
+                    expression: self.expression(text, start)?,
+                    text: text.to_string(),
+                },
+                Piece::Tag(tag) => {
+                    let word = tag.split_whitespace().next().unwrap_or_default();
+                    if until.contains(&word) {
+                        return Ok((nodes, Some((tag, start))));
+                    }
+                    let (kind, end) = match word {
+                        "if" => self.if_block(tag, start)?,
+                        "for" => self.for_block(tag, start)?,
+                        _ => return Err(self.error(start, format!("unexpected {{% {} %}}", tag))),
+                    };
+                    nodes.push(Node {
+                        kind,
+                        source: self.source[start..end].to_string(),
+                        line,
+                    });
+                    continue;
+                }
+            };
+            nodes.push(Node { kind, source, line });
+        }
+        Ok((nodes, None))
+    }
+
+    /// The body of a block up to one of `until`, which must come
+    fn body(
+        &mut self,
+        until: &[&str],
+        opened: &str,
+        start: usize,
+    ) -> Result<(Vec<Node>, &'a str, usize), TemplateError> {
+        match self.nodes(until)? {
+            (nodes, Some((tag, tag_start))) => Ok((nodes, tag, tag_start)),
+            (_, None) => Err(self.error(
+                start,
+                format!(
+                    "{{% {} %}} without {{% {} %}}",
+                    opened,
+                    until.last().unwrap()
+                ),
+            )),
+        }
+    }
+
+    /// End offset of the piece just consumed
+    fn consumed_end(&self) -> usize {
+        self.pieces[self.pos - 1].2
+    }
+
+    fn if_block(&mut self, tag: &str, start: usize) -> Result<(NodeKind, usize), TemplateError> {
+        let mut branches = Vec::new();
+        let mut condition = self.expression(keyword_rest(tag, "if"), start)?;
+        let mut otherwise = Vec::new();
+        loop {
+            let (body, end_tag, end_start) = self.body(&["elif", "else", "endif"], tag, start)?;
+            branches.push((condition, body));
+            match end_tag.split_whitespace().next() {
+                Some("elif") => {
+                    condition = self.expression(keyword_rest(end_tag, "elif"), end_start)?
+                }
+                Some("else") => {
+                    let (body, _, _) = self.body(&["endif"], tag, start)?;
+                    otherwise = body;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Ok((
+            NodeKind::If {
+                branches,
+                otherwise,
+            },
+            self.consumed_end(),
+        ))
+    }
+
+    fn for_block(&mut self, tag: &str, start: usize) -> Result<(NodeKind, usize), TemplateError> {
+        let rest = keyword_rest(tag, "for");
+        let (var, items) = rest
+            .split_once(" in ")
+            .map(|(var, items)| (var.trim(), items.trim()))
+            .filter(|(var, _)| {
+                !var.is_empty() && var.chars().all(|c| c.is_alphanumeric() || c == '_')
+            })
+            .ok_or_else(|| {
+                self.error(
+                    start,
+                    format!("expected {{% for NAME in ... %}}, found {{% {} %}}", tag),
+                )
+            })?;
+        let items = self.expression(items, start)?;
+        let (body, end_tag, _) = self.body(&["else", "endfor"], tag, start)?;
+        let otherwise = if end_tag.trim() == "else" {
+            self.body(&["endfor"], tag, start)?.0
+        } else {
+            Vec::new()
+        };
+        let kind = NodeKind::For {
+            var: var.to_string(),
+            items,
+            body,
+            otherwise,
+        };
+        Ok((kind, self.consumed_end()))
+    }
+}
+
+/// `tag` without its leading keyword
+fn keyword_rest<'t>(tag: &'t str, keyword: &str) -> &'t str {
+    tag.strip_prefix(keyword).unwrap_or(tag).trim()
+}
+
+struct Renderer {
+    /// Escape rendered values, for [`Template::render_partial`]
+    escape: bool,
+    /// Quote values for the shell, for [`Template::for_shell`]
+    shell: bool,
+}
+
+impl Renderer {
+    fn nodes(
+        &self,
+        nodes: &[Node],
+        scope: &dyn Scope,
+        out: &mut String,
+    ) -> Result<(), TemplateError> {
+        for node in nodes {
+            self.node(node, scope, out)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, out: &mut String, text: &str) {
+        if self.escape {
+            out.push_str(&escape(text));
+        } else {
+            out.push_str(text);
+        }
+    }
+
+    fn node(&self, node: &Node, scope: &dyn Scope, out: &mut String) -> Result<(), TemplateError> {
+        let error = |error| TemplateError::Render {
+            line: node.line,
+            error,
+        };
+        match &node.kind {
+            NodeKind::Text(text) => out.push_str(text),
+            NodeKind::Raw(text) => self.write(out, text),
+            NodeKind::Value { expression, text } => {
+                let value = match expression.evaluate_var(scope).map_err(error)? {
+                    Some(value) => condition::display(&value),
+                    None => format!("[{} not found]", text),
+                };
+                if self.shell && !expression.is_raw() {
+                    let context = shell_context(out, self.escape);
+                    let quoted = shell_quote(&value, &context).map_err(error)?;
+                    self.write(out, &quoted);
+                } else {
+                    self.write(out, &value);
+                }
+            }
+            NodeKind::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    if condition.is_true(scope).map_err(error)? {
+                        return self.nodes(body, scope, out);
+                    }
+                }
+                self.nodes(otherwise, scope, out)?;
+            }
+            NodeKind::For {
+                var,
+                items,
+                body,
+                otherwise,
+            } => {
+                let items = match items.evaluate(scope).map_err(error)? {
+                    Value::Array(items) => items,
+                    Value::Null => Vec::new(),
+                    Value::String(text) => serde_json::from_str(text.trim()).map_err(|_| {
+                        error(ConditionError::Eval(
+                            "can't loop over text that isn't a JSON array; use | lines to loop over its lines"
+                                .to_string(),
+                        ))
+                    })?,
+                    other => {
+                        return Err(error(ConditionError::Eval(format!(
+                            "can't loop over {}",
+                            other
+                        ))))
+                    }
+                };
+                if items.is_empty() {
+                    return self.nodes(otherwise, scope, out);
+                }
+                let length = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    let local = Local {
+                        outer: scope,
+                        var,
+                        item,
+                        loop_info: serde_json::json!({
+                            "index": i + 1,
+                            "index0": i,
+                            "first": i == 0,
+                            "last": i + 1 == length,
+                            "length": length,
+                        }),
+                    };
+                    self.nodes(body, &local, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a shell command left off, which decides how a value is quoted
+#[derive(Debug, Clone, PartialEq)]
+enum ShellContext {
+    Bare,
+    Single,
+    Double,
+    /// In a here-document; the body of a quoted one (`<<'EOF'`) isn't expanded
+    Heredoc {
+        delimiter: String,
+        quoted: bool,
+    },
+}
+
+/// Open quotes and command substitutions in a shell command
+#[derive(Debug, Clone, Copy)]
+enum Frame {
+    /// Outside quotes, with the number of plain parentheses open
+    Bare(usize),
+    Single,
+    Double,
+}
+
+/// A here-document started by `<<` or `<<-`
+#[derive(Debug)]
+struct Heredoc {
+    delimiter: String,
+    quoted: bool,
+    strip_tabs: bool,
+}
+
+/// The quoting at the end of `command`, following quotes, `$(...)`,
+/// comments and here-documents. With `skip_tags`, template tags written
+/// back by [`Template::render_partial`] are passed over.
+fn shell_context(command: &str, skip_tags: bool) -> ShellContext {
+    let mut frames = vec![Frame::Bare(0)];
+    // Here-documents start on the line after their `<<`
+    let mut pending: VecDeque<Heredoc> = VecDeque::new();
+    let mut heredoc: Option<Heredoc> = None;
+    let mut pos = 0;
+    while pos < command.len() {
+        let rest = &command[pos..];
+        if let Some(doc) = &heredoc {
+            let Some(end) = rest.find('\n') else {
+                break;
+            };
+            if heredoc_ends(doc, &rest[..end]) {
+                heredoc = pending.pop_front();
+            }
+            pos += end + 1;
+            continue;
+        }
+        if skip_tags && (rest.starts_with("{{") || rest.starts_with("{%")) {
+            let close = if rest.starts_with("{{") { "}}" } else { "%}" };
+            if let Some(end) = find_close(&rest[2..], close) {
+                pos += 2 + end + close.len();
+                continue;
+            }
+        }
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+        let word_start = command[..pos]
+            .chars()
+            .last()
+            .map_or(true, |prev| prev.is_whitespace() || ";&|(".contains(prev));
+        pos += c.len_utf8();
+        let top = *frames.last().expect("the outermost frame is never popped");
+        match (top, c) {
+            (Frame::Single, '\'') | (Frame::Double, '"') => {
+                frames.pop();
+            }
+            (Frame::Single, _) => {}
+            (_, '\\') => {
+                // The next character is escaped
+                pos += command[pos..].chars().next().map_or(0, char::len_utf8);
+            }
+            (_, '$') if command[pos..].starts_with('(') => {
+                pos += 1;
+                frames.push(Frame::Bare(0));
+            }
+            (Frame::Double, _) => {}
+            (Frame::Bare(_), '\'') => frames.push(Frame::Single),
+            (Frame::Bare(_), '"') => frames.push(Frame::Double),
+            (Frame::Bare(open), '(') => *frames.last_mut().unwrap() = Frame::Bare(open + 1),
+            (Frame::Bare(0), ')') if frames.len() > 1 => {
+                frames.pop();
+            }
+            (Frame::Bare(open), ')') => {
+                *frames.last_mut().unwrap() = Frame::Bare(open.saturating_sub(1))
+            }
+            (Frame::Bare(_), '#') if word_start => {
+                // A comment, up to the newline
+                pos += command[pos..].find('\n').unwrap_or(command.len() - pos);
+            }
+            (Frame::Bare(_), '<') if rest.starts_with("<<") && !rest.starts_with("<<<") => {
+                let (doc, end) = heredoc_start(command, pos + 1);
+                pending.extend(doc);
+                pos = end;
+            }
+            (Frame::Bare(_), '\n') => heredoc = pending.pop_front(),
+            (Frame::Bare(_), _) => {}
+        }
+    }
+    if let Some(doc) = heredoc {
+        return ShellContext::Heredoc {
+            delimiter: doc.delimiter,
+            quoted: doc.quoted,
+        };
+    }
+    match frames.last() {
+        Some(Frame::Single) => ShellContext::Single,
+        Some(Frame::Double) => ShellContext::Double,
+        _ => ShellContext::Bare,
+    }
+}
+
+/// The here-document whose `<<` ends at `pos`, and where its delimiter ends
+fn heredoc_start(command: &str, mut pos: usize) -> (Option<Heredoc>, usize) {
+    let strip_tabs = command[pos..].starts_with('-');
+    if strip_tabs {
+        pos += 1;
+    }
+    pos += command[pos..].len() - command[pos..].trim_start_matches([' ', '\t']).len();
+    let mut delimiter = String::new();
+    let mut quoted = false;
+    let mut chars = command[pos..].char_indices().peekable();
+    let mut end = command.len();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                quoted = true;
+                for (_, inner) in chars.by_ref() {
+                    if inner == c {
+                        break;
+                    }
+                    delimiter.push(inner);
+                }
+            }
+            '\\' => {
+                quoted = true;
+                delimiter.extend(chars.next().map(|(_, c)| c));
+            }
+            c if c.is_whitespace() || ";&|<>()".contains(c) => {
+                end = pos + i;
+                break;
+            }
+            c => delimiter.push(c),
+        }
+    }
+    let doc = (!delimiter.is_empty()).then_some(Heredoc {
+        delimiter,
+        quoted,
+        strip_tabs,
+    });
+    (doc, end)
+}
+
+fn heredoc_ends(doc: &Heredoc, line: &str) -> bool {
+    let line = if doc.strip_tabs {
+        line.trim_start_matches('\t')
+    } else {
+        line
+    };
+    line == doc.delimiter
+}
+
+/// `value` as literal text at a point in a shell command with `context`
+fn shell_quote(value: &str, context: &ShellContext) -> Result<String, ConditionError> {
+    let escape = |special: &[char]| {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    Ok(match context {
+        ShellContext::Bare => format!("'{}'", value.replace('\'', r"'\''")),
+        // Close the quotes for an escaped quote, then reopen them
+        ShellContext::Single => value.replace('\'', r"'\''"),
+        ShellContext::Double => escape(&['\\', '"', '$', '`']),
+        ShellContext::Heredoc { delimiter, quoted } => {
+            // Nothing escapes the delimiter, so a value can't hold it
+            if value
+                .lines()
+                .any(|line| line.trim_start_matches('\t') == delimiter)
+            {
+                return Err(ConditionError::Eval(format!(
+                    "the value has a line '{}', which would end the here-document",
+                    delimiter
+                )));
+            }
+            if *quoted {
+                value.to_string()
+            } else {
+                escape(&['\\', '$', '`'])
+            }
+        }
+    })
+}
+
+/// A loop variable over the scope around the loop
+struct Local<'a> {
+    outer: &'a dyn Scope,
+    var: &'a str,
+    item: Value,
+    loop_info: Value,
+}
+
+impl Scope for Local<'_> {
+    fn lookup(&self, path: &[Segment]) -> Result<Option<Value>, ConditionError> {
+        match path {
+            [Segment::Field(name), rest @ ..] if name == self.var => {
+                Ok(condition::navigate(self.item.clone(), rest))
+            }
+            [Segment::Field(name), rest @ ..] if name == "loop" => {
+                Ok(condition::navigate(self.loop_info.clone(), rest))
+            }
+            _ => self.outer.lookup(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `name`, `later` (deferred) and a few JSON values
+    struct TestScope;
+
+    impl Scope for TestScope {
+        fn lookup(&self, path: &[Segment]) -> Result<Option<Value>, ConditionError> {
+            let values = serde_json::json!({
+                "name": "lok",
+                "output": "Error: boom\nat main.rs:3\n",
+                "findings": [
+                    {"file": "a.rs", "line": 3},
+                    {"file": "b.rs", "message": "unused {{ braces }}"}
+                ],
+                "empty": [],
+                "quote": "it's \"$(echo hi)\" `x` \\ $HOME",
+            });
+            match path {
+                [Segment::Field(root), ..] if root == "later" => {
+                    Err(ConditionError::Deferred(condition::path_to_string(path)))
+                }
+                [Segment::Field(root), ..] if values.get(root).is_some() => {
+                    Ok(condition::navigate(values, path))
+                }
+                _ => Err(ConditionError::UnknownVariable(condition::path_to_string(
+                    path,
+                ))),
+            }
+        }
+    }
+
+    fn render(source: &str) -> String {
+        Template::parse(source)
+            .unwrap_or_else(|e| panic!("{}: {}", source, e))
+            .render(&TestScope)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_values_and_filters() {
+        assert_eq!(render("Hi {{ name }}!"), "Hi lok!");
+        assert_eq!(render("{{ output | lines | first }}"), "Error: boom");
+        assert_eq!(
+            render("{{ findings[0] | json }}"),
+            r#"{"file":"a.rs","line":3}"#
+        );
+        assert_eq!(
+            render("{{ findings[0].message }}"),
+            "[findings[0].message not found]"
+        );
+        assert_eq!(render(r#"{{ findings[0].message | default("-") }}"#), "-");
+        assert_eq!(render("{{ name | truncate(2) }}"), "lo...");
+        // Values aren't templates
+        assert_eq!(render("{{ findings[1].message }}"), "unused {{ braces }}");
+    }
+
+    #[test]
+    fn test_if_blocks() {
+        let template = r#"Result:
+{% if "Error" in output %}
+  failed: {{ output | lines | last }}
+{% elif name == "lok" %}
+  ok
+{% else %}
+  unknown
+{% endif %}
+done"#;
+        assert_eq!(render(template), "Result:\n  failed: at main.rs:3\ndone");
+        assert_eq!(
+            render("{% if empty %}some{% else %}none{% endif %}"),
+            "none"
+        );
+        assert_eq!(render("{% if not empty %}x{% endif %}"), "x");
+    }
+
+    #[test]
+    fn test_for_blocks() {
+        let template = "{% for f in findings %}\n{{ loop.index }}. {{ f.file }}\n{% endfor %}";
+        assert_eq!(render(template), "1. a.rs\n2. b.rs\n");
+        assert_eq!(
+            render("{% for f in findings %}{{ f.file }}{% if not loop.last %}, {% endif %}{% endfor %}"),
+            "a.rs, b.rs"
+        );
+        assert_eq!(
+            render("{% for line in output | lines %}[{{ line }}]{% endfor %}"),
+            "[Error: boom][at main.rs:3]"
+        );
+        assert_eq!(
+            render("{% for f in empty %}x{% else %}nothing{% endfor %}"),
+            "nothing"
+        );
+        assert!(Template::parse("{% for f in name %}{% endfor %}")
+            .unwrap()
+            .render(&TestScope)
+            .is_err());
+    }
+
+    #[test]
+    fn test_raw() {
+        assert_eq!(
+            render("{% raw %}{{ name }} {% if %}{% endraw %} {{ name }}"),
+            "{{ name }} {% if %} lok"
+        );
+    }
+
+    #[test]
+    fn test_partial_render() {
+        let template = Template::parse(
+            "{{ name }}: {{ later.x }}\n{% if later.y %}\n{{ findings[1].message }}\n{% endif %}\n{% raw %}{{ kept }}{% endraw %}",
+        )
+        .unwrap();
+        let partial = template.render_partial(&TestScope).unwrap();
+        assert_eq!(
+            unescape(&partial),
+            "lok: {{ later.x }}\n{% if later.y %}\n{{ findings[1].message }}\n{% endif %}\n{{ kept }}"
+        );
+        // A second pass renders only what was deferred
+        assert!(!partial.contains("{{ kept }}"));
+    }
+
+    #[test]
+    fn test_shell_quoting() {
+        let quote = "it's \"$(echo hi)\" `x` \\ $HOME";
+        let shell = |source: &str| {
+            let command = Template::parse(source)
+                .unwrap()
+                .for_shell()
+                .render(&TestScope)
+                .unwrap();
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", command);
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(shell("printf %s {{ quote }}"), quote);
+        assert_eq!(
+            shell("printf %s 'said: {{ quote }}'"),
+            format!("said: {}", quote)
+        );
+        assert_eq!(
+            shell("printf %s \"said: {{ quote }}\""),
+            format!("said: {}", quote)
+        );
+        assert_eq!(
+            shell("printf '%s;' {% for f in findings %}{{ f.file }}-\"{{ loop.index }}\" {% endfor %}"),
+            "a.rs-1;b.rs-2;"
+        );
+        assert_eq!(shell("printf '%s;' {{ 'a b' | raw }}"), "a;b;");
+        // Quotes inside tags written back by a partial render don't count
+        let template = Template::parse(
+            "echo \"{{ later.x }}\" {% if later.y == \"'\" %}{% endif %}{{ name }}",
+        )
+        .unwrap()
+        .for_shell();
+        assert_eq!(
+            template.render_partial(&TestScope).unwrap(),
+            "echo \"{{ later.x }}\" {% if later.y == \"'\" %}{% endif %}'lok'"
+        );
+    }
+
+    #[test]
+    fn test_shell_heredocs() {
+        let quote = "it's \"$(echo hi)\" `x` \\ $HOME";
+        let shell = |source: &str| {
+            let command = Template::parse(source)
+                .unwrap()
+                .for_shell()
+                .render(&TestScope)?;
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", command);
+            Ok::<_, TemplateError>(String::from_utf8(output.stdout).unwrap())
+        };
+        assert_eq!(
+            shell("cat <<'EOF'\n{{ quote }}\nEOF").unwrap(),
+            format!("{}\n", quote)
+        );
+        assert_eq!(
+            shell("cat <<-EOF\n\t{{ quote }}\n\tEOF\nprintf %s {{ name }}").unwrap(),
+            format!("{}\nlok", quote)
+        );
+        assert_eq!(
+            shell("printf %s \"$(cat <<'LOKEOF'\nsaid: {{ quote }}\nLOKEOF\n)\" # it's done")
+                .unwrap(),
+            format!("said: {}", quote)
+        );
+        assert_eq!(shell("# don't\nprintf %s {{ quote }}").unwrap(), quote);
+        let error = shell("cat <<EOF\n{{ 'EOF\\nrm -rf ~' }}\nEOF").unwrap_err();
+        assert!(error.to_string().contains("would end the here-document"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (bad, message) in [
+            ("{{ name", "line 1: '{{' is never closed"),
+            (
+                "a\n{% if name %}",
+                "line 2: {% if name %} without {% endif %}",
+            ),
+            ("{% endfor %}", "line 1: unexpected {% endfor %}"),
+            (
+                "{% for in findings %}{% endfor %}",
+                "line 1: expected {% for NAME in ... %}",
+            ),
+            ("{{ name | shout }}", "line 1: unknown filter 'shout'"),
+            ("{% while name %}", "line 1: unexpected {% while name %}"),
+        ] {
+            let error = Template::parse(bad).unwrap_err().to_string();
+            assert!(error.starts_with(message), "{:?}: {}", bad, error);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Token usage and cost accounting
+//!
+//! Backends report token counts where their API exposes them (Claude API,
+//! Ollama, Bedrock, Codex, Gemini, OpenAI-compatible servers). Costs are
+//! estimated from the `[prices]` config table, keyed by backend name or model:
+//!
+//! ```toml
+//! [prices.claude]
+//! input = 3.0     # USD per million input tokens
+//! output = 15.0   # USD per million output tokens
+//! ```
+
+use crate::backend::QueryResult;
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+/// Tokens consumed by one or more queries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+impl Usage {
+    pub fn new(input_tokens: u64, output_tokens: u64) -> Self {
+        Self {
+            input_tokens,
+            output_tokens,
+        }
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// Price in USD per million tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    #[serde(default)]
+    pub input: f64,
+    #[serde(default)]
+    pub output: f64,
+}
+
+impl Price {
+    /// Estimated cost in USD
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input + usage.output_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Look up the price for a backend: by backend name first, then by its configured model
+pub fn price_for(config: &Config, backend: &str) -> Option<Price> {
+    config
+        .prices
+        .get(backend)
+        .or_else(|| {
+            config
+                .backends
+                .get(backend)
+                .and_then(|b| b.model.as_ref())
+                .and_then(|model| config.prices.get(model))
+        })
+        .copied()
+}
+
+/// Token usage broken down by backend
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsageByBackend(BTreeMap<String, Usage>);
+
+impl UsageByBackend {
+    /// Sum usage across query results
+    pub fn from_results(results: &[QueryResult]) -> Self {
+        let mut tally = Self::default();
+        for result in results {
+            tally.record(result.answered_by(), result.usage);
+        }
+        tally
+    }
+
+    pub fn record(&mut self, backend: &str, usage: Option<Usage>) {
+        if let Some(usage) = usage {
+            *self.0.entry(backend.to_string()).or_default() += usage;
+        }
+    }
+
+    pub fn merge(&mut self, other: &UsageByBackend) {
+        for (backend, usage) in &other.0 {
+            self.record(backend, Some(*usage));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn total(&self) -> Usage {
+        let mut total = Usage::default();
+        for usage in self.0.values() {
+            total += *usage;
+        }
+        total
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Usage)> {
+        self.0
+            .iter()
+            .map(|(backend, usage)| (backend.as_str(), usage))
+    }
+}
+
+/// Format token counts, e.g. "1,234 in / 56 out"
+pub fn format_tokens(usage: &Usage) -> String {
+    format!(
+        "{} in / {} out",
+        group_digits(usage.input_tokens),
+        group_digits(usage.output_tokens)
+    )
+}
+
+/// Format a dollar amount with enough precision for small local runs
+pub fn format_cost(usd: f64) -> String {
+    if usd >= 1.0 {
+        format!("${:.2}", usd)
+    } else {
+        format!("${:.4}", usd)
+    }
+}
+
+/// Estimated cost for one backend's usage, or None if no price is configured
+pub fn estimate_cost(config: &Config, backend: &str, usage: &Usage) -> Option<f64> {
+    price_for(config, backend).map(|price| price.cost(usage))
+}
+
+/// One line per backend plus a total, e.g. "claude  1,234 in / 56 out  $0.0045"
+pub fn summary_lines(usage: &UsageByBackend, config: &Config) -> Vec<String> {
+    let width = usage
+        .iter()
+        .map(|(backend, _)| backend.len())
+        .chain(std::iter::once("total".len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = Vec::new();
+    let mut total_cost = 0.0;
+    let mut unpriced = false;
+
+    for (backend, u) in usage.iter() {
+        let cost = match estimate_cost(config, backend, u) {
+            Some(cost) => {
+                total_cost += cost;
+                format_cost(cost)
+            }
+            None => {
+                unpriced = true;
+                "no price".to_string()
+            }
+        };
+        lines.push(format!(
+            "{:width$}  {}  {}",
+            backend,
+            format_tokens(u),
+            cost,
+            width = width
+        ));
+    }
+
+    let mut total = format!(
+        "{:width$}  {}  {}",
+        "total",
+        format_tokens(&usage.total()),
+        format_cost(total_cost),
+        width = width
+    );
+    if unpriced {
+        total.push_str(" (excluding unpriced backends)");
+    }
+    lines.push(total);
+
+    lines
+}
+
+fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendConfig;
+
+    fn priced_config() -> Config {
+        let mut config = Config::default();
+        config.prices.insert(
+            "claude".to_string(),
+            Price {
+                input: 3.0,
+                output: 15.0,
+            },
+        );
+        config.prices.insert(
+            "qwen2.5-coder:32b".to_string(),
+            Price {
+                input: 0.0,
+                output: 0.0,
+            },
+        );
+        config.backends.insert(
+            "ollama-big".to_string(),
+            BackendConfig {
+                kind: Some("ollama".to_string()),
+                model: Some("qwen2.5-coder:32b".to_string()),
+                ..Default::default()
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn test_price_cost() {
+        let price = Price {
+            input: 3.0,
+            output: 15.0,
+        };
+        let cost = price.cost(&Usage::new(1_000_000, 100_000));
+        assert!((cost - 4.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_price_lookup_by_name_then_model() {
+        let config = priced_config();
+        assert_eq!(price_for(&config, "claude").unwrap().input, 3.0);
+        assert_eq!(price_for(&config, "ollama-big").unwrap().output, 0.0);
+        assert!(price_for(&config, "gemini").is_none());
+    }
+
+    #[test]
+    fn test_usage_by_backend_accumulates() {
+        let mut tally = UsageByBackend::default();
+        tally.record("claude", Some(Usage::new(10, 5)));
+        tally.record("claude", Some(Usage::new(1, 1)));
+        tally.record("gemini", None);
+        let mut other = UsageByBackend::default();
+        other.record("codex", Some(Usage::new(100, 50)));
+        tally.merge(&other);
+
+        let entries: Vec<_> = tally.iter().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], ("claude", &Usage::new(11, 6)));
+        assert_eq!(tally.total(), Usage::new(111, 56));
+    }
+
+    #[test]
+    fn test_summary_lines() {
+        let config = priced_config();
+        let mut tally = UsageByBackend::default();
+        tally.record("claude", Some(Usage::new(1_000_000, 0)));
+        tally.record("gemini", Some(Usage::new(1_234, 56)));
+
+        let lines = summary_lines(&tally, &config);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("1,000,000 in / 0 out"), "{}", lines[0]);
+        assert!(lines[0].ends_with("$3.00"), "{}", lines[0]);
+        assert!(lines[1].ends_with("no price"), "{}", lines[1]);
+        assert!(lines[2].contains("$3.00 (excluding unpriced backends)"));
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1234), "1,234");
+        assert_eq!(group_digits(1234567), "1,234,567");
+    }
+}
@@ -4,7 +4,7 @@ use colored::Colorize;
 use std::path::{Path, PathBuf};
 
 /// Classification of backend errors for user-friendly messaging
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BackendErrorKind {
     /// Rate limited (429, quota exceeded)
     RateLimited,
@@ -14,6 +14,8 @@ pub enum BackendErrorKind {
     AuthError,
     /// Network error (connection refused, timeout)
     NetworkError,
+    /// No answer within the time limit
+    Timeout,
     /// Command not found / not installed
     NotInstalled,
     /// Unknown error (show full message)
@@ -28,11 +30,17 @@ impl BackendErrorKind {
             BackendErrorKind::CapacityExhausted => "no capacity available",
             BackendErrorKind::AuthError => "authentication failed",
             BackendErrorKind::NetworkError => "network error",
+            BackendErrorKind::Timeout => "timed out",
             BackendErrorKind::NotInstalled => "not installed",
             BackendErrorKind::Unknown => "failed",
         }
     }
 
+    /// Whether another backend could plausibly succeed where this one failed
+    pub fn should_fall_back(&self) -> bool {
+        !matches!(self, BackendErrorKind::Unknown)
+    }
+
     /// Returns a hint for the user
     pub fn hint(&self) -> Option<&'static str> {
         match self {
@@ -40,6 +48,7 @@ impl BackendErrorKind {
             BackendErrorKind::CapacityExhausted => Some("try again later"),
             BackendErrorKind::AuthError => Some("check your API credentials"),
             BackendErrorKind::NetworkError => Some("check your internet connection"),
+            BackendErrorKind::Timeout => Some("raise the timeout or try a faster backend"),
             BackendErrorKind::NotInstalled => Some("install the backend CLI tool"),
             BackendErrorKind::Unknown => None,
         }
@@ -80,6 +89,11 @@ pub fn classify_backend_error(error: &str) -> BackendErrorKind {
         return BackendErrorKind::AuthError;
     }
 
+    // Timeout patterns, before network ones so "connection timeout" is a timeout
+    if error_lower.contains("timed out") || error_lower.contains("timeout") {
+        return BackendErrorKind::Timeout;
+    }
+
     // Network error patterns
     if error_lower.contains("econnrefused")
         || error_lower.contains("etimedout")
@@ -281,6 +295,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_classify_timeout() {
+        assert_eq!(
+            classify_backend_error("Error: Timeout (300s)"),
+            BackendErrorKind::Timeout
+        );
+    }
+
+    #[test]
+    fn test_classify_connection_timeout() {
+        assert_eq!(
+            classify_backend_error("network error: connection timeout"),
+            BackendErrorKind::Timeout
+        );
+        assert_eq!(
+            classify_backend_error("connect ETIMEDOUT 10.0.0.1:443"),
+            BackendErrorKind::NetworkError
+        );
+    }
+
     #[test]
     fn test_classify_not_installed() {
         assert_eq!(
@@ -9,10 +9,15 @@
 //! - `apply_edits` parses JSON edits from LLM output and applies them
 //! - `verify` runs a shell command after edits to validate them
 
-use crate::backend;
+use crate::backend::budget::{self, Budget, ContextOverflow};
+use crate::backend::permissions;
+use crate::backend::{self, Permissions, QueryRequest, ResponseFormat};
+use crate::condition::{self, Condition, ConditionError, Scope, Segment};
 use crate::config::Config;
 use crate::context::{resolve_format_command, resolve_verify_command, CodebaseContext};
 use crate::git_agent;
+use crate::template::{Template, TemplateError};
+use crate::usage::{self, UsageByBackend};
 use crate::utils::summarize_backend_error;
 use anyhow::{Context, Result};
 use colored::Colorize;
@@ -40,7 +45,7 @@ pub enum WorkflowError {
         referenced: String,
     },
 
-    #[error("Workflow '{workflow}': step '{step}' has unknown variable '{{{{ {variable} }}}}'\n  hint: valid forms are steps.X.output, steps.X.field, env.VAR, arg.N, workflow.backends")]
+    #[error("Workflow '{workflow}': step '{step}' has unknown variable '{{{{ {variable} }}}}'\n  hint: valid forms are steps.X.output, steps.X.field, env.VAR, arg.N, workflow.backends, and item and index in for_each steps")]
     UnknownVariable {
         workflow: String,
         step: String,
@@ -53,6 +58,21 @@ pub enum WorkflowError {
         duplicates: Vec<String>,
     },
 
+    #[error("Workflow '{workflow}': step '{step}' has an invalid condition '{condition}': {error}\n  hint: e.g. steps.X.success && contains(steps.X.output, \"text\")")]
+    InvalidCondition {
+        workflow: String,
+        step: String,
+        condition: String,
+        error: String,
+    },
+
+    #[error("Workflow '{workflow}': step '{step}' has an invalid template: {error}\n  hint: values go in {{{{ ... }}}}, blocks in {{% if %}}/{{% for %}}; wrap literal braces in {{% raw %}}...{{% endraw %}}")]
+    InvalidTemplate {
+        workflow: String,
+        step: String,
+        error: String,
+    },
+
     #[error("Workflow '{workflow}': step '{step}' has min_deps_success but no dependencies\n  hint: min_deps_success requires depends_on to be non-empty")]
     MinDepsSuccessWithoutDeps { workflow: String, step: String },
 
@@ -71,98 +91,48 @@ pub enum WorkflowError {
         timeout: u64,
         min: u64,
     },
+
+    #[error("Workflow '{workflow}': step '{step}' prompt is ~{tokens} tokens but {backend} has room for {available}\n  hint: shorten the prompt or attachments, raise context_window, or set context_overflow = \"truncate\", \"drop_oldest\" or \"chunk\"")]
+    PromptTooLarge {
+        workflow: String,
+        step: String,
+        backend: String,
+        tokens: u64,
+        available: u64,
+    },
+
+    #[error("Workflow '{workflow}': step '{step}' has context_overflow = \"chunk\" but queries several backends or loops\n  hint: chunking needs a single backend and no for_each")]
+    ChunkNeedsSingleQuery { workflow: String, step: String },
+
+    #[error("Workflow '{workflow}': steps '{first}' and '{second}' would both be passed to shell commands as ${var}\n  hint: rename one so they differ in more than punctuation or case")]
+    StepEnvVarCollision {
+        workflow: String,
+        first: String,
+        second: String,
+        var: String,
+    },
+
+    #[error("Workflow '{workflow}': step '{step}' applies edits, but {backend} would run with {permissions} permissions and the workflow allows {allowed}\n  hint: lower permissions on the step or backend, or set permissions = \"{permissions}\" at the top of the workflow")]
+    PermissionsExceeded {
+        workflow: String,
+        step: String,
+        backend: String,
+        permissions: Permissions,
+        allowed: Permissions,
+    },
 }
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
 use tokio::process::Command;
 
-/// Regex for matching {{ steps.NAME.output }} patterns
-static INTERPOLATE_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*steps\.([a-zA-Z0-9_-]+)\.output\s*\}\}").unwrap());
-
-/// Regex for matching "steps.X.output contains 'Y'" conditions (legacy syntax)
-static CONDITION_LEGACY_RE: LazyLock<regex::Regex> = LazyLock::new(|| {
-    regex::Regex::new(r#"steps\.([a-zA-Z0-9_-]+)\.output\s+contains\s+['"](.+)['"]"#).unwrap()
-});
-
-/// Regex for matching contains(step.field, "string") conditions
-/// Captures: (1) step name, (2) field name, (3) search string
-static CONDITION_CONTAINS_RE: LazyLock<regex::Regex> = LazyLock::new(|| {
-    regex::Regex::new(r#"contains\(\s*([a-zA-Z0-9_-]+)\.([a-zA-Z0-9_]+)\s*,\s*['"](.+)['"]\s*\)"#)
-        .unwrap()
-});
-
-/// Regex for matching equals(step.field, "string") conditions
-/// Captures: (1) step name, (2) field name, (3) expected value
-static CONDITION_EQUALS_RE: LazyLock<regex::Regex> = LazyLock::new(|| {
-    regex::Regex::new(r#"equals\(\s*([a-zA-Z0-9_-]+)\.([a-zA-Z0-9_]+)\s*,\s*['"](.+)['"]\s*\)"#)
-        .unwrap()
-});
-
-/// Regex for matching not(...) conditions
-static CONDITION_NOT_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r#"not\(\s*(.+)\s*\)"#).unwrap());
-
-/// Regex for matching {{ steps.NAME.field }} patterns (for JSON field access)
-static FIELD_RE: LazyLock<regex::Regex> = LazyLock::new(|| {
-    regex::Regex::new(r"\{\{\s*steps\.([a-zA-Z0-9_-]+)\.([a-zA-Z0-9_]+)\s*\}\}").unwrap()
-});
-
-/// Regex for matching {{ env.VAR }} patterns (environment variables)
-static ENV_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*env\.([a-zA-Z0-9_]+)\s*\}\}").unwrap());
-
-/// Regex for matching {{ arg.N }} patterns (positional arguments, 1-indexed)
-static ARG_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*arg\.(\d+)\s*\}\}").unwrap());
-
-/// Regex for matching {{ workflow.backends }} pattern
-static WORKFLOW_BACKENDS_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*workflow\.backends\s*\}\}").unwrap());
-
 /// Default timeout for workflow steps in milliseconds (2 minutes)
 const DEFAULT_STEP_TIMEOUT_MS: u64 = 120_000;
 
 /// Minimum timeout value in milliseconds (values 1 to MIN-1 are rejected)
 const MIN_TIMEOUT_MS: u64 = 100;
 
-/// Regex for detecting unknown {{ ... }} variables after all substitutions
-static UNKNOWN_VAR_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*([^}]+)\s*\}\}").unwrap());
-
-/// Regex for matching {{ item }} pattern (loop iteration item)
-static ITEM_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*item\s*\}\}").unwrap());
-
-/// Regex for matching {{ item.field }} pattern (loop item field access)
-static ITEM_FIELD_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*item\.([a-zA-Z0-9_]+)\s*\}\}").unwrap());
-
-/// Regex for matching {{ index }} pattern (loop iteration index)
-static INDEX_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"\{\{\s*index\s*\}\}").unwrap());
-
-/// Regex for matching steps.X.success condition (checks if step succeeded)
-/// Captures: (1) step name
-static CONDITION_SUCCESS_RE: LazyLock<regex::Regex> =
-    LazyLock::new(|| regex::Regex::new(r"^steps\.([a-zA-Z0-9_-]+)\.success$").unwrap());
-
-/// Placeholder for escaped braces - uses a pattern unlikely to appear in real content
-const ESCAPED_OPEN_BRACE: &str = "\x00LOK_OPEN_BRACE\x00";
-
-/// Escape {{ in content so it won't be treated as a variable reference
-fn escape_braces(s: &str) -> String {
-    s.replace("{{", ESCAPED_OPEN_BRACE)
-}
-
-/// Restore escaped braces after interpolation is complete
-fn unescape_braces(s: &str) -> String {
-    s.replace(ESCAPED_OPEN_BRACE, "{{")
-}
-
 /// A file edit to apply
 #[derive(Debug, Deserialize, Clone)]
 pub struct FileEdit {
@@ -173,7 +143,7 @@ pub struct FileEdit {
 
 /// Structured output from an LLM step with edits
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)] // Fields used for JSON schema, extracted via extract_json_field()
+#[allow(dead_code)] // Fields used for JSON schema, read by name through step_json()
 pub struct AgenticOutput {
     #[serde(default)]
     pub edits: Vec<FileEdit>,
@@ -200,12 +170,33 @@ pub struct Workflow {
     /// Default timeout for all steps in milliseconds (steps can override)
     #[serde(default)]
     pub timeout: Option<u64>,
+    /// Most an agent may be allowed to do in `apply_edits` steps (default read-only,
+    /// since lok applies the edits itself)
+    #[serde(default)]
+    pub permissions: Option<Permissions>,
 }
 
 impl Workflow {
     /// Validate workflow configuration at load time
     pub fn validate(&self) -> Result<(), WorkflowError> {
+        let mut env_vars: HashMap<String, &str> = HashMap::new();
         for step in &self.steps {
+            let var = step_env_var(&step.name);
+            if let Some(first) = env_vars.get(&var) {
+                if *first != step.name {
+                    return Err(WorkflowError::StepEnvVarCollision {
+                        workflow: self.name.clone(),
+                        first: first.to_string(),
+                        second: step.name.clone(),
+                        var,
+                    });
+                }
+            }
+            env_vars.insert(var, &step.name);
+            if let Some(condition) = &step.when {
+                self.validate_condition(step, condition)?;
+            }
+            self.validate_templates(step)?;
             if let Some(min) = step.min_deps_success {
                 let deps_count = step.depends_on.len();
                 if min > deps_count {
@@ -229,6 +220,76 @@ impl Workflow {
                     });
                 }
             }
+            if step.context_overflow == ContextOverflow::Chunk
+                && (step.get_backends().len() > 1 || step.for_each.is_some())
+            {
+                return Err(WorkflowError::ChunkNeedsSingleQuery {
+                    workflow: self.name.clone(),
+                    step: step.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// A `when` condition must parse and name only steps in the workflow
+    fn validate_condition(&self, step: &Step, condition: &str) -> Result<(), WorkflowError> {
+        let invalid = |error: String| WorkflowError::InvalidCondition {
+            workflow: self.name.clone(),
+            step: step.name.clone(),
+            condition: condition.to_string(),
+            error,
+        };
+        let parsed = Condition::parse(condition).map_err(|e| invalid(e.to_string()))?;
+        for name in parsed.steps() {
+            if !self.steps.iter().any(|s| s.name == name) {
+                return Err(invalid(format!("unknown step '{}'", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// A step's prompt, system prompt, shell and verify commands and
+    /// attachments must parse as templates
+    fn validate_templates(&self, step: &Step) -> Result<(), WorkflowError> {
+        let fields = [
+            ("prompt", Some(&step.prompt)),
+            ("system", step.system.as_ref()),
+            ("shell", step.shell.as_ref()),
+            ("verify", step.verify.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(field, template)| Some((field, template?)))
+        .chain(step.attach.iter().map(|a| ("attach", a)));
+        for (field, template) in fields {
+            if let Err(e) = Template::parse(template) {
+                return Err(WorkflowError::InvalidTemplate {
+                    workflow: self.name.clone(),
+                    step: step.name.clone(),
+                    error: format!("{} {}", field, e),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse agents in `apply_edits` steps that may do more than the workflow
+    /// allows under `config`. Checked before any step runs and by `lok workflow validate`.
+    pub fn check_permissions(&self, config: &Config) -> Result<(), WorkflowError> {
+        let allowed = self.permissions.unwrap_or_default();
+        for step in self.steps.iter().filter(|s| s.apply_edits) {
+            for backend in step.get_backends() {
+                let permissions = permissions::for_backend(&backend, config, step.permissions);
+                if permissions > allowed {
+                    return Err(WorkflowError::PermissionsExceeded {
+                        workflow: self.name.clone(),
+                        step: step.name.clone(),
+                        backend,
+                        permissions,
+                        allowed,
+                    });
+                }
+            }
         }
         Ok(())
     }
@@ -325,6 +386,50 @@ pub struct Step {
     /// - "weighted_vote": Weighted majority by backend tier
     #[serde(default)]
     pub consensus: Option<crate::consensus::ConsensusStrategy>,
+
+    // Live output
+    /// Print backend output live as it arrives instead of only when the step finishes
+    #[serde(default)]
+    pub stream: bool,
+    /// Prefix each streamed line with the backend name (implies `stream`)
+    /// Useful for multi-backend steps and steps running in parallel
+    #[serde(default)]
+    pub stream_prefix: bool,
+
+    // Generation options, passed to backends that support them
+    /// System prompt (supports {{ }} interpolation)
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Sampling temperature
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Maximum output tokens
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Stop sequences
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// Ask for "text" (default) or "json" output; uses the backend's JSON mode where available
+    #[serde(default)]
+    pub response_format: ResponseFormat,
+
+    // Context budget
+    /// What to do when the prompt doesn't fit the backend's `context_window`:
+    /// "error" (default), "truncate", "drop_oldest" or "chunk"
+    #[serde(default)]
+    pub context_overflow: ContextOverflow,
+
+    // Permissions
+    /// What agentic CLIs may do for this step, instead of their backend's
+    /// `permissions`: "read-only", "workspace-write" or "full"
+    #[serde(default)]
+    pub permissions: Option<Permissions>,
+
+    // Attachments
+    /// Files, images or globs (e.g. "src/**/*.rs") sent with the prompt,
+    /// relative to the working directory (supports {{ }} interpolation)
+    #[serde(default)]
+    pub attach: Vec<String>,
 }
 
 impl Step {
@@ -349,12 +454,65 @@ impl Step {
     pub fn get_consensus_strategy(&self) -> crate::consensus::ConsensusStrategy {
         self.consensus.clone().unwrap_or_default()
     }
+
+    /// Request options for this step; the prompt is filled in per query
+    pub fn query_request(&self, system: Option<String>) -> QueryRequest {
+        QueryRequest {
+            system,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            stop: self.stop.clone(),
+            response_format: self.response_format,
+            permissions: self.permissions,
+            ..Default::default()
+        }
+    }
+
+    /// `Some(prefix)` when backend output should be streamed live
+    pub fn stream_mode(&self) -> Option<bool> {
+        (self.stream || self.stream_prefix).then_some(self.stream_prefix)
+    }
 }
 
 fn default_retry_delay() -> u64 {
     1000
 }
 
+/// Query a backend, printing its output live when the step streams
+async fn query_backend(
+    backend: &dyn backend::Backend,
+    request: &QueryRequest,
+    cwd: &Path,
+    stream: Option<bool>,
+) -> Result<backend::Completion> {
+    match stream {
+        Some(prefix) => backend::query_live(backend, request, cwd, prefix).await,
+        None => backend.complete(request, cwd).await,
+    }
+}
+
+/// Send each part of a chunked prompt in turn and join the answers
+async fn query_chunks(
+    backend: &dyn backend::Backend,
+    request: &QueryRequest,
+    chunks: &[String],
+    cwd: &Path,
+    stream: Option<bool>,
+) -> Result<backend::Completion> {
+    let mut joined = backend::Completion::default();
+    let mut answers = Vec::new();
+    for chunk in chunks {
+        let completion = query_backend(backend, &request.with_prompt(chunk), cwd, stream).await?;
+        if let Some(usage) = completion.usage {
+            *joined.usage.get_or_insert_with(Default::default) += usage;
+        }
+        joined.fallback = joined.fallback.or(completion.fallback);
+        answers.push(completion.text);
+    }
+    joined.text = answers.join("\n\n");
+    Ok(joined)
+}
+
 /// Parse step output based on format
 fn parse_step_output(output: &str, format: Option<&str>) -> Option<serde_json::Value> {
     match format {
@@ -406,17 +564,38 @@ pub struct StepResult {
     pub success: bool,
     pub elapsed_ms: u64,
     pub backend: Option<String>,
+    /// Tokens used by each backend queried for this step
+    pub usage: UsageByBackend,
+    /// Set when a `fallback` backend answered instead of `backend`
+    pub fallback: Option<backend::Fallback>,
+    /// What an agentic backend did, for `{{ steps.NAME.trace }}`
+    pub trace: Option<backend::Trace>,
+}
+
+/// A step prompt fitted to its backends' context window
+struct FittedPrompt {
+    prompt: String,
+    /// Parts to send instead of `prompt` when it was chunked
+    chunks: Vec<String>,
+    /// Line to print under the step header: always when the prompt was
+    /// changed, in verbose mode otherwise
+    report: Option<String>,
 }
 
 /// Prepared step ready for execution
 struct PreparedStep<'a> {
     step: &'a Step,
     prompt: String,
+    chunks: Vec<String>,
+    budget_report: Option<String>,
+    system: Option<String>,
     shell: Option<String>,
     format: Option<String>,
     verify: Option<String>,
     for_each_items: Option<Vec<serde_json::Value>>,
     output_format: Option<String>,
+    attachments: Vec<backend::Attachment>,
+    step_files: Option<StepFiles>,
 }
 
 /// Workflow executor
@@ -425,19 +604,32 @@ pub struct WorkflowRunner {
     cwd: PathBuf,
     args: Vec<String>,
     context: CodebaseContext,
+    /// Shared by every step, so a backend that keeps failing is skipped
+    breaker: backend::CircuitBreaker,
+    /// Report each step's context budget
+    verbose: bool,
 }
 
 impl WorkflowRunner {
     pub fn new(config: Config, cwd: PathBuf, args: Vec<String>) -> Self {
         let context = CodebaseContext::detect(&cwd);
+        let breaker = backend::CircuitBreaker::new(&config.circuit_breaker);
         Self {
             config,
             cwd,
             args,
             context,
+            breaker,
+            verbose: false,
         }
     }
 
+    /// Report each step's context budget, not only the prompts it had to change
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
     /// Execute a workflow, returning results for each step
     /// Steps at the same depth level (no dependencies between them) run in parallel
     pub async fn run(&self, workflow: &Workflow) -> Result<Vec<StepResult>> {
@@ -446,6 +638,7 @@ impl WorkflowRunner {
 
         // Group steps by depth level for parallel execution
         let depth_levels = self.group_by_depth(&workflow.steps, &workflow.name)?;
+        workflow.check_permissions(&self.config)?;
 
         println!("{} {}", "Running workflow:".bold(), workflow.name.cyan());
         if let Some(ref desc) = workflow.description {
@@ -482,7 +675,10 @@ impl WorkflowRunner {
 
                 // Check condition if present
                 if let Some(ref condition) = step.when {
-                    if !self.evaluate_condition(condition, &results) {
+                    let met = self
+                        .evaluate_condition(condition, &results)
+                        .with_context(|| format!("Step '{}'", step.name))?;
+                    if !met {
                         println!(
                             "{} {} (condition not met)",
                             "[skip]".yellow(),
@@ -577,6 +773,9 @@ impl WorkflowRunner {
                                 success: false,
                                 elapsed_ms: 0,
                                 backend: None,
+                                usage: UsageByBackend::default(),
+                                fallback: None,
+                                trace: None,
                             };
                             results.insert(step.name.clone(), skip_result.clone());
                             ordered_results.push(skip_result);
@@ -619,6 +818,9 @@ impl WorkflowRunner {
                             success: false,
                             elapsed_ms: 0,
                             backend: None,
+                            usage: UsageByBackend::default(),
+                            fallback: None,
+                            trace: None,
                         };
                         results.insert(step.name.clone(), skip_result.clone());
                         ordered_results.push(skip_result);
@@ -634,22 +836,60 @@ impl WorkflowRunner {
                 }
 
                 // Interpolate variables in prompt/shell (uses results from previous depths)
-                let prompt = self.interpolate_with_fields(
-                    &step.prompt,
-                    &results,
-                    &workflow.name,
-                    &step.name,
-                )?;
+                let prompt =
+                    self.interpolate_for_step(step, &step.prompt, &results, &workflow.name, false)?;
+                let system = step
+                    .system
+                    .as_ref()
+                    .map(|s| self.interpolate_with_fields(s, &results, &workflow.name, &step.name))
+                    .transpose()?;
+                // Read attached files now, so a missing one fails before any query,
+                // and counts against the context budget
+                let attach = step
+                    .attach
+                    .iter()
+                    .map(|a| self.interpolate_with_fields(a, &results, &workflow.name, &step.name))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let attachments = backend::attachment::load_all(&attach, &self.cwd)
+                    .map_err(|e| anyhow::anyhow!("Step '{}': {:#}", step.name, e))?;
+
+                let FittedPrompt {
+                    prompt,
+                    chunks,
+                    report: budget_report,
+                } = if step.shell.is_none() {
+                    // The system prompt and text attachments go along whole
+                    let fixed_tokens = system.as_deref().map_or(0, budget::estimate_tokens)
+                        + attachments
+                            .iter()
+                            .filter(|a| !a.is_image())
+                            .map(|a| budget::estimate_tokens(&a.inline()))
+                            .sum::<u64>();
+                    self.fit_prompt(
+                        step,
+                        prompt,
+                        fixed_tokens,
+                        &results,
+                        &ordered_results,
+                        &workflow.name,
+                    )?
+                } else {
+                    FittedPrompt {
+                        prompt,
+                        chunks: Vec::new(),
+                        report: None,
+                    }
+                };
                 let shell = step
                     .shell
                     .as_ref()
-                    .map(|s| self.interpolate_with_fields(s, &results, &workflow.name, &step.name))
+                    .map(|s| self.interpolate_for_step(step, s, &results, &workflow.name, true))
                     .transpose()?;
                 // When verify is set, also resolve format command to run first
                 let verify_value = step
                     .verify
                     .as_ref()
-                    .map(|v| self.interpolate_with_fields(v, &results, &workflow.name, &step.name))
+                    .map(|v| self.interpolate_command(v, &results, &workflow.name, &step.name))
                     .transpose()?;
                 let format = verify_value
                     .as_ref()
@@ -664,14 +904,28 @@ impl WorkflowRunner {
                     .transpose()
                     .map_err(|e| anyhow::anyhow!("Step '{}': {}", step.name, e))?;
 
+                // Earlier outputs for the step's commands, as $LOK_STEP_<NAME>
+                let step_files = if shell.is_some() || verify.is_some() {
+                    let files = StepFiles::write(&results)
+                        .map_err(|e| anyhow::anyhow!("Step '{}': {:#}", step.name, e))?;
+                    Some(files)
+                } else {
+                    None
+                };
+
                 steps_to_run.push(PreparedStep {
                     step,
                     prompt,
+                    chunks,
+                    budget_report,
+                    system,
                     shell,
                     format,
                     verify,
                     for_each_items,
                     output_format: step.output_format.clone(),
+                    attachments,
+                    step_files,
                 });
             }
 
@@ -680,19 +934,26 @@ impl WorkflowRunner {
             }
 
             // Execute steps at this depth in parallel
+            let earlier = &results;
             let futures: Vec<_> = steps_to_run
                 .into_iter()
                 .map(|prepared| {
                     let PreparedStep {
                         step,
                         prompt,
+                        chunks,
+                        budget_report,
+                        system,
                         shell,
                         format,
                         verify,
                         for_each_items,
                         output_format,
+                        attachments,
+                        step_files,
                     } = prepared;
                     let config = self.config.clone();
+                    let breaker = self.breaker.clone();
                     let cwd = self.cwd.clone();
                     let step_name = step.name.clone();
                     let backend_name = step.backend.clone();
@@ -703,9 +964,18 @@ impl WorkflowRunner {
                     let max_retries = step.retries;
                     let retry_delay = step.retry_delay;
                     let step_timeout = workflow.step_timeout(step);
+                    let stream = step.stream_mode();
+                    let request = QueryRequest {
+                        attachments,
+                        ..step.query_request(system)
+                    };
 
                     async move {
+                        let shell_env = step_files.as_ref().map(StepFiles::env).unwrap_or_default();
                         println!("{} {}", "[step]".cyan(), step_name.bold());
+                        if let Some(ref report) = budget_report {
+                            println!("  {}", report);
+                        }
                         let start = std::time::Instant::now();
 
                         // Calculate timeout duration (default 120s, 0 means no timeout)
@@ -725,12 +995,36 @@ impl WorkflowRunner {
                             );
 
                             let mut iteration_results: Vec<serde_json::Value> = Vec::new();
+                            let mut loop_usage = UsageByBackend::default();
+                            // First fallback hit by any iteration
+                            let mut loop_fallback = None;
+                            let mut loop_trace = None;
                             let mut all_success = true;
 
                             for (index, item) in items.iter().enumerate() {
                                 // Interpolate item/index into prompt and shell
-                                let iter_prompt = interpolate_loop_vars(&prompt, item, index);
-                                let iter_shell = shell.as_ref().map(|s| interpolate_loop_vars(s, item, index));
+                                let rendered = self
+                                    .interpolate_loop_vars(&prompt, earlier, LoopVars::Item(item, index), &workflow.name, &step_name, false)
+                                    .and_then(|p| {
+                                        let s = shell
+                                            .as_ref()
+                                            .map(|s| self.interpolate_loop_vars(s, earlier, LoopVars::Item(item, index), &workflow.name, &step_name, true))
+                                            .transpose()?;
+                                        Ok((p, s))
+                                    });
+                                let (iter_prompt, iter_shell) = match rendered {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        all_success = false;
+                                        iteration_results.push(serde_json::json!({
+                                            "index": index,
+                                            "item": item,
+                                            "output": format!("Error: {}", e),
+                                            "success": false
+                                        }));
+                                        continue;
+                                    }
+                                };
 
                                 println!(
                                     "    {} [{}/{}]",
@@ -744,7 +1038,7 @@ impl WorkflowRunner {
 
                                 // Shell iteration
                                 if let Some(ref shell_cmd) = iter_shell {
-                                    match tokio::time::timeout(timeout_duration, run_shell(shell_cmd, &cwd, self.config.defaults.command_wrapper.as_deref())).await {
+                                    match tokio::time::timeout(timeout_duration, run_shell(shell_cmd, &cwd, self.config.defaults.command_wrapper.as_deref(), shell_env)).await {
                                         Ok(Ok(output)) => {
                                             iter_output = output;
                                             iter_success = true;
@@ -762,23 +1056,20 @@ impl WorkflowRunner {
                                     }
                                 } else {
                                     // LLM iteration
-                                    let backend_config = match config.backends.get(&backend_name) {
-                                        Some(cfg) => cfg,
-                                        None => {
-                                            iter_output = format!("Backend not found: {}", backend_name);
-                                            iter_success = false;
-                                            all_success = false;
-                                            iteration_results.push(serde_json::json!({
-                                                "index": index,
-                                                "item": item,
-                                                "output": iter_output,
-                                                "success": iter_success
-                                            }));
-                                            continue;
-                                        }
-                                    };
+                                    if !config.backends.contains_key(&backend_name) {
+                                        iter_output = format!("Backend not found: {}", backend_name);
+                                        iter_success = false;
+                                        all_success = false;
+                                        iteration_results.push(serde_json::json!({
+                                            "index": index,
+                                            "item": item,
+                                            "output": iter_output,
+                                            "success": iter_success
+                                        }));
+                                        continue;
+                                    }
 
-                                    let backend = match backend::create_backend(&backend_name, backend_config) {
+                                    let backend = match backend::build_backend(&backend_name, &config, Some(&breaker)) {
                                         Ok(b) => b,
                                         Err(e) => {
                                             iter_output = format!("Failed to create backend: {}", e);
@@ -794,9 +1085,15 @@ impl WorkflowRunner {
                                         }
                                     };
 
-                                    match tokio::time::timeout(timeout_duration, backend.query(&iter_prompt, &cwd)).await {
-                                        Ok(Ok(text)) => {
-                                            iter_output = text;
+                                    let query_timeout = query_deadline(timeout_duration, &backend_name, &config);
+                                    match tokio::time::timeout(query_timeout, query_backend(backend.as_ref(), &request.with_prompt(&iter_prompt), &cwd, stream)).await {
+                                        Ok(Ok(completion)) => {
+                                            loop_usage.record(completion.answered_by(&backend_name), completion.usage);
+                                            if loop_fallback.is_none() {
+                                                loop_fallback = completion.fallback;
+                                            }
+                                            backend::trace::append(&mut loop_trace, completion.trace);
+                                            iter_output = completion.text;
                                             iter_success = true;
                                         }
                                         Ok(Err(e)) => {
@@ -805,7 +1102,8 @@ impl WorkflowRunner {
                                             all_success = false;
                                         }
                                         Err(_) => {
-                                            iter_output = format!("Error: Step timed out after {}s", timeout_duration.as_secs());
+                                            breaker.record_timeout(&backend_name);
+                                            iter_output = format!("Error: Step timed out after {}s", query_timeout.as_secs());
                                             iter_success = false;
                                             all_success = false;
                                         }
@@ -841,6 +1139,9 @@ impl WorkflowRunner {
                                 success: all_success,
                                 elapsed_ms,
                                 backend: if shell.is_none() { Some(backend_name) } else { None },
+                                usage: loop_usage,
+                                fallback: loop_fallback,
+                                trace: loop_trace,
                             };
                         }
 
@@ -863,7 +1164,7 @@ impl WorkflowRunner {
                                     tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
                                 }
 
-                                match tokio::time::timeout(timeout_duration, run_shell(shell_cmd, &cwd, self.config.defaults.command_wrapper.as_deref())).await {
+                                match tokio::time::timeout(timeout_duration, run_shell(shell_cmd, &cwd, self.config.defaults.command_wrapper.as_deref(), shell_env)).await {
                                     Ok(Ok(output)) => {
                                         let elapsed_ms = start.elapsed().as_millis() as u64;
                                         // Record step complete (success)
@@ -883,6 +1184,9 @@ impl WorkflowRunner {
                                             success: true,
                                             elapsed_ms,
                                             backend: None,
+                                            usage: UsageByBackend::default(),
+                                            fallback: None,
+                                            trace: None,
                                         };
                                     }
                                     Ok(Err(e)) => {
@@ -899,6 +1203,9 @@ impl WorkflowRunner {
                                                 success: false,
                                                 elapsed_ms,
                                                 backend: None,
+                                                usage: UsageByBackend::default(),
+                                                fallback: None,
+                                                trace: None,
                                             };
                                         }
                                         let summary = summarize_backend_error("shell", &e.to_string());
@@ -917,6 +1224,9 @@ impl WorkflowRunner {
                                                 success: false,
                                                 elapsed_ms,
                                                 backend: None,
+                                                usage: UsageByBackend::default(),
+                                                fallback: None,
+                                                trace: None,
                                             };
                                         }
                                         println!("  {} timed out (will retry)", "⚠".yellow());
@@ -934,6 +1244,9 @@ impl WorkflowRunner {
                                 success: false,
                                 elapsed_ms,
                                 backend: None,
+                                usage: UsageByBackend::default(),
+                                fallback: None,
+                                trace: None,
                             };
                         }
 
@@ -949,26 +1262,29 @@ impl WorkflowRunner {
                             for bn in &backends_list {
                                 let bn = bn.clone();
                                 let cfg = config.clone();
-                                let prompt = prompt.clone();
+                                let breaker = breaker.clone();
+                                let request = request.with_prompt(&prompt);
                                 let cwd = cwd.clone();
-                                let timeout_dur = timeout_duration;
+                                let timeout_dur = query_deadline(timeout_duration, &bn, &config);
 
                                 handles.push(tokio::spawn(async move {
-                                    let backend_config = match cfg.backends.get(&bn) {
-                                        Some(c) => c,
-                                        None => return (bn.clone(), Err(format!("Backend not found: {}", bn))),
-                                    };
-                                    let backend = match backend::create_backend(&bn, backend_config) {
+                                    if !cfg.backends.contains_key(&bn) {
+                                        return (bn.clone(), Err(format!("Backend not found: {}", bn)));
+                                    }
+                                    let backend = match backend::build_backend(&bn, &cfg, Some(&breaker)) {
                                         Ok(b) => b,
                                         Err(e) => return (bn.clone(), Err(format!("Failed to create backend: {}", e))),
                                     };
                                     if !backend.is_available() {
                                         return (bn.clone(), Err(format!("Backend {} not available", bn)));
                                     }
-                                    match tokio::time::timeout(timeout_dur, backend.query(&prompt, &cwd)).await {
+                                    match tokio::time::timeout(timeout_dur, query_backend(backend.as_ref(), &request, &cwd, stream)).await {
                                         Ok(Ok(text)) => (bn.clone(), Ok(text)),
                                         Ok(Err(e)) => (bn.clone(), Err(e.to_string())),
-                                        Err(_) => (bn.clone(), Err(format!("Timeout after {}s", timeout_dur.as_secs()))),
+                                        Err(_) => {
+                                            breaker.record_timeout(&bn);
+                                            (bn.clone(), Err(format!("Timeout after {}s", timeout_dur.as_secs())))
+                                        }
                                     }
                                 }));
                             }
@@ -976,11 +1292,23 @@ impl WorkflowRunner {
                             // Collect results
                             let mut responses: Vec<BackendResponse> = Vec::new();
                             let mut errors: Vec<String> = Vec::new();
+                            let mut step_usage = UsageByBackend::default();
+                            let mut step_trace = None;
+                            // Backends whose answer came from a fallback
+                            let mut fallbacks: Vec<(String, backend::Fallback)> = Vec::new();
                             for handle in handles {
                                 match handle.await {
-                                    Ok((backend, Ok(content))) => {
-                                        println!("    {} {}", "✓".green(), backend);
-                                        responses.push(BackendResponse { backend, content });
+                                    Ok((backend, Ok(completion))) => {
+                                        match completion.fallback {
+                                            Some(ref fallback) => {
+                                                println!("    {} {} - {}", "✓".green(), backend, fallback);
+                                                fallbacks.push((backend.clone(), fallback.clone()));
+                                            }
+                                            None => println!("    {} {}", "✓".green(), backend),
+                                        }
+                                        step_usage.record(completion.answered_by(&backend), completion.usage);
+                                        backend::trace::append(&mut step_trace, completion.trace);
+                                        responses.push(BackendResponse { backend, content: completion.text });
                                     }
                                     Ok((backend, Err(e))) => {
                                         println!("    {} {} - {}", "✗".red(), backend, e);
@@ -1001,10 +1329,14 @@ impl WorkflowRunner {
                                     success: false,
                                     elapsed_ms,
                                     backend: None,
+                                    usage: step_usage,
+                                    fallback: None,
+                                    trace: None,
                                 };
                             }
 
                             // Apply consensus strategy
+                            let mut synth_fallback = None;
                             let (final_output, used_backend) = match consensus_strategy {
                                 ConsensusStrategy::First => {
                                     let r = &responses[0];
@@ -1067,18 +1399,22 @@ impl WorkflowRunner {
 
                                     println!("    {} Synthesizing with {}...", "⚙".cyan(), synth_backend_name);
 
-                                    if let Some(synth_config) = config.backends.get(synth_backend_name) {
-                                        if let Ok(synth_backend) = backend::create_backend(synth_backend_name, synth_config) {
-                                            match tokio::time::timeout(timeout_duration, synth_backend.query(&synth_prompt, &cwd)).await {
+                                    if config.backends.contains_key(synth_backend_name) {
+                                        if let Ok(synth_backend) = backend::build_backend(synth_backend_name, &config, Some(&breaker)) {
+                                            let synth_timeout = query_deadline(timeout_duration, synth_backend_name, &config);
+                                            match tokio::time::timeout(synth_timeout, synth_backend.complete(&request.with_prompt(&synth_prompt), &cwd)).await {
                                                 Ok(Ok(synthesized)) => {
                                                     println!("    {} Synthesized", "✓".green());
-                                                    (synthesized, Some(synth_backend_name.to_string()))
+                                                    step_usage.record(synthesized.answered_by(synth_backend_name), synthesized.usage);
+                                                    synth_fallback = synthesized.fallback;
+                                                    (synthesized.text, Some(synth_backend_name.to_string()))
                                                 }
                                                 Ok(Err(e)) => {
                                                     println!("    {} Synthesis failed: {}, using first response", "⚠".yellow(), e);
                                                     (responses[0].content.clone(), Some(responses[0].backend.clone()))
                                                 }
                                                 Err(_) => {
+                                                    breaker.record_timeout(synth_backend_name);
                                                     println!("    {} Synthesis timed out, using first response", "⚠".yellow());
                                                     (responses[0].content.clone(), Some(responses[0].backend.clone()))
                                                 }
@@ -1103,6 +1439,14 @@ impl WorkflowRunner {
                                 backends_list.len()
                             );
 
+                            // The fallback behind the answer used, or behind any
+                            // of the answers a vote counted
+                            let fallback = synth_fallback.or_else(|| {
+                                fallbacks
+                                    .into_iter()
+                                    .find(|(backend, _)| used_backend.as_ref().map_or(true, |used| used == backend))
+                                    .map(|(_, fallback)| fallback)
+                            });
                             let parsed = parse_step_output(&final_output, output_format.as_deref());
                             return StepResult {
                                 name: step_name,
@@ -1111,26 +1455,29 @@ impl WorkflowRunner {
                                 success: true,
                                 elapsed_ms,
                                 backend: used_backend,
+                                usage: step_usage,
+                                fallback,
+                                trace: step_trace,
                             };
                         }
 
                         // Single backend path (original code)
-                        let backend_config = match config.backends.get(&backend_name) {
-                            Some(cfg) => cfg,
-                            None => {
-                                // Record step complete (failure - backend not found)
-                                return StepResult {
-                                    name: step_name,
-                                    output: format!("Backend not found: {}", backend_name),
-                                    parsed_output: None,
-                                    success: false,
-                                    elapsed_ms: 0,
-                                    backend: Some(backend_name),
-                                };
-                            }
-                        };
+                        if !config.backends.contains_key(&backend_name) {
+                            // Record step complete (failure - backend not found)
+                            return StepResult {
+                                name: step_name,
+                                output: format!("Backend not found: {}", backend_name),
+                                parsed_output: None,
+                                success: false,
+                                elapsed_ms: 0,
+                                backend: Some(backend_name),
+                                usage: UsageByBackend::default(),
+                                fallback: None,
+                                trace: None,
+                            };
+                        }
 
-                        let backend = match backend::create_backend(&backend_name, backend_config) {
+                        let backend = match backend::build_backend(&backend_name, &config, Some(&breaker)) {
                             Ok(b) => b,
                             Err(e) => {
                                 // Record step complete (failure - failed to create backend)
@@ -1141,6 +1488,9 @@ impl WorkflowRunner {
                                     success: false,
                                     elapsed_ms: 0,
                                     backend: Some(backend_name),
+                                    usage: UsageByBackend::default(),
+                                    fallback: None,
+                                    trace: None,
                                 };
                             }
                         };
@@ -1155,38 +1505,68 @@ impl WorkflowRunner {
                                 success: false,
                                 elapsed_ms: 0,
                                 backend: Some(backend_name),
+                                usage: UsageByBackend::default(),
+                                fallback: None,
+                                trace: None,
                             };
                         }
 
                         // Execute LLM query (with retry support)
+                        let query_timeout = query_deadline(timeout_duration, &backend_name, &config);
                         let mut last_error = String::new();
                         let mut text = String::new();
+                        let mut step_usage = UsageByBackend::default();
+                        let mut fallback = None;
+                        let mut trace = None;
                         let mut query_success = false;
+                        // Fix retries continue this conversation instead of resending everything
+                        let mut conversation = backend::Conversation::with_options(request.clone());
+                        // How long the provider asked us to wait after the last failure
+                        let mut retry_hint: Option<std::time::Duration> = None;
 
                         for attempt in 0..=max_retries {
                             if attempt > 0 {
-                                let delay = retry_delay * 2_u64.pow(attempt - 1);
+                                let (delay, asked) = match retry_hint.take() {
+                                    Some(hint) => (hint.as_millis() as u64, " as asked"),
+                                    None => (retry_delay * 2_u64.pow(attempt - 1), ""),
+                                };
                                 // Record retry attempt
                                 println!(
-                                    "  {} Retry {}/{} in {}ms...",
+                                    "  {} Retry {}/{} in {}ms{}...",
                                     "↻".yellow(),
                                     attempt,
                                     max_retries,
-                                    delay
+                                    delay,
+                                    asked
                                 );
                                 tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
                             }
 
                             // Record backend query
 
-                            match tokio::time::timeout(timeout_duration, backend.query(&prompt, &cwd)).await {
-                                Ok(Ok(t)) => {
-                                    text = t;
+                            let query = async {
+                                if chunks.is_empty() {
+                                    query_backend(backend.as_ref(), &conversation.request(&prompt), &cwd, stream).await
+                                } else {
+                                    query_chunks(backend.as_ref(), &request, &chunks, &cwd, stream).await
+                                }
+                            };
+                            match tokio::time::timeout(query_timeout, query).await {
+                                Ok(Ok(completion)) => {
+                                    step_usage.record(completion.answered_by(&backend_name), completion.usage);
+                                    // A chunked prompt is too large to carry into fix retries
+                                    if chunks.is_empty() {
+                                        conversation.record(&prompt, &completion);
+                                    }
+                                    fallback = completion.fallback;
+                                    trace = completion.trace;
+                                    text = completion.text;
                                     query_success = true;
                                     break;
                                 }
                                 Ok(Err(e)) => {
                                     last_error = e.to_string();
+                                    retry_hint = backend::retry::retry_after(&e);
                                     if attempt == max_retries {
                                         let elapsed_ms = start.elapsed().as_millis() as u64;
                                         let summary = summarize_backend_error(&backend_name, &e.to_string());
@@ -1199,16 +1579,20 @@ impl WorkflowRunner {
                                             success: false,
                                             elapsed_ms,
                                             backend: Some(backend_name),
+                                            usage: step_usage,
+                                            fallback: None,
+                                            trace: None,
                                         };
                                     }
                                     let summary = summarize_backend_error(&backend_name, &e.to_string());
                                     println!("  {} {} {} (will retry)", "⚠".yellow(), backend_name.to_uppercase(), summary);
                                 }
                                 Err(_) => {
-                                    last_error = format!("Step timed out after {}s", timeout_duration.as_secs());
+                                    breaker.record_timeout(&backend_name);
+                                    last_error = format!("Step timed out after {}s", query_timeout.as_secs());
                                     if attempt == max_retries {
                                         let elapsed_ms = start.elapsed().as_millis() as u64;
-                                        println!("  {} {} timed out after {}s", "✗".red(), backend_name.to_uppercase(), timeout_duration.as_secs());
+                                        println!("  {} {} timed out after {}s", "✗".red(), backend_name.to_uppercase(), query_timeout.as_secs());
                                         // Record step complete (failure)
                                         return StepResult {
                                             name: step_name,
@@ -1217,6 +1601,9 @@ impl WorkflowRunner {
                                             success: false,
                                             elapsed_ms,
                                             backend: Some(backend_name),
+                                            usage: step_usage,
+                                            fallback: None,
+                                            trace: None,
                                         };
                                     }
                                     println!("  {} {} timed out (will retry)", "⚠".yellow(), backend_name.to_uppercase());
@@ -1227,7 +1614,10 @@ impl WorkflowRunner {
                         let elapsed_ms = start.elapsed().as_millis() as u64;
 
                         if query_success {
-                            println!("  {} ({:.1}s)", "✓".green(), elapsed_ms as f64 / 1000.0);
+                            match fallback {
+                                Some(ref fallback) => println!("  {} ({:.1}s, {})", "✓".green(), elapsed_ms as f64 / 1000.0, fallback),
+                                None => println!("  {} ({:.1}s)", "✓".green(), elapsed_ms as f64 / 1000.0),
+                            }
 
                             // Fix retry loop for apply/verify cycle
                             let mut fix_attempt = 0u32;
@@ -1297,6 +1687,9 @@ impl WorkflowRunner {
                                                                 success: false,
                                                                 elapsed_ms,
                                                                 backend: Some(backend_name.clone()),
+                                                                usage: step_usage,
+                                                                fallback: fallback.clone(),
+                                                                trace: trace.clone(),
                                                             };
                                                         }
                                                     }
@@ -1325,6 +1718,9 @@ impl WorkflowRunner {
                                                 success: false,
                                                 elapsed_ms,
                                                 backend: Some(backend_name.clone()),
+                                                usage: step_usage,
+                                                fallback: fallback.clone(),
+                                                trace: trace.clone(),
                                             };
                                         }
                                     }
@@ -1333,7 +1729,7 @@ impl WorkflowRunner {
                                 // Run format before verify if requested
                                 if let Some(ref format_cmd) = format {
                                     println!("  {} {}", "format:".dimmed(), format_cmd.dimmed());
-                                    match tokio::time::timeout(timeout_duration, run_shell(format_cmd, &cwd, self.config.defaults.command_wrapper.as_deref())).await {
+                                    match tokio::time::timeout(timeout_duration, run_shell(format_cmd, &cwd, self.config.defaults.command_wrapper.as_deref(), shell_env)).await {
                                         Ok(Ok(_)) => {
                                             println!("    {} Format complete", "✓".green());
                                         }
@@ -1355,7 +1751,7 @@ impl WorkflowRunner {
                                 // Run verification if requested
                                 if let Some(ref verify_cmd) = verify {
                                     println!("  {} {}", "verify:".dimmed(), verify_cmd.dimmed());
-                                    match tokio::time::timeout(timeout_duration, run_shell(verify_cmd, &cwd, self.config.defaults.command_wrapper.as_deref())).await {
+                                    match tokio::time::timeout(timeout_duration, run_shell(verify_cmd, &cwd, self.config.defaults.command_wrapper.as_deref(), shell_env)).await {
                                         Ok(Ok(_)) => {
                                             println!("    {} Verification passed", "✓".green());
                                             break 'fix_loop;
@@ -1383,18 +1779,23 @@ impl WorkflowRunner {
                                                     fix_retries
                                                 );
                                                 let fix_prompt = format!(
-                                                    "{}\n\n## Previous Attempt Failed\n\nVerification error:\n```\n{}\n```\n\nPlease provide a corrected fix.",
-                                                    prompt, error_msg
+                                                    "## Previous Attempt Failed\n\nVerification error:\n```\n{}\n```\n\nPlease provide a corrected fix.",
+                                                    error_msg
                                                 );
-                                                match tokio::time::timeout(timeout_duration, backend.query(&fix_prompt, &cwd)).await {
+                                                match tokio::time::timeout(query_timeout, query_backend(backend.as_ref(), &conversation.request(&fix_prompt), &cwd, stream)).await {
                                                     Ok(Ok(new_response)) => {
-                                                        current_text = new_response;
+                                                        step_usage.record(new_response.answered_by(&backend_name), new_response.usage);
+                                                        conversation.record(&fix_prompt, &new_response);
+                                                        fallback = new_response.fallback;
+                                                        backend::trace::append(&mut trace, new_response.trace);
+                                                        current_text = new_response.text;
                                                         continue 'fix_loop;
                                                     }
                                                     Ok(Err(e)) => {
                                                         println!("    {} Re-query failed: {}", "✗".red(), e);
                                                     }
                                                     Err(_) => {
+                                                        breaker.record_timeout(&backend_name);
                                                         println!("    {} Re-query timed out", "✗".red());
                                                     }
                                                 }
@@ -1410,6 +1811,9 @@ impl WorkflowRunner {
                                                 success: false,
                                                 elapsed_ms,
                                                 backend: Some(backend_name.clone()),
+                                                usage: step_usage,
+                                                fallback: fallback.clone(),
+                                                trace: trace.clone(),
                                             };
                                         }
                                         Err(_) => {
@@ -1431,18 +1835,23 @@ impl WorkflowRunner {
                                                     fix_retries
                                                 );
                                                 let fix_prompt = format!(
-                                                    "{}\n\n## Previous Attempt Failed\n\n{}\n\nPlease provide a corrected fix.",
-                                                    prompt, error_msg
+                                                    "## Previous Attempt Failed\n\n{}\n\nPlease provide a corrected fix.",
+                                                    error_msg
                                                 );
-                                                match tokio::time::timeout(timeout_duration, backend.query(&fix_prompt, &cwd)).await {
+                                                match tokio::time::timeout(query_timeout, query_backend(backend.as_ref(), &conversation.request(&fix_prompt), &cwd, stream)).await {
                                                     Ok(Ok(new_response)) => {
-                                                        current_text = new_response;
+                                                        step_usage.record(new_response.answered_by(&backend_name), new_response.usage);
+                                                        conversation.record(&fix_prompt, &new_response);
+                                                        fallback = new_response.fallback;
+                                                        backend::trace::append(&mut trace, new_response.trace);
+                                                        current_text = new_response.text;
                                                         continue 'fix_loop;
                                                     }
                                                     Ok(Err(e)) => {
                                                         println!("    {} Re-query failed: {}", "✗".red(), e);
                                                     }
                                                     Err(_) => {
+                                                        breaker.record_timeout(&backend_name);
                                                         println!("    {} Re-query timed out", "✗".red());
                                                     }
                                                 }
@@ -1458,6 +1867,9 @@ impl WorkflowRunner {
                                                 success: false,
                                                 elapsed_ms,
                                                 backend: Some(backend_name.clone()),
+                                                usage: step_usage,
+                                                fallback: fallback.clone(),
+                                                trace: trace.clone(),
                                             };
                                         }
                                     }
@@ -1482,6 +1894,9 @@ impl WorkflowRunner {
                                 success: true,
                                 elapsed_ms,
                                 backend: Some(backend_name),
+                                usage: step_usage,
+                                fallback,
+                                trace,
                             }
                         } else {
                             // Record step complete (failure - should never reach here)
@@ -1493,6 +1908,9 @@ impl WorkflowRunner {
                                 success: false,
                                 elapsed_ms,
                                 backend: Some(backend_name),
+                                usage: step_usage,
+                                fallback,
+                                trace,
                             }
                         }
                     }
@@ -1637,275 +2055,309 @@ impl WorkflowRunner {
         Ok(levels)
     }
 
-    /// Interpolate {{ steps.X.output }} variables in a string
-    ///
-    /// Uses replace_all for O(n) complexity instead of O(n*m) with repeated replace()
-    /// Step outputs are escaped to prevent their content from being treated as variables.
-    fn interpolate(
+    /// Evaluate a step's `when` condition against the results so far. See
+    /// [`crate::condition`] for the syntax.
+    fn evaluate_condition(
         &self,
-        template: &str,
+        condition: &str,
         results: &HashMap<String, StepResult>,
+    ) -> Result<bool> {
+        let parsed = Condition::parse(condition)
+            .with_context(|| format!("Invalid condition '{}'", condition))?;
+        parsed
+            .evaluate(results)
+            .with_context(|| format!("Condition '{}'", condition))
+    }
+
+    /// Fit an interpolated prompt to the step's context budget, the smallest
+    /// `context_window` of its backends, using its `context_overflow` strategy.
+    /// `fixed_tokens` is what the system prompt and attachments take, which
+    /// no strategy shortens. `completed` lists earlier steps in the order
+    /// they finished.
+    fn fit_prompt(
+        &self,
+        step: &Step,
+        prompt: String,
+        fixed_tokens: u64,
+        results: &HashMap<String, StepResult>,
+        completed: &[StepResult],
         workflow_name: &str,
-        current_step: &str,
-    ) -> Result<String, WorkflowError> {
-        // First pass: validate all step references exist
-        for cap in INTERPOLATE_RE.captures_iter(template) {
-            let referenced_step = cap.get(1).expect("regex group 1 always exists").as_str();
-            if !results.contains_key(referenced_step) {
-                return Err(WorkflowError::MissingStepOutput {
-                    workflow: workflow_name.to_string(),
-                    step: current_step.to_string(),
-                    referenced: referenced_step.to_string(),
-                });
-            }
-        }
+    ) -> Result<FittedPrompt, WorkflowError> {
+        let unchanged = |prompt, report| FittedPrompt {
+            prompt,
+            chunks: Vec::new(),
+            report,
+        };
+        let Some(budget) = Budget::for_backends(&step.get_backends(), &self.config, step.max_tokens)
+        else {
+            return Ok(unchanged(prompt, None));
+        };
 
-        // Second pass: replace all in one pass (O(n) instead of O(n*m))
-        // Escape {{ in step outputs so they don't get treated as variables
-        let output = INTERPOLATE_RE
-            .replace_all(template, |caps: &regex::Captures| {
-                let step = &caps[1];
-                results
-                    .get(step)
-                    .map(|r| escape_braces(&r.output))
-                    .unwrap_or_default()
-            })
-            .into_owned();
-
-        Ok(output)
-    }
-
-    /// Evaluate a condition expression
-    ///
-    /// Supported syntax:
-    /// - `contains(step.output, "string")` - true if step output contains string
-    /// - `equals(step.output, "string")` - true if step output equals string exactly
-    /// - `not(condition)` - negates the inner condition
-    /// - `steps.X.output contains 'Y'` - legacy syntax, still supported
-    fn evaluate_condition(&self, condition: &str, results: &HashMap<String, StepResult>) -> bool {
-        // Handle not(...) wrapper first
-        if let Some(caps) = CONDITION_NOT_RE.captures(condition) {
-            let inner = caps.get(1).unwrap().as_str().trim();
-            return !self.evaluate_condition(inner, results);
+        let room = budget.available().saturating_sub(fixed_tokens);
+        let tokens = budget::estimate_tokens(&prompt);
+        if tokens <= room {
+            let report = self
+                .verbose
+                .then(|| format!("{} {}", "budget:".dimmed(), budget.describe(tokens + fixed_tokens)));
+            return Ok(unchanged(prompt, report));
         }
 
-        // Handle contains(step.field, "string")
-        if let Some(caps) = CONDITION_CONTAINS_RE.captures(condition) {
-            let step_name = caps.get(1).unwrap().as_str();
-            let field_name = caps.get(2).unwrap().as_str();
-            let search_str = caps.get(3).unwrap().as_str();
-            return results
-                .get(step_name)
-                .map(|r| {
-                    let value = if field_name == "output" {
-                        r.output.clone()
-                    } else {
-                        // Extract JSON field from output
-                        extract_json_field(&r.output, field_name).unwrap_or_default()
-                    };
-                    value.contains(search_str)
-                })
-                .unwrap_or(false);
+        let too_large = || WorkflowError::PromptTooLarge {
+            workflow: workflow_name.to_string(),
+            step: step.name.clone(),
+            backend: budget.backend.clone(),
+            tokens: tokens + fixed_tokens,
+            available: budget.available(),
+        };
+        // Shortening the prompt can't help
+        if room == 0 {
+            return Err(too_large());
         }
+        let over = format!(
+            "{} prompt is ~{} tokens over the budget of {} on {}",
+            "⚠".yellow(),
+            tokens - room,
+            budget.available(),
+            budget.backend
+        );
 
-        // Handle equals(step.field, "string")
-        if let Some(caps) = CONDITION_EQUALS_RE.captures(condition) {
-            let step_name = caps.get(1).unwrap().as_str();
-            let field_name = caps.get(2).unwrap().as_str();
-            let expected = caps.get(3).unwrap().as_str();
-            return results
-                .get(step_name)
-                .map(|r| {
-                    let value = if field_name == "output" {
-                        r.output.trim().to_string()
-                    } else {
-                        // Extract JSON field from output
-                        extract_json_field(&r.output, field_name).unwrap_or_default()
-                    };
-                    value == expected
+        match step.context_overflow {
+            ContextOverflow::Error => Err(too_large()),
+            ContextOverflow::Truncate => Ok(unchanged(
+                budget::truncate_middle(&prompt, room),
+                Some(format!("{}, truncated the middle", over)),
+            )),
+            ContextOverflow::DropOldest => {
+                let mut kept = results.clone();
+                for (dropped, oldest) in completed.iter().enumerate() {
+                    if let Some(result) = kept.get_mut(&oldest.name) {
+                        result.output = format!(
+                            "[output of step '{}' left out to fit the context window]",
+                            oldest.name
+                        );
+                        result.parsed_output = None;
+                    }
+                    let candidate =
+                        self.interpolate_for_step(step, &step.prompt, &kept, workflow_name, false)?;
+                    if budget::estimate_tokens(&candidate) <= room {
+                        let count = dropped + 1;
+                        let plural = if count == 1 { "" } else { "s" };
+                        return Ok(unchanged(
+                            candidate,
+                            Some(format!("{}, left out {} earlier step output{}", over, count, plural)),
+                        ));
+                    }
+                }
+                Err(too_large())
+            }
+            ContextOverflow::Chunk => {
+                let chunks = budget::split_chunks(&prompt, room);
+                let report = format!("{}, sending it in {} parts", over, chunks.len());
+                Ok(FittedPrompt {
+                    prompt,
+                    chunks,
+                    report: Some(report),
                 })
-                .unwrap_or(false);
+            }
         }
+    }
 
-        // Legacy syntax: "steps.X.output contains 'Y'"
-        if let Some(caps) = CONDITION_LEGACY_RE.captures(condition) {
-            let step_name = caps.get(1).unwrap().as_str();
-            let search_str = caps.get(2).unwrap().as_str();
-            return results
-                .get(step_name)
-                .map(|r| r.output.contains(search_str))
-                .unwrap_or(false);
-        }
+    /// Render a template (prompt, shell command, ...) for a step. See
+    /// [`crate::template`] for the syntax.
+    fn interpolate_with_fields(
+        &self,
+        template: &str,
+        results: &HashMap<String, StepResult>,
+        workflow_name: &str,
+        current_step: &str,
+    ) -> Result<String, WorkflowError> {
+        let scope = TemplateScope {
+            runner: self,
+            results,
+            loop_vars: LoopVars::Absent,
+        };
+        Template::parse(template)
+            .and_then(|t| t.render(&scope))
+            .map_err(|e| template_error(e, workflow_name, current_step))
+    }
 
-        // Handle steps.X.success (check if step succeeded)
-        if let Some(caps) = CONDITION_SUCCESS_RE.captures(condition) {
-            let step_name = caps.get(1).unwrap().as_str();
-            return results.get(step_name).map(|r| r.success).unwrap_or(false);
-        }
+    /// Render a shell command, with values quoted as shell words
+    fn interpolate_command(
+        &self,
+        template: &str,
+        results: &HashMap<String, StepResult>,
+        workflow_name: &str,
+        current_step: &str,
+    ) -> Result<String, WorkflowError> {
+        let scope = TemplateScope {
+            runner: self,
+            results,
+            loop_vars: LoopVars::Absent,
+        };
+        parse_template(template, true)
+            .and_then(|t| t.render(&scope))
+            .map_err(|e| template_error(e, workflow_name, current_step))
+    }
 
-        // Default: if we can't parse, return true (run the step)
-        true
+    /// Render a step's prompt or (with `shell`) shell command. A for_each
+    /// step's `item` and `index` are left for
+    /// [`WorkflowRunner::interpolate_loop_vars`].
+    fn interpolate_for_step(
+        &self,
+        step: &Step,
+        template: &str,
+        results: &HashMap<String, StepResult>,
+        workflow_name: &str,
+        shell: bool,
+    ) -> Result<String, WorkflowError> {
+        if step.for_each.is_none() {
+            return if shell {
+                self.interpolate_command(template, results, workflow_name, &step.name)
+            } else {
+                self.interpolate_with_fields(template, results, workflow_name, &step.name)
+            };
+        }
+        let scope = TemplateScope {
+            runner: self,
+            results,
+            loop_vars: LoopVars::Later,
+        };
+        parse_template(template, shell)
+            .and_then(|t| t.render_partial(&scope))
+            .map_err(|e| template_error(e, workflow_name, &step.name))
     }
 
-    /// Interpolate with JSON field access: {{ steps.X.field }} and env vars: {{ env.VAR }}
-    ///
-    /// Uses replace_all for O(n) complexity per pattern instead of O(n*m) with repeated replace()
-    fn interpolate_with_fields(
+    /// Render what [`WorkflowRunner::interpolate_for_step`] left for one
+    /// for_each iteration
+    fn interpolate_loop_vars(
         &self,
         template: &str,
         results: &HashMap<String, StepResult>,
+        loop_vars: LoopVars,
         workflow_name: &str,
         current_step: &str,
+        shell: bool,
     ) -> Result<String, WorkflowError> {
-        let output = self.interpolate(template, results, workflow_name, current_step)?;
-
-        // Handle {{ steps.X.field }} for JSON field access
-        // First validate all step references exist
-        for cap in FIELD_RE.captures_iter(&output) {
-            let referenced_step = cap.get(1).expect("regex group 1 always exists").as_str();
-            let field_name = cap.get(2).expect("regex group 2 always exists").as_str();
-            if field_name != "output" && !results.contains_key(referenced_step) {
-                return Err(WorkflowError::MissingStepOutput {
-                    workflow: workflow_name.to_string(),
-                    step: current_step.to_string(),
-                    referenced: referenced_step.to_string(),
-                });
-            }
-        }
-        // Then replace all in one pass
-        let output = FIELD_RE
-            .replace_all(&output, |caps: &regex::Captures| {
-                let step = &caps[1];
-                let field = &caps[2];
-                if field == "output" {
-                    // Already handled by interpolate(), return original match
-                    caps[0].to_string()
-                } else {
-                    // Try parsed_output first if available, then fall back to string parsing
-                    results
-                        .get(step)
-                        .and_then(|r| {
-                            // Use parsed_output if available
-                            if let Some(ref parsed) = r.parsed_output {
-                                parsed.get(field).map(|v| match v {
-                                    serde_json::Value::String(s) => s.clone(),
-                                    other => other.to_string(),
-                                })
-                            } else {
-                                // Fall back to parsing from string
-                                extract_json_field(&r.output, field)
-                            }
-                        })
-                        .unwrap_or_else(|| format!("[field {} not found]", field))
-                }
-            })
-            .into_owned();
+        let scope = TemplateScope {
+            runner: self,
+            results,
+            loop_vars,
+        };
+        parse_template(template, shell)
+            .and_then(|t| t.render(&scope))
+            .map_err(|e| template_error(e, workflow_name, current_step))
+    }
 
-        // Handle {{ env.VAR }} for environment variables - single pass
-        let output = ENV_RE
-            .replace_all(&output, |caps: &regex::Captures| {
-                let var_name = &caps[1];
-                std::env::var(var_name).unwrap_or_else(|_| format!("[env {} not set]", var_name))
-            })
-            .into_owned();
-
-        // Handle {{ arg.N }} for positional arguments (1-indexed) - single pass
-        let output = ARG_RE
-            .replace_all(&output, |caps: &regex::Captures| {
-                let arg_index: usize = caps[1].parse().unwrap_or(0);
-                if arg_index > 0 && arg_index <= self.args.len() {
-                    self.args[arg_index - 1].clone()
-                } else {
-                    format!("[arg {} not provided]", arg_index)
+    /// Backends that answered so far, for `{{ workflow.backends }}`:
+    /// "Claude + Codex", or "lok" before any did
+    fn workflow_backends(results: &HashMap<String, StepResult>) -> String {
+        let mut backends: Vec<String> = results.values().filter_map(|r| r.backend.clone()).collect();
+        backends.sort();
+        backends.dedup();
+
+        // Capitalize first letter of each backend name
+        let formatted: Vec<String> = backends
+            .iter()
+            .map(|b| {
+                let mut chars = b.chars();
+                match chars.next() {
+                    Some(c) => c.to_uppercase().to_string() + chars.as_str(),
+                    None => String::new(),
                 }
             })
-            .into_owned();
+            .collect();
 
-        // Handle {{ workflow.backends }} - list unique backends used - single pass
-        let output = if WORKFLOW_BACKENDS_RE.is_match(&output) {
-            let mut backends: Vec<String> =
-                results.values().filter_map(|r| r.backend.clone()).collect();
-            backends.sort();
-            backends.dedup();
+        if formatted.is_empty() {
+            "lok".to_string()
+        } else {
+            formatted.join(" + ")
+        }
+    }
+}
 
-            // Capitalize first letter of each backend name
-            let formatted: Vec<String> = backends
-                .iter()
-                .map(|b| {
-                    let mut chars = b.chars();
-                    match chars.next() {
-                        Some(c) => c.to_uppercase().to_string() + chars.as_str(),
-                        None => String::new(),
-                    }
-                })
-                .collect();
+/// `item` and `index` in a template
+enum LoopVars<'a> {
+    /// Not a for_each step
+    Absent,
+    /// A for_each step, before its iterations
+    Later,
+    Item(&'a serde_json::Value, usize),
+}
 
-            let replacement = if formatted.is_empty() {
-                "lok".to_string()
-            } else {
-                formatted.join(" + ")
-            };
+/// The variables a step's templates see
+struct TemplateScope<'a> {
+    runner: &'a WorkflowRunner,
+    results: &'a HashMap<String, StepResult>,
+    loop_vars: LoopVars<'a>,
+}
 
-            WORKFLOW_BACKENDS_RE
-                .replace_all(&output, &replacement)
-                .into_owned()
-        } else {
-            output
+impl Scope for TemplateScope<'_> {
+    fn lookup(&self, path: &[Segment]) -> Result<Option<serde_json::Value>, ConditionError> {
+        use serde_json::Value;
+        let unknown = || ConditionError::UnknownVariable(condition::path_to_string(path));
+        let [Segment::Field(root), rest @ ..] = path else {
+            return Err(unknown());
         };
-
-        // Check for any remaining unknown {{ ... }} variables
-        // Skip item, item.field, and index - those are handled by for_each loops
-        for cap in UNKNOWN_VAR_RE.captures_iter(&output) {
-            let variable = cap
-                .get(1)
-                .expect("regex group 1 always exists")
-                .as_str()
-                .trim();
-
-            // Skip loop variables - they'll be interpolated later by for_each
-            if variable == "item" || variable == "index" || variable.starts_with("item.") {
-                continue;
+        match (root.as_str(), rest) {
+            ("steps", [Segment::Field(step), fields @ ..]) => {
+                let result = self
+                    .results
+                    .get(step)
+                    .ok_or_else(|| ConditionError::UnknownStep(step.clone()))?;
+                if fields.is_empty() {
+                    return Err(unknown());
+                }
+                Ok(condition::step_value(result, fields))
             }
-
-            return Err(WorkflowError::UnknownVariable {
-                workflow: workflow_name.to_string(),
-                step: current_step.to_string(),
-                variable: variable.to_string(),
-            });
+            ("env", [Segment::Field(var)]) => Ok(std::env::var(var).ok().map(Value::String)),
+            // 1-indexed
+            ("arg", [Segment::Field(n)]) => Ok(n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.runner.args.get(n.checked_sub(1)?))
+                .map(|arg| Value::String(arg.clone()))),
+            ("workflow", [Segment::Field(field)]) if field == "backends" => Ok(Some(Value::String(
+                WorkflowRunner::workflow_backends(self.results),
+            ))),
+            ("item" | "index", _) => match self.loop_vars {
+                LoopVars::Item(item, _) if root == "item" => {
+                    Ok(condition::navigate(item.clone(), rest))
+                }
+                LoopVars::Item(_, index) if rest.is_empty() => Ok(Some(Value::from(index))),
+                LoopVars::Item(..) => Ok(None),
+                LoopVars::Later => Err(ConditionError::Deferred(condition::path_to_string(path))),
+                LoopVars::Absent => Err(unknown()),
+            },
+            _ => Err(unknown()),
         }
-
-        // Restore escaped braces from step outputs
-        Ok(unescape_braces(&output))
     }
 }
 
-/// Interpolate loop variables ({{ item }}, {{ item.field }}, {{ index }}) in a string
-fn interpolate_loop_vars(template: &str, item: &serde_json::Value, index: usize) -> String {
-    // Handle {{ item.field }} for object field access first
-    let output = ITEM_FIELD_RE
-        .replace_all(template, |caps: &regex::Captures| {
-            let field = &caps[1];
-            item.get(field)
-                .map(|v| match v {
-                    serde_json::Value::String(s) => s.clone(),
-                    other => other.to_string(),
-                })
-                .unwrap_or_else(|| format!("[item.{} not found]", field))
-        })
-        .into_owned();
-
-    // Handle {{ item }} for the whole item
-    let output = ITEM_RE
-        .replace_all(&output, |_: &regex::Captures| match item {
-            serde_json::Value::String(s) => s.clone(),
-            other => other.to_string(),
-        })
-        .into_owned();
-
-    // Handle {{ index }} for iteration index
-    INDEX_RE
-        .replace_all(&output, index.to_string().as_str())
-        .into_owned()
+/// A template error as the workflow error it stands for
+fn template_error(error: TemplateError, workflow: &str, step: &str) -> WorkflowError {
+    match error {
+        TemplateError::Render {
+            error: ConditionError::UnknownStep(referenced),
+            ..
+        } => WorkflowError::MissingStepOutput {
+            workflow: workflow.to_string(),
+            step: step.to_string(),
+            referenced,
+        },
+        TemplateError::Render {
+            error: ConditionError::UnknownVariable(variable),
+            ..
+        } => WorkflowError::UnknownVariable {
+            workflow: workflow.to_string(),
+            step: step.to_string(),
+            variable,
+        },
+        other => WorkflowError::InvalidTemplate {
+            workflow: workflow.to_string(),
+            step: step.to_string(),
+            error: other.to_string(),
+        },
+    }
 }
 
 /// Parse for_each value into a JSON array
@@ -2020,9 +2472,86 @@ fn parse_for_each_array(
     }
 }
 
+/// Parse a template, to render with values quoted for the shell if `shell`
+fn parse_template(template: &str, shell: bool) -> Result<Template, TemplateError> {
+    let template = Template::parse(template)?;
+    Ok(if shell { template.for_shell() } else { template })
+}
+
+/// Every earlier step's output in a temp file, for shell commands to read
+/// without splicing it into the command: `--body-file "$LOK_STEP_REVIEW"`.
+/// The files are removed when this is dropped.
+struct StepFiles {
+    _dir: tempfile::TempDir,
+    env: Vec<(String, PathBuf)>,
+}
+
+impl StepFiles {
+    fn write(results: &HashMap<String, StepResult>) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("lok-steps-")
+            .tempdir()
+            .context("Failed to create a temp dir for step outputs")?;
+        let mut names: Vec<&String> = results.keys().collect();
+        names.sort();
+        let mut env = Vec::new();
+        for (i, name) in names.into_iter().enumerate() {
+            // Numbered, since step names needn't be valid file names
+            let path = dir.path().join(format!("{}.txt", i));
+            std::fs::write(&path, &results[name].output)
+                .with_context(|| format!("Failed to write the output of step '{}'", name))?;
+            env.push((step_env_var(name), path));
+        }
+        Ok(Self { _dir: dir, env })
+    }
+
+    fn env(&self) -> &[(String, PathBuf)] {
+        &self.env
+    }
+}
+
+/// How long a step waits on `backend`: the step timeout, or for a backend with
+/// fallbacks at least long enough for each in its chain to use its own `timeout`
+fn query_deadline(
+    step_timeout: std::time::Duration,
+    backend: &str,
+    config: &Config,
+) -> std::time::Duration {
+    let has_chain = config
+        .backends
+        .get(backend)
+        .is_some_and(|b| !b.fallback.is_empty());
+    if has_chain {
+        step_timeout.max(backend::chain_timeout(backend, config))
+    } else {
+        step_timeout
+    }
+}
+
+/// `LOK_STEP_` and the step name in capitals, with anything but letters
+/// and digits as `_`: `get-diff` is `LOK_STEP_GET_DIFF`
+fn step_env_var(step: &str) -> String {
+    let name: String = step
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("LOK_STEP_{}", name)
+}
+
 /// Run a shell command and return output
 /// If wrapper is provided (e.g., "nix-shell --run '{cmd}'"), the command will be wrapped
-async fn run_shell(cmd: &str, cwd: &Path, wrapper: Option<&str>) -> Result<String> {
+async fn run_shell(
+    cmd: &str,
+    cwd: &Path,
+    wrapper: Option<&str>,
+    env: &[(String, PathBuf)],
+) -> Result<String> {
     // Apply wrapper if provided
     let final_cmd = if let Some(w) = wrapper {
         // If wrapper uses single quotes around {cmd}, escape single quotes in the command
@@ -2042,6 +2571,7 @@ async fn run_shell(cmd: &str, cwd: &Path, wrapper: Option<&str>) -> Result<Strin
         .arg("-c")
         .arg(&final_cmd)
         .current_dir(cwd)
+        .envs(env.iter().map(|(name, path)| (name, path)))
         .kill_on_drop(true)
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
@@ -2091,20 +2621,35 @@ fn extract_json_array_from_text(text: &str) -> Option<String> {
     None
 }
 
-/// Extract a field from JSON in text (handles markdown code blocks)
-fn extract_json_field(text: &str, field: &str) -> Option<String> {
+/// A step's output as JSON: its `parsed_output`, or else the JSON object in
+/// its text
+pub(crate) fn step_json(result: &StepResult) -> Option<serde_json::Value> {
+    match &result.parsed_output {
+        Some(parsed) => Some(parsed.clone()),
+        None => parse_json_from_text(&result.output),
+    }
+}
+
+/// Parse the JSON object in text (handles markdown code blocks)
+fn parse_json_from_text(text: &str) -> Option<serde_json::Value> {
     // Try to find JSON in the text (may be wrapped in ```json blocks)
     let json_str = extract_json_from_text(text)?;
 
     // Try parsing, and if it fails due to control characters, sanitize and retry
-    let value: serde_json::Value = serde_json::from_str(&json_str)
+    serde_json::from_str(&json_str)
         .or_else(|_| {
             // LLMs sometimes output literal newlines/tabs in JSON strings instead of \n\t escapes
             // Sanitize by escaping control characters inside string values
             let sanitized = sanitize_json_strings(&json_str);
             serde_json::from_str(&sanitized)
         })
-        .ok()?;
+        .ok()
+}
+
+/// Extract a field from JSON in text (handles markdown code blocks)
+#[cfg(test)]
+fn extract_json_field(text: &str, field: &str) -> Option<String> {
+    let value = parse_json_from_text(text)?;
 
     value.get(field).map(|v| match v {
         serde_json::Value::String(s) => s.clone(),
@@ -2606,28 +3151,39 @@ fn merge_workflows(parent: Workflow, child: Workflow) -> Workflow {
         continue_on_error: child.continue_on_error || parent.continue_on_error,
         // Child's timeout takes precedence if set
         timeout: child.timeout.or(parent.timeout),
+        permissions: child.permissions.or(parent.permissions),
     }
 }
 
 /// Print workflow results
-pub fn print_results(results: &[StepResult]) {
-    print!("{}", format_results(results));
+pub fn print_results(results: &[StepResult], config: &Config) {
+    print!("{}", format_results(results, config));
 }
 
 /// Format workflow results as a string (for file output)
-pub fn format_results(results: &[StepResult]) -> String {
+/// Ends with token usage and estimated cost when any backend reported usage
+pub fn format_results(results: &[StepResult], config: &Config) -> String {
     let mut output = String::new();
     output.push_str("\nResults:\n\n");
 
+    let mut total_usage = UsageByBackend::default();
+
     for result in results {
         let status = if result.success { "[OK]" } else { "[FAIL]" };
 
-        output.push_str(&format!(
-            "{} {} ({:.1}s)\n\n",
-            status,
-            result.name,
-            result.elapsed_ms as f64 / 1000.0
-        ));
+        let mut timing = format!("{:.1}s", result.elapsed_ms as f64 / 1000.0);
+        if !result.usage.is_empty() {
+            timing.push_str(&format!(
+                ", {}",
+                usage::format_tokens(&result.usage.total())
+            ));
+        }
+        total_usage.merge(&result.usage);
+        if let Some(ref fallback) = result.fallback {
+            timing.push_str(&format!(", {}", fallback));
+        }
+
+        output.push_str(&format!("{} {} ({})\n\n", status, result.name, timing));
 
         // Indent output
         for line in result.output.lines() {
@@ -2636,6 +3192,14 @@ pub fn format_results(results: &[StepResult]) -> String {
         output.push('\n');
     }
 
+    if !total_usage.is_empty() {
+        output.push_str("Usage:\n\n");
+        for line in usage::summary_lines(&total_usage, config) {
+            output.push_str(&format!("  {}\n", line));
+        }
+        output.push('\n');
+    }
+
     output
 }
 
@@ -2764,6 +3328,9 @@ mod tests {
                 success: true,
                 elapsed_ms: 1000,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -2844,6 +3411,16 @@ line2"}"#;
                 min_deps_success: None,
                 timeout: None,
                 consensus: None,
+                stream: false,
+                stream_prefix: false,
+                system: None,
+                temperature: None,
+                max_tokens: None,
+                stop: Vec::new(),
+                response_format: ResponseFormat::Text,
+                context_overflow: ContextOverflow::Error,
+                permissions: None,
+                attach: Vec::new(),
             },
             Step {
                 name: "fetch".to_string(), // duplicate!
@@ -2864,6 +3441,16 @@ line2"}"#;
                 min_deps_success: None,
                 timeout: None,
                 consensus: None,
+                stream: false,
+                stream_prefix: false,
+                system: None,
+                temperature: None,
+                max_tokens: None,
+                stop: Vec::new(),
+                response_format: ResponseFormat::Text,
+                context_overflow: ContextOverflow::Error,
+                permissions: None,
+                attach: Vec::new(),
             },
         ];
 
@@ -2907,6 +3494,16 @@ line2"}"#;
             min_deps_success: Some(2), // Requires 2 deps but has none
             timeout: None,
             consensus: None,
+            stream: false,
+            stream_prefix: false,
+            system: None,
+            temperature: None,
+            max_tokens: None,
+            stop: Vec::new(),
+            response_format: ResponseFormat::Text,
+            context_overflow: ContextOverflow::Error,
+            permissions: None,
+            attach: Vec::new(),
         }];
 
         let config = crate::config::Config::default();
@@ -2953,6 +3550,16 @@ line2"}"#;
                 min_deps_success: None,
                 timeout: None,
                 consensus: None,
+                stream: false,
+                stream_prefix: false,
+                system: None,
+                temperature: None,
+                max_tokens: None,
+                stop: Vec::new(),
+                response_format: ResponseFormat::Text,
+                context_overflow: ContextOverflow::Error,
+                permissions: None,
+                attach: Vec::new(),
             },
             Step {
                 name: "late_step".to_string(),
@@ -2973,6 +3580,16 @@ line2"}"#;
                 min_deps_success: None,
                 timeout: None,
                 consensus: None,
+                stream: false,
+                stream_prefix: false,
+                system: None,
+                temperature: None,
+                max_tokens: None,
+                stop: Vec::new(),
+                response_format: ResponseFormat::Text,
+                context_overflow: ContextOverflow::Error,
+                permissions: None,
+                attach: Vec::new(),
             },
         ];
 
@@ -3011,6 +3628,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
         results.insert(
@@ -3022,6 +3642,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 50,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
         results
@@ -3034,11 +3657,11 @@ line2"}"#;
         let results = make_test_results();
 
         // New syntax: contains(step.output, "string")
-        assert!(runner.evaluate_condition(r#"contains(analyze.output, "ISSUES_FOUND")"#, &results));
-        assert!(!runner.evaluate_condition(r#"contains(analyze.output, "NO_ISSUES")"#, &results));
+        assert!(runner.evaluate_condition(r#"contains(analyze.output, "ISSUES_FOUND")"#, &results).unwrap());
+        assert!(!runner.evaluate_condition(r#"contains(analyze.output, "NO_ISSUES")"#, &results).unwrap());
 
         // Step doesn't exist
-        assert!(!runner.evaluate_condition(r#"contains(missing.output, "test")"#, &results));
+        assert!(!runner.evaluate_condition(r#"contains(missing.output, "test")"#, &results).unwrap());
     }
 
     #[test]
@@ -3048,11 +3671,11 @@ line2"}"#;
         let results = make_test_results();
 
         // Exact match (trims whitespace)
-        assert!(runner.evaluate_condition(r#"equals(check.output, "PASS")"#, &results));
-        assert!(!runner.evaluate_condition(r#"equals(check.output, "FAIL")"#, &results));
+        assert!(runner.evaluate_condition(r#"equals(check.output, "PASS")"#, &results).unwrap());
+        assert!(!runner.evaluate_condition(r#"equals(check.output, "FAIL")"#, &results).unwrap());
 
         // Partial match should fail equals
-        assert!(!runner.evaluate_condition(r#"equals(analyze.output, "ISSUES_FOUND")"#, &results));
+        assert!(!runner.evaluate_condition(r#"equals(analyze.output, "ISSUES_FOUND")"#, &results).unwrap());
     }
 
     #[test]
@@ -3063,11 +3686,11 @@ line2"}"#;
 
         // Negation
         assert!(!runner
-            .evaluate_condition(r#"not(contains(analyze.output, "ISSUES_FOUND"))"#, &results));
+            .evaluate_condition(r#"not(contains(analyze.output, "ISSUES_FOUND"))"#, &results).unwrap());
         assert!(
-            runner.evaluate_condition(r#"not(contains(analyze.output, "NO_ISSUES"))"#, &results)
+            runner.evaluate_condition(r#"not(contains(analyze.output, "NO_ISSUES"))"#, &results).unwrap()
         );
-        assert!(runner.evaluate_condition(r#"not(equals(check.output, "FAIL"))"#, &results));
+        assert!(runner.evaluate_condition(r#"not(equals(check.output, "FAIL"))"#, &results).unwrap());
     }
 
     #[test]
@@ -3078,22 +3701,25 @@ line2"}"#;
 
         // Legacy syntax still works
         assert!(
-            runner.evaluate_condition(r#"steps.analyze.output contains 'ISSUES_FOUND'"#, &results)
+            runner.evaluate_condition(r#"steps.analyze.output contains 'ISSUES_FOUND'"#, &results).unwrap()
         );
         assert!(
-            !runner.evaluate_condition(r#"steps.analyze.output contains 'NO_ISSUES'"#, &results)
+            !runner.evaluate_condition(r#"steps.analyze.output contains 'NO_ISSUES'"#, &results).unwrap()
         );
     }
 
     #[test]
-    fn test_condition_unparseable_returns_true() {
+    fn test_condition_unparseable_is_error() {
         let config = Config::default();
         let runner = WorkflowRunner::new(config, PathBuf::from("."), vec![]);
         let results = make_test_results();
 
-        // Unparseable conditions default to true (step runs)
-        assert!(runner.evaluate_condition("some random text", &results));
-        assert!(runner.evaluate_condition("", &results));
+        // A typo must not run the step
+        assert!(runner.evaluate_condition("some random text", &results).is_err());
+        assert!(runner.evaluate_condition("", &results).is_err());
+        assert!(runner
+            .evaluate_condition("steps.check.success & steps.analyze.success", &results)
+            .is_err());
     }
 
     #[test]
@@ -3110,6 +3736,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
         results.insert(
@@ -3121,24 +3750,27 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
         // JSON field access: equals(step.field, "value")
-        assert!(runner.evaluate_condition(r#"equals(fix.action, "close")"#, &results));
-        assert!(!runner.evaluate_condition(r#"equals(fix.action, "fix")"#, &results));
-        assert!(runner.evaluate_condition(r#"equals(fix2.action, "fix")"#, &results));
-        assert!(!runner.evaluate_condition(r#"equals(fix2.action, "close")"#, &results));
+        assert!(runner.evaluate_condition(r#"equals(fix.action, "close")"#, &results).unwrap());
+        assert!(!runner.evaluate_condition(r#"equals(fix.action, "fix")"#, &results).unwrap());
+        assert!(runner.evaluate_condition(r#"equals(fix2.action, "fix")"#, &results).unwrap());
+        assert!(!runner.evaluate_condition(r#"equals(fix2.action, "close")"#, &results).unwrap());
 
         // JSON field access: contains(step.field, "substring")
-        assert!(runner.evaluate_condition(r#"contains(fix.reason, "Already")"#, &results));
-        assert!(!runner.evaluate_condition(r#"contains(fix.reason, "NotHere")"#, &results));
+        assert!(runner.evaluate_condition(r#"contains(fix.reason, "Already")"#, &results).unwrap());
+        assert!(!runner.evaluate_condition(r#"contains(fix.reason, "NotHere")"#, &results).unwrap());
 
         // .output still works as before
-        assert!(runner.evaluate_condition(r#"contains(fix.output, "action")"#, &results));
+        assert!(runner.evaluate_condition(r#"contains(fix.output, "action")"#, &results).unwrap());
 
         // Missing field returns false
-        assert!(!runner.evaluate_condition(r#"equals(fix.missing_field, "value")"#, &results));
+        assert!(!runner.evaluate_condition(r#"equals(fix.missing_field, "value")"#, &results).unwrap());
     }
 
     #[test]
@@ -3157,6 +3789,14 @@ line2"}"#;
         );
     }
 
+    /// A for_each step's prompt for one iteration, with no earlier steps
+    fn interpolate_loop_vars(template: &str, item: &serde_json::Value, index: usize) -> String {
+        let runner = WorkflowRunner::new(Config::default(), PathBuf::from("."), vec![]);
+        runner
+            .interpolate_loop_vars(template, &HashMap::new(), LoopVars::Item(item, index), "wf", "step", false)
+            .unwrap()
+    }
+
     #[test]
     fn test_interpolate_loop_vars_item_string() {
         let item = serde_json::json!("hello");
@@ -3240,6 +3880,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3263,6 +3906,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3298,6 +3944,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3321,6 +3970,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3351,6 +4003,221 @@ line2"}"#;
         assert_eq!(step.for_each, Some("steps.plan.output".to_string()));
     }
 
+    #[test]
+    fn test_chunk_needs_single_query() {
+        let toml_str = r#"
+            name = "wf"
+
+            [[steps]]
+            name = "review"
+            backends = ["claude", "codex"]
+            prompt = "Review this"
+            context_overflow = "chunk"
+        "#;
+        let workflow: Workflow = toml::from_str(toml_str).unwrap();
+        assert_eq!(workflow.steps[0].context_overflow, ContextOverflow::Chunk);
+        assert!(matches!(
+            workflow.validate(),
+            Err(WorkflowError::ChunkNeedsSingleQuery { .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_condition_rejected() {
+        let workflow_with = |condition: &str| -> Workflow {
+            toml::from_str(&format!(
+                r#"
+                name = "wf"
+
+                [[steps]]
+                name = "review"
+                backend = "claude"
+                prompt = "Review this"
+
+                [[steps]]
+                name = "fix"
+                backend = "codex"
+                prompt = "Fix it"
+                apply_edits = true
+                when = '''{}'''
+                "#,
+                condition
+            ))
+            .unwrap()
+        };
+
+        let ok = r#"steps.review.success && (len(steps.review.output.issues) > 0 || contains(review.output, "FIX"))"#;
+        assert!(workflow_with(ok).validate().is_ok());
+
+        let err = workflow_with("steps.review.output contians 'FIX'")
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, WorkflowError::InvalidCondition { .. }));
+        assert!(err.to_string().contains("step 'fix' has an invalid condition"));
+
+        let err = workflow_with("steps.reveiw.success").validate().unwrap_err();
+        assert!(err.to_string().contains("unknown step 'reveiw'"));
+    }
+
+    #[test]
+    fn test_invalid_template_rejected() {
+        let toml_str = r#"
+            name = "wf"
+
+            [[steps]]
+            name = "review"
+            backend = "claude"
+            prompt = """
+            {% for f in steps.scan.findings %}
+            {{ f.file | upper }}
+            """
+        "#;
+        let workflow: Workflow = toml::from_str(toml_str).unwrap();
+        let err = workflow.validate().unwrap_err();
+        assert!(matches!(err, WorkflowError::InvalidTemplate { .. }));
+        assert!(err.to_string().contains("prompt line 2: unknown filter 'upper'"));
+    }
+
+    #[test]
+    fn test_query_deadline_covers_fallback_chain() {
+        let mut config = Config::default();
+        let step_timeout = std::time::Duration::from_millis(DEFAULT_STEP_TIMEOUT_MS);
+        assert_eq!(query_deadline(step_timeout, "codex", &config), step_timeout);
+
+        // Codex gets the default 300s and gemini its 600s, so the step waits for both
+        config.backends.get_mut("codex").unwrap().fallback = vec!["gemini".to_string()];
+        assert_eq!(
+            query_deadline(step_timeout, "codex", &config),
+            std::time::Duration::from_secs(900)
+        );
+    }
+
+    #[test]
+    fn test_step_env_var_collision_rejected() {
+        let toml_str = r#"
+            name = "wf"
+
+            [[steps]]
+            name = "get-diff"
+            shell = "git diff"
+
+            [[steps]]
+            name = "get_diff"
+            shell = "git diff --cached"
+        "#;
+        let workflow: Workflow = toml::from_str(toml_str).unwrap();
+        let err = workflow.validate().unwrap_err();
+        assert!(matches!(err, WorkflowError::StepEnvVarCollision { .. }));
+        assert!(err
+            .to_string()
+            .contains("steps 'get-diff' and 'get_diff' would both be passed to shell commands as $LOK_STEP_GET_DIFF"));
+    }
+
+    #[test]
+    fn test_step_files() {
+        assert_eq!(step_env_var("get-diff"), "LOK_STEP_GET_DIFF");
+        assert_eq!(step_env_var("step1"), "LOK_STEP_STEP1");
+
+        let results = make_test_results();
+        let files = StepFiles::write(&results).unwrap();
+        assert_eq!(files.env().len(), results.len());
+        for (name, result) in &results {
+            let (_, path) = files
+                .env()
+                .iter()
+                .find(|(var, _)| *var == step_env_var(name))
+                .unwrap();
+            assert_eq!(std::fs::read_to_string(path).unwrap(), result.output);
+        }
+        let path = files.env()[0].1.clone();
+        drop(files);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_shell_command_quoted() {
+        let runner = WorkflowRunner::new(Config::default(), PathBuf::from("."), vec![]);
+        let mut results = make_test_results();
+        results.get_mut("analyze").unwrap().output = "it's $(done)".to_string();
+        let command = runner
+            .interpolate_command(
+                "echo {{ steps.analyze.output }} '{{ steps.analyze.output }}' {{ steps.analyze.output | raw }}",
+                &results,
+                "wf",
+                "step",
+            )
+            .unwrap();
+        assert_eq!(
+            command,
+            r"echo 'it'\''s $(done)' 'it'\''s $(done)' it's $(done)"
+        );
+    }
+
+    #[test]
+    fn test_condition_nested_json() {
+        let config = Config::default();
+        let runner = WorkflowRunner::new(config, PathBuf::from("."), vec![]);
+        let mut results = make_test_results();
+        results.insert(
+            "scan".to_string(),
+            StepResult {
+                name: "scan".to_string(),
+                output: String::new(),
+                parsed_output: Some(serde_json::json!({
+                    "summary": {"critical": 2},
+                    "findings": [{"file": "src/main.rs", "severity": "high"}]
+                })),
+                success: true,
+                elapsed_ms: 10,
+                backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
+            },
+        );
+
+        let condition = r#"steps.scan.output.summary.critical >= 2 && steps.scan.findings[0].severity == "high""#;
+        assert!(runner.evaluate_condition(condition, &results).unwrap());
+        assert!(runner
+            .evaluate_condition("len(steps.scan.findings) == 1 && steps.check.output == 'PASS'", &results)
+            .unwrap());
+        assert!(!runner
+            .evaluate_condition(r#"matches(steps.scan.findings[0].file, "^tests/")"#, &results)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_apply_edits_permissions_ceiling() {
+        let mut config = Config::default();
+        config.backends.get_mut("codex").unwrap().permissions = Some(Permissions::Full);
+
+        let toml_str = r#"
+            name = "wf"
+
+            [[steps]]
+            name = "fix"
+            backend = "codex"
+            prompt = "Fix it"
+            apply_edits = true
+        "#;
+        let mut workflow: Workflow = toml::from_str(toml_str).unwrap();
+        assert!(matches!(
+            workflow.check_permissions(&config),
+            Err(WorkflowError::PermissionsExceeded {
+                permissions: Permissions::Full,
+                allowed: Permissions::ReadOnly,
+                ..
+            })
+        ));
+
+        // The step can run the agent with less, or the workflow can allow more
+        workflow.steps[0].permissions = Some(Permissions::ReadOnly);
+        assert!(workflow.check_permissions(&config).is_ok());
+        workflow.steps[0].permissions = None;
+        workflow.permissions = Some(Permissions::Full);
+        assert!(workflow.check_permissions(&config).is_ok());
+    }
+
     #[test]
     fn test_step_for_each_inline_array_toml() {
         let toml_str = r#"
@@ -3425,6 +4292,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3448,6 +4318,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3683,6 +4556,69 @@ line2"}"#;
         assert!(err.contains("File not found"));
     }
 
+    #[test]
+    fn test_format_results_includes_usage() {
+        let mut config = Config::default();
+        config.prices.insert(
+            "claude".to_string(),
+            crate::usage::Price {
+                input: 3.0,
+                output: 15.0,
+            },
+        );
+
+        let mut step_usage = UsageByBackend::default();
+        step_usage.record("claude", Some(crate::usage::Usage::new(200_000, 10_000)));
+        let results = vec![
+            StepResult {
+                name: "analyze".to_string(),
+                output: "done".to_string(),
+                parsed_output: None,
+                success: true,
+                elapsed_ms: 1500,
+                backend: Some("claude".to_string()),
+                usage: step_usage,
+                fallback: None,
+                trace: None,
+            },
+            StepResult {
+                name: "check".to_string(),
+                output: "ok".to_string(),
+                parsed_output: None,
+                success: true,
+                elapsed_ms: 10,
+                backend: None,
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
+            },
+        ];
+
+        let output = format_results(&results, &config);
+        assert!(output.contains("[OK] analyze (1.5s, 200,000 in / 10,000 out)"));
+        assert!(output.contains("[OK] check (0.0s)"));
+        assert!(output.contains("Usage:"));
+        // 0.2M * $3 + 0.01M * $15
+        assert!(output.contains("$0.7500"), "got: {}", output);
+    }
+
+    #[test]
+    fn test_format_results_without_usage() {
+        let results = vec![StepResult {
+            name: "shell".to_string(),
+            output: "ok".to_string(),
+            parsed_output: None,
+            success: true,
+            elapsed_ms: 10,
+            backend: None,
+            usage: UsageByBackend::default(),
+            fallback: None,
+            trace: None,
+        }];
+        let output = format_results(&results, &Config::default());
+        assert!(!output.contains("Usage:"));
+    }
+
     // Fail-fast tests (Issue #136)
 
     #[test]
@@ -3701,6 +4637,9 @@ line2"}"#;
                 success: true,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
@@ -3714,19 +4653,22 @@ line2"}"#;
                 success: false,
                 elapsed_ms: 100,
                 backend: Some("claude".to_string()),
+                usage: UsageByBackend::default(),
+                fallback: None,
+                trace: None,
             },
         );
 
         // steps.X.success should return the success field
-        assert!(runner.evaluate_condition("steps.step1.success", &results));
-        assert!(!runner.evaluate_condition("steps.step2.success", &results));
+        assert!(runner.evaluate_condition("steps.step1.success", &results).unwrap());
+        assert!(!runner.evaluate_condition("steps.step2.success", &results).unwrap());
 
         // Works with not()
-        assert!(!runner.evaluate_condition("not(steps.step1.success)", &results));
-        assert!(runner.evaluate_condition("not(steps.step2.success)", &results));
+        assert!(!runner.evaluate_condition("not(steps.step1.success)", &results).unwrap());
+        assert!(runner.evaluate_condition("not(steps.step2.success)", &results).unwrap());
 
         // Missing step returns false
-        assert!(!runner.evaluate_condition("steps.missing.success", &results));
+        assert!(!runner.evaluate_condition("steps.missing.success", &results).unwrap());
     }
 
     #[test]
@@ -3794,6 +4736,31 @@ line2"}"#;
         assert!(!workflow.step_continue_on_error(&workflow.steps[0]));
     }
 
+    #[test]
+    fn test_step_generation_options() {
+        let toml_str = r#"
+            name = "classify"
+            backend = "ollama"
+            system = "You are a reviewer."
+            temperature = 0.2
+            max_tokens = 500
+            stop = ["END"]
+            response_format = "json"
+            prompt = "test"
+        "#;
+        let step: Step = toml::from_str(toml_str).unwrap();
+        let request = step.query_request(step.system.clone());
+        assert_eq!(request.system.as_deref(), Some("You are a reviewer."));
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.max_tokens, Some(500));
+        assert_eq!(request.stop, vec!["END"]);
+        assert!(request.wants_json());
+
+        // Defaults leave everything to the backend
+        let step: Step = toml::from_str("name = \"plain\"\nprompt = \"test\"").unwrap();
+        assert_eq!(step.query_request(None), QueryRequest::default());
+    }
+
     #[tokio::test]
     async fn test_min_deps_success_validation_exceeds_deps() {
         let dir = tempdir().unwrap();
@@ -1,13 +1,18 @@
 //! Integration tests for lok workflow engine
 //!
-//! These tests use shell-only workflows to verify engine behavior
-//! without requiring LLM backends.
+//! These tests use shell-only workflows, or `mock` backends answering from
+//! fixtures in tests/mock, to verify engine behavior without real LLMs.
 
 use std::process::Command;
 
 fn run_workflow(workflow_path: &str) -> (bool, String) {
+    run_lok(&["run", workflow_path])
+}
+
+fn run_lok(args: &[&str]) -> (bool, String) {
     let output = Command::new("cargo")
-        .args(["run", "--quiet", "--bin", "lok", "--", "run", workflow_path])
+        .args(["run", "--quiet", "--bin", "lok", "--"])
+        .args(args)
         .current_dir(env!("CARGO_MANIFEST_DIR"))
         .output()
         .expect("Failed to execute lok");
@@ -110,3 +115,384 @@ fn test_parallel_workflow() {
         output
     );
 }
+
+#[test]
+fn test_mock_backends_workflow() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_mock_backends.toml",
+    ]);
+
+    assert!(success, "Workflow failed: {}", output);
+
+    // First query fails with a retryable error, the retry gets the answer
+    assert!(
+        output.contains("will retry") && output.contains("FLAKY_RECOVERED"),
+        "flaky step should recover on retry: {}",
+        output
+    );
+
+    // The retry waits as long as the backend asked, not retry_delay
+    assert!(
+        output.contains("Retry 1/1 in 300ms as asked") && output.contains("BACKOFF_RECOVERED"),
+        "backoff step should honor the retry hint: {}",
+        output
+    );
+
+    // A fixture that never answers trips the step timeout
+    assert!(
+        output.contains("[FAIL] slow"),
+        "slow step should time out: {}",
+        output
+    );
+
+    assert!(
+        output.contains("2/2 backends agreed"),
+        "vote step should reach consensus: {}",
+        output
+    );
+
+    assert!(
+        output.contains("consensus reached (2/3 succeeded)")
+            && output.contains("SUMMARY: FLAKY_RECOVERED / yes"),
+        "summary should run on partial results: {}",
+        output
+    );
+}
+
+#[test]
+fn test_record_and_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    let workflow = "tests/workflows/test_mock_backends.toml";
+
+    let (success, recorded) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "--record",
+        dir,
+        "run",
+        workflow,
+    ]);
+    assert!(success, "Recording run failed: {}", recorded);
+    assert!(
+        std::path::Path::new(dir).join("mock-a.jsonl").exists(),
+        "mock-a traffic should be recorded"
+    );
+
+    // The replay config has no fixtures at all, so every answer comes from the recording
+    let (success, replayed) = run_lok(&[
+        "--config",
+        "tests/mock/replay.toml",
+        "--replay",
+        dir,
+        "run",
+        workflow,
+    ]);
+    assert!(success, "Replay run failed: {}", replayed);
+    assert!(
+        replayed.contains("will retry")
+            && replayed.contains("[FAIL] slow")
+            && replayed.contains("SUMMARY: FLAKY_RECOVERED / yes"),
+        "Replay should reproduce the recorded run: {}",
+        replayed
+    );
+}
+
+#[test]
+fn test_spawn_record_and_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+
+    let (success, recorded) = run_lok(&[
+        "--config",
+        "tests/mock/spawn.toml",
+        "--record",
+        dir,
+        "spawn",
+        "Ship the docs",
+    ]);
+    assert!(success, "Recording spawn failed: {}", recorded);
+    let traffic = std::fs::read_to_string(std::path::Path::new(dir).join("claude.jsonl")).unwrap();
+    assert!(
+        traffic.contains("You are a task planner"),
+        "the planner's query should be recorded: {}",
+        traffic
+    );
+
+    let (success, replayed) = run_lok(&[
+        "--config",
+        "tests/mock/replay.toml",
+        "--replay",
+        dir,
+        "spawn",
+        "Ship the docs",
+    ]);
+    assert!(success, "Replay spawn failed: {}", replayed);
+    assert!(
+        replayed.contains("Planned agents:")
+            && replayed.contains("✓ docs (claude)")
+            && replayed.matches("AGENT_DONE").count() >= 2,
+        "Replay should plan and run the recorded agents: {}",
+        replayed
+    );
+}
+
+#[test]
+fn test_fallback_in_multi_backend_step() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_fallback.toml",
+    ]);
+
+    assert!(success, "Workflow failed: {}", output);
+    assert!(
+        output.contains("answered by mock-a (mock-hang: timed out)"),
+        "the step should report the fallback: {}",
+        output
+    );
+    assert!(!output.contains("TOO_LATE"), "{}", output);
+    assert!(
+        output.contains("[OK] single"),
+        "a short step timeout should not cut off the chain: {}",
+        output
+    );
+}
+
+#[test]
+fn test_step_timeouts_open_circuit() {
+    let (_, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_circuit.toml",
+    ]);
+
+    assert!(
+        output.contains("mock-stuck failed 3 times in a row (timed out)"),
+        "step timeouts should count against the backend: {}",
+        output
+    );
+    assert!(
+        output.contains("mock-stuck skipped: circuit open"),
+        "the fourth step should fail fast: {}",
+        output
+    );
+}
+
+#[test]
+fn test_conduct_with_prompted_tools() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "conduct",
+        "Is the README clear?",
+        "--backend",
+        "mock-conductor",
+    ]);
+
+    assert!(success, "Conductor failed: {}", output);
+    assert!(
+        output.contains("Querying mock-a") && output.contains("CONDUCTED: mock-a voted yes"),
+        "Conductor should delegate to mock-a, then answer: {}",
+        output
+    );
+}
+
+#[test]
+fn test_context_budget_workflow() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "--verbose",
+        "run",
+        "tests/workflows/test_context_budget.toml",
+    ]);
+
+    assert!(success, "Workflow failed: {}", output);
+    assert!(
+        output.contains("truncated the middle") && output.contains("TRUNCATED_OK"),
+        "truncate step should cut the prompt: {}",
+        output
+    );
+    assert!(
+        output.contains("parts") && output.matches("PART_OK").count() > 1,
+        "chunk step should send several parts: {}",
+        output
+    );
+    assert!(
+        output.contains("left out 1 earlier step output") && output.contains("DROPPED_OK"),
+        "drop step should leave out the big output: {}",
+        output
+    );
+}
+
+#[test]
+fn test_context_overflow_error() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_context_overflow_error.toml",
+    ]);
+
+    assert!(!success, "Oversized prompt should fail: {}", output);
+    assert!(
+        output.contains("step 'too-big' prompt is ~150 tokens but mock-small has room for 64"),
+        "Error should give the size and budget: {}",
+        output
+    );
+}
+
+#[test]
+fn test_attachment_overflow_error() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_attach_overflow.toml",
+    ]);
+
+    assert!(!success, "Oversized attachment should fail: {}", output);
+    assert!(
+        output.contains("step 'attached' prompt is ~") && output.contains("but mock-small has room for 64"),
+        "Error should count the attachment: {}",
+        output
+    );
+    assert!(!output.contains("TRUNCATED_OK"), "{}", output);
+}
+
+#[test]
+fn test_trace_in_workflow_and_ask() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_trace.toml",
+    ]);
+    assert!(success, "Workflow failed: {}", output);
+    assert!(
+        output.contains("AUDIT:")
+            && output.contains("$ cat src/main.rs (exit 0)")
+            && output.contains("update src/main.rs"),
+        "audit step should see the agent's trace: {}",
+        output
+    );
+
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "--verbose",
+        "ask",
+        "--no-cache",
+        "--backend",
+        "mock-a",
+        "TRACED: what does main do?",
+    ]);
+    assert!(success, "ask failed: {}", output);
+    assert!(
+        output.contains("=== TRACE ===") && output.contains("reasoning: Reading the entry point"),
+        "ask -v should print the trace: {}",
+        output
+    );
+}
+
+#[test]
+fn test_attachments_in_workflow_and_ask() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "run",
+        "tests/workflows/test_attach.toml",
+    ]);
+    assert!(success, "Workflow failed: {}", output);
+    assert!(
+        output.contains("ATTACHED_OK"),
+        "attached files should be in the prompt: {}",
+        output
+    );
+
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "ask",
+        "--no-cache",
+        "--backend",
+        "mock-a",
+        "-f",
+        "Cargo.toml",
+        "ATTACHED: which crate is this?",
+    ]);
+    assert!(success, "ask failed: {}", output);
+    assert!(output.contains("ATTACHED_OK"), "ask -f: {}", output);
+
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "ask",
+        "--backend",
+        "mock-a",
+        "-f",
+        "src/**/*.nothing",
+        "ATTACHED: never sent",
+    ]);
+    assert!(!success, "ask should fail on an empty glob: {}", output);
+    assert!(output.contains("matched no files"), "{}", output);
+}
+
+#[test]
+fn test_validate_checks_permissions() {
+    let (success, output) = run_lok(&[
+        "--config",
+        "tests/mock/lok.toml",
+        "workflow",
+        "validate",
+        "tests/workflows/test_permissions.toml",
+    ]);
+    assert!(!success, "validate should refuse the workflow: {}", output);
+    assert!(
+        output.contains("mock-exec would run with full permissions"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn test_templates_workflow() {
+    let (success, output) = run_workflow("tests/workflows/test_templates.toml");
+    assert!(success, "Workflow failed: {}", output);
+
+    for expected in [
+        "FILE 1 a.rs line ?",
+        "FILE 2 b.rs line 7",
+        "TEMPLATE_OK 2",
+        "{{ not a variable }}",
+        "ITEM 0 a.rs",
+        "ITEM 1 b.rs at 7",
+    ] {
+        assert!(output.contains(expected), "missing {:?}: {}", expected, output);
+    }
+    assert!(!output.contains("TEMPLATE_WRONG_BRANCH"), "{}", output);
+}
+
+#[test]
+fn test_shell_quoting_workflow() {
+    let (success, output) = run_workflow("tests/workflows/test_shell_quoting.toml");
+    assert!(success, "Workflow failed: {}", output);
+
+    for expected in [
+        r#"BARE it's "done" $(echo INJECTED)"#,
+        r#"SINGLE it's "done" $(echo INJECTED)"#,
+        r#"DOUBLE it's "done" $(echo INJECTED)"#,
+        r#"FILE it's "done" $(echo INJECTED)"#,
+        "RAW_OK",
+        "EACH 0 a'b from /",
+        "EACH 1 c  d from /",
+    ] {
+        assert!(output.contains(expected), "missing {:?}: {}", expected, output);
+    }
+}